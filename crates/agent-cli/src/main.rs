@@ -1,10 +1,26 @@
+mod offline;
+mod output;
+mod panel;
+mod service;
+
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
-use reqwest::blocking::Client;
+use agent_core::backend::PolicyFetch;
+use agent_core::paths::Paths;
+use output::{cell, fmt_age, fmt_dur, fmt_ts, print_json, u64_of, Format};
+use panel::{Panel, PanelError};
+use std::process::ExitCode;
 
+/// Códigos de salida: 0 ok, 1 error local, 2 el agente respondió con error, 3 el agente no responde.
 #[derive(Parser)]
 #[command(name = "agent", version)]
 struct Cli {
+    /// Salida JSON en stdout (los errores van a stderr como {"ok":false,"error","code"})
+    #[arg(long, global = true)]
+    json: bool,
+    /// Lee los datos locales sin el agente (status, queue, focus, drops, diag, policy show)
+    #[arg(long, global = true)]
+    offline: bool,
     #[command(subcommand)]
     cmd: Cmd,
 }
@@ -12,6 +28,158 @@ struct Cli {
 #[derive(Subcommand)]
 enum Cmd {
     #[command(name = "policy")] Policy(PolicyCmd),
+    /// Estado del agente local (/state): actividad, pausa, cola, envíos, auth y policy
+    Status,
+    /// Comprueba que el agente responde (/healthz)
+    Health,
+    /// Pausa la captura durante N minutos
+    Pause {
+        #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..=1440))]
+        minutes: u64,
+    },
+    /// Reanuda la captura (cancela la pausa)
+    Resume,
+    /// Eventos pendientes de envío (descifrados, más recientes primero)
+    Queue {
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// Con --offline, descifra los eventos con la clave local (contienen títulos de ventana)
+        #[arg(long)]
+        decrypt: bool,
+    },
+    /// Bloques de foco y tiempo por app
+    Focus(FocusCmd),
+    /// Muestras descartadas recientes (exclusiones, reglas, pausa, kill switch)
+    Drops {
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+    /// Permisos de captura en macOS (Accesibilidad y Grabación de pantalla)
+    Permissions {
+        #[command(subcommand)]
+        sub: Option<PermsSub>,
+    },
+    /// Panel de transparencia
+    Privacy(PrivacyCmd),
+    /// Ciclo de vida del daemon y servicio del SO (systemd de usuario, LaunchAgent, tarea programada)
+    Daemon(DaemonCmd),
+    /// Enrola este dispositivo con un código de un solo uso emitido por un admin
+    Enroll {
+        /// Código de enrolamiento (p.ej. ABCD-1234)
+        #[arg(long)]
+        code: String,
+    },
+    /// Genera un paquete de diagnóstico para soporte (zip con manifiesto SHA-256)
+    Diag {
+        /// Ruta del zip a escribir (p.ej. /tmp/agent_diag.zip)
+        #[arg(long)]
+        export: std::path::PathBuf,
+        /// Incluye los eventos pendientes de la cola descifrados (contienen títulos de ventana)
+        #[arg(long)]
+        include_payloads: bool,
+    },
+}
+
+#[derive(Parser)]
+struct FocusCmd {
+    #[command(subcommand)]
+    sub: FocusSub,
+}
+
+#[derive(Subcommand)]
+enum FocusSub {
+    /// Bloques de foco recientes
+    Blocks {
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// Duración mínima del bloque (por defecto `focusMinMinutes` de la policy)
+        #[arg(long)]
+        min_minutes: Option<u32>,
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+    },
+    /// Tiempo de foco por día y app
+    Aggregate {
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PermsSub {
+    /// Pide los permisos al sistema (diálogo de macOS)
+    Prompt,
+    /// Abre el panel de Privacidad y Seguridad correspondiente
+    Open {
+        #[arg(value_enum)]
+        pane: PermsPane,
+    },
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum PermsPane {
+    Accessibility,
+    Screen,
+}
+
+#[derive(Parser)]
+struct PrivacyCmd {
+    #[command(subcommand)]
+    sub: PrivacySub,
+}
+
+#[derive(Subcommand)]
+enum PrivacySub {
+    /// Abre el panel del agente en el navegador (qué se captura y la política efectiva)
+    Open {
+        /// Usa la UI inline (/) en vez del panel estático (/panel)
+        #[arg(long)]
+        inline: bool,
+    },
+}
+
+#[derive(Parser)]
+struct DaemonCmd {
+    #[command(subcommand)]
+    sub: DaemonSub,
+}
+
+#[derive(clap::Args)]
+struct SpecArgs {
+    /// Binario del daemon (por defecto agent-daemon junto a la CLI o en el PATH)
+    #[arg(long)]
+    daemon_path: Option<std::path::PathBuf>,
+    /// Directorio de trabajo del daemon, de donde lee `.env` (por defecto el actual)
+    #[arg(long)]
+    workdir: Option<std::path::PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum DaemonSub {
+    /// Arranca el daemon: vía servicio si está instalado, si no en segundo plano
+    Start {
+        #[command(flatten)]
+        spec: SpecArgs,
+    },
+    /// Detiene el daemon (y el servicio, para que no lo relance)
+    Stop,
+    /// Servicio instalado, procesos agent-daemon y panel en PANEL_ADDR
+    Status,
+    /// Instala el servicio de usuario con reinicio automático y lo arranca
+    InstallService {
+        #[command(flatten)]
+        spec: SpecArgs,
+        /// Solo instala; no arranca el daemon
+        #[arg(long)]
+        no_start: bool,
+        /// Imprime el archivo de servicio sin instalar nada
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Detiene y desinstala el servicio de usuario
+    UninstallService,
 }
 
 #[derive(Parser)]
@@ -23,11 +191,7 @@ struct PolicyCmd {
 #[derive(Subcommand)]
 enum PolicySub {
     /// Muestra la política efectiva desde el agente local (/state)
-    Show {
-        /// Solo imprime JSON (policy y etag)
-        #[arg(long)]
-        json: bool,
-    },
+    Show,
     /// Descarga la política desde el backend y la guarda localmente
     Pull,
     /// Abre el panel del agente en el navegador
@@ -41,34 +205,102 @@ enum PolicySub {
         /// Ruta del archivo JSON con la policy (puede incluir {"policy":{...}} o la policy directa)
         file: String,
     },
+    /// Valida una policy local sin aplicarla (errores y avisos por campo)
+    Validate {
+        /// Ruta del archivo JSON con la policy (puede incluir {"policy":{...}} o la policy directa)
+        file: String,
+    },
     /// Edita el policy.json local con $EDITOR (o abre con app por defecto) y aplica
     Edit,
     /// Solicita al agente que refresque la policy desde el backend (ETag-aware)
     Refresh,
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    let json = cli.json;
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let panel = e.downcast_ref::<PanelError>();
+            if json {
+                eprintln!("{}", serde_json::json!({"ok": false, "error": format!("{:#}", e), "code": panel.map(|p| p.code()).unwrap_or("error")}));
+            } else {
+                eprintln!("[error] {:#}", e);
+            }
+            ExitCode::from(panel.map(|p| p.exit_code()).unwrap_or(1))
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let json = cli.json;
+    let offline = cli.offline;
+    let online_only = |cmd: &str| -> Result<()> {
+        if offline { return Err(anyhow!("`agent {}` necesita el agente en marcha; --offline solo aplica a status, queue, focus, drops, diag y policy show", cmd)); }
+        Ok(())
+    };
+    match &cli.cmd {
+        Cmd::Policy(pc) if !matches!(pc.sub, PolicySub::Show) => online_only("policy")?,
+        Cmd::Health => online_only("health")?,
+        Cmd::Pause { .. } | Cmd::Resume => online_only("pause/resume")?,
+        Cmd::Permissions { .. } => online_only("permissions")?,
+        Cmd::Privacy(_) => online_only("privacy")?,
+        Cmd::Enroll { .. } => online_only("enroll")?,
+        _ => {}
+    }
     match cli.cmd {
         Cmd::Policy(pc) => match pc.sub {
-            PolicySub::Show { json } => policy_show(json),
-            PolicySub::Pull => policy_pull(),
-            PolicySub::Open { inline } => policy_open(inline),
-            PolicySub::Apply { file } => policy_apply(&file),
-            PolicySub::Edit => policy_edit(),
-            PolicySub::Refresh => policy_refresh(),
+            PolicySub::Show => policy_show(json, offline),
+            PolicySub::Pull => policy_pull(json),
+            PolicySub::Open { inline } => open_panel(inline),
+            PolicySub::Apply { file } => policy_apply(&file, json),
+            PolicySub::Validate { file } => policy_validate(&file, json),
+            PolicySub::Edit => policy_edit(json),
+            PolicySub::Refresh => policy_refresh(json),
         },
+        Cmd::Status => status(json, offline),
+        Cmd::Health => health(json),
+        Cmd::Pause { minutes } => pause(minutes, json),
+        Cmd::Resume => resume(json),
+        Cmd::Queue { limit, decrypt } => queue(limit, decrypt, json, offline),
+        Cmd::Focus(fc) => match fc.sub {
+            FocusSub::Blocks { limit, min_minutes, format } => focus_blocks(limit, min_minutes, Format::resolve(format, json), offline),
+            FocusSub::Aggregate { days, format } => focus_aggregate(days, Format::resolve(format, json), offline),
+        },
+        Cmd::Drops { limit } => drops(limit, json, offline),
+        Cmd::Permissions { sub } => permissions(sub, json),
+        Cmd::Privacy(pc) => match pc.sub {
+            PrivacySub::Open { inline } => open_panel(inline),
+        },
+        Cmd::Daemon(dc) => match dc.sub {
+            DaemonSub::Start { spec } => daemon_start(spec, json),
+            DaemonSub::Stop => daemon_stop(json),
+            DaemonSub::Status => daemon_status(json),
+            DaemonSub::InstallService { spec, no_start, dry_run } => daemon_install(spec, !no_start, dry_run, json),
+            DaemonSub::UninstallService => daemon_uninstall(json),
+        },
+        Cmd::Enroll { code } => enroll(&code, json),
+        Cmd::Diag { export, include_payloads } => diag_export(&export, include_payloads, json, offline),
     }
 }
 
-fn panel_base() -> String {
-    std::env::var("PANEL_ADDR").map(|a| format!("http://{}", a)).unwrap_or_else(|_| "http://127.0.0.1:49219".to_string())
+/// Respuesta del panel en `path` o, con `--offline`, la equivalente leída de disco.
+fn fetch(offline: bool, path: &str, local: impl FnOnce(&Paths) -> Result<serde_json::Value>) -> Result<serde_json::Value> {
+    if offline { local(&Paths::new()?) } else { Ok(Panel::new().get_json(path)?) }
+}
+
+/// Resultado de una acción: el JSON tal cual con `--json`, si no `[ok] <msg>`.
+fn done(json: bool, v: serde_json::Value, msg: impl std::fmt::Display) {
+    if json { print_json(&v) } else { println!("[ok] {}", msg) }
 }
 
-fn policy_show(json: bool) -> Result<()> {
-    let base = panel_base();
-    let url = format!("{}/state", base);
-    let resp: serde_json::Value = Client::new().get(url).send()?.error_for_status()?.json()?;
+fn policy_show(json: bool, offline: bool) -> Result<()> {
+    let resp = fetch(offline, "/state", |paths| {
+        let policy = std::fs::read_to_string(paths.policy_file()).ok().and_then(|txt| serde_json::from_str::<serde_json::Value>(&txt).ok());
+        let meta = std::fs::read_to_string(paths.policy_meta_file()).ok().and_then(|txt| serde_json::from_str::<serde_json::Value>(&txt).ok()).unwrap_or_default();
+        Ok(serde_json::json!({"policy": policy.unwrap_or_else(|| serde_json::json!({})), "policy_etag": meta.get("etag")}))
+    })?;
     let policy = resp.get("policy").cloned().unwrap_or(serde_json::json!({}));
     let etag = resp.get("policy_etag").cloned().unwrap_or(serde_json::Value::Null);
     if json {
@@ -80,60 +312,165 @@ fn policy_show(json: bool) -> Result<()> {
     Ok(())
 }
 
-fn policy_pull() -> Result<()> {
-    let api = std::env::var("API_BASE_URL").map_err(|_| anyhow!("API_BASE_URL no configurado"))?;
-    let user = std::env::var("USER_EMAIL").map_err(|_| anyhow!("USER_EMAIL no configurado"))?;
+/// Cliente del backend con la misma configuración TLS que el daemon: env más los `tlsPins`
+/// de la policy local, si está verificada.
+fn backend_client(paths: &Paths, rt: &tokio::runtime::Runtime) -> Result<agent_core::backend::BackendClient> {
+    let http = agent_core::http::ClientFactory::from_env()?;
+    let backend = agent_core::backend::BackendClient::from_env(http, paths.clone(), env!("CARGO_PKG_VERSION"));
+    if let Some(base) = backend.base_url() { backend.http().prime_proxy(base); }
+    if let Some(policy) = agent_core::policy_sig::load_verified_policy(paths) {
+        rt.block_on(backend.apply_tls_pins(&policy.tlsPins));
+    }
+    Ok(backend)
+}
+
+fn policy_pull(json: bool) -> Result<()> {
     let paths = agent_core::paths::Paths::new()?;
-    let secrets = agent_core::auth::AgentSecrets::load(&paths)?.ok_or_else(|| anyhow!("Secrets no encontrados; ejecuta primero el agente para bootstrap"))?;
-    let url = format!("{}/v1/policy/{}", api.trim_end_matches('/'), urlencoding::encode(&user));
-    let client = Client::new();
-    let resp = client.get(&url).header("Agent-Token", secrets.agent_token).send()?;
-    if resp.status().is_success() {
-        let etag = resp.headers().get("etag").and_then(|v| v.to_str().ok()).map(|s| s.to_string());
-        let v: serde_json::Value = resp.json()?;
-        let pol_v = v.get("policy").cloned().unwrap_or(v);
-        // Guardar en policy.json y policy_meta.json
-        std::fs::write(paths.policy_file(), serde_json::to_vec_pretty(&pol_v)?)?;
-        let meta = serde_json::json!({"etag": etag});
-        std::fs::write(paths.policy_meta_file(), serde_json::to_vec_pretty(&meta)?)?;
-        println!("[ok] Policy guardada en {} (etag={:?})", paths.policy_file().display(), meta.get("etag"));
-        // Hot-apply en el agente local
-        let panel = panel_base();
-        let apply = Client::new().post(format!("{}/policy/apply", panel)).json(&pol_v).send()?;
-        if apply.status().is_success() { println!("[ok] Policy aplicada en agente local"); Ok(()) }
-        else { println!("[warn] No se pudo aplicar en agente: {}", apply.status()); Ok(()) }
-    } else if resp.status().as_u16() == 304 {
-        println!("[ok] Policy sin cambios (304)");
-        Ok(())
-    } else {
-        Err(anyhow!("Fallo al obtener policy: status {}", resp.status()))
+    if agent_core::auth::AgentSecrets::load(&paths)?.is_none() {
+        return Err(anyhow!("Secrets no encontrados; ejecuta primero el agente para bootstrap"));
     }
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let backend = backend_client(&paths, &rt)?;
+    let api = backend.base_url().ok_or_else(|| anyhow!("API_BASE_URL no configurado"))?.to_string();
+    let fetched = match rt.block_on(backend.fetch_policy(None)) {
+        Ok(f) => f,
+        Err(e) if e.is_pin_mismatch() => return Err(anyhow!("pin TLS no coincide con el certificado de {}", api)),
+        Err(e) => return Err(anyhow!("Fallo al obtener policy: {}", e)),
+    };
+    match fetched {
+        PolicyFetch::Updated { policy: pol_v, etag, signature, .. } => {
+            // no se escribe a disco nada que el agente rechazaría al arrancar
+            if let Err(rej) = agent_core::policy_sig::TrustStore::load(&paths).admit(&pol_v, signature.as_ref()).1 {
+                return Err(anyhow!("policy del backend rechazada: {}", rej.reason));
+            }
+            // Guardar en policy.json y policy_meta.json (con la firma, que el agente re-verifica al cargar)
+            std::fs::write(paths.policy_file(), serde_json::to_vec_pretty(&pol_v)?)?;
+            let meta = serde_json::json!({"etag": etag, "signature": signature});
+            std::fs::write(paths.policy_meta_file(), serde_json::to_vec_pretty(&meta)?)?;
+            if !json { println!("[ok] Policy guardada en {} (etag={:?})", paths.policy_file().display(), meta.get("etag")); }
+            // Hot-apply en el agente local
+            let applied = Panel::new().post_json("/policy/apply", &serde_json::json!({"policy": pol_v, "signature": signature}));
+            match &applied {
+                Ok(_) if !json => println!("[ok] Policy aplicada en agente local"),
+                Err(e) if !json => println!("[warn] No se pudo aplicar en agente: {}", e),
+                _ => {}
+            }
+            if json { print_json(&serde_json::json!({"ok": true, "updated": true, "etag": meta.get("etag"), "file": paths.policy_file(), "applied": applied.is_ok(), "apply_error": applied.err().map(|e| e.to_string())})); }
+            Ok(())
+        }
+        PolicyFetch::NotModified => {
+            done(json, serde_json::json!({"ok": true, "updated": false}), "Policy sin cambios (304)");
+            Ok(())
+        }
+    }
+}
+
+fn enroll(code: &str, json: bool) -> Result<()> {
+    // Preferir el agente en ejecución (actualiza su estado en caliente); si no responde, enrolar localmente
+    match Panel::with_timeout(std::time::Duration::from_secs(60)).post_json("/enroll", &serde_json::json!({"code": code})) {
+        Ok(v) => {
+            let id = v.get("identity").cloned().unwrap_or_default();
+            done(json, v, format!("Dispositivo enrolado: org={} user={}", cell(&id, "org_id"), cell(&id, "user_email")));
+            Ok(())
+        }
+        Err(e) if e.is_unreachable() => {
+            let paths = agent_core::paths::Paths::new()?;
+            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            let backend = backend_client(&paths, &rt)?;
+            let id = rt.block_on(backend.enroll(code)).map_err(|e| anyhow!("enrolamiento falló: {}", e))?;
+            let msg = format!("Dispositivo enrolado (agente no activo): org={} user={}", id.org_id, id.user_email);
+            done(json, serde_json::json!({"ok": true, "identity": id, "via": "backend"}), msg);
+            Ok(())
+        }
+        Err(PanelError::Rejected(reason)) => Err(anyhow::Error::new(PanelError::Rejected(format!("enrolamiento rechazado: {}", reason)))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn diag_export(out: &std::path::Path, include_payloads: bool, json: bool, offline: bool) -> Result<()> {
+    // Preferir el agente en ejecución (agrega /state, descartes recientes y permisos); si no responde, solo disco
+    let panel = Panel::with_timeout(std::time::Duration::from_secs(120));
+    let fetched = if offline { Err(PanelError::Unreachable(panel.base().to_string())) } else { panel.get_bytes(&format!("/diag/export?payloads={}", include_payloads)) };
+    let (zip, source) = match fetched {
+        Ok(zip) => (zip, "agente"),
+        Err(e) if e.is_unreachable() => {
+            let paths = agent_core::paths::Paths::new()?;
+            let mut bundle = agent_core::diag::DiagBundle::new();
+            let state = agent_core::state::AgentState::load(&paths).ok().flatten();
+            if let Some(st) = &state { bundle.add_json("state.json", st); }
+            bundle.collect_disk(&paths, &agent_core::diag::DiagOptions { include_payloads });
+            let meta = serde_json::json!({
+                "source": "cli",
+                "agent_version": env!("CARGO_PKG_VERSION"),
+                "device_id": state.map(|s| s.device_id),
+                "os": std::env::consts::OS,
+                "include_payloads": include_payloads,
+            });
+            (bundle.finish(meta)?, if offline { "disco (--offline)" } else { "disco (agente no activo)" })
+        }
+        Err(e) => return Err(e.into()),
+    };
+    std::fs::write(out, &zip)?;
+    let v = serde_json::json!({"ok": true, "file": out, "bytes": zip.len(), "source": if source == "agente" { "agent" } else { "cli" }, "include_payloads": include_payloads});
+    done(json, v, format!("diagnóstico desde {} en {} ({} bytes)", source, out.display(), zip.len()));
+    if include_payloads && !json { println!("[warn] incluye eventos descifrados de la cola; compartir solo con soporte"); }
+    Ok(())
 }
 
-fn policy_open(inline: bool) -> Result<()> {
-    let base = panel_base();
-    let url = if inline { format!("{}/", base) } else { format!("{}/panel", base) };
+fn open_panel(inline: bool) -> Result<()> {
+    let panel = Panel::new();
+    let url = panel.url(if inline { "/" } else { "/panel" });
     webbrowser::open(&url).map(|_| ()).map_err(|e| anyhow!("no se pudo abrir navegador: {}", e))
 }
 
-fn policy_apply(file: &str) -> Result<()> {
+fn policy_apply(file: &str, json: bool) -> Result<()> {
     let txt = std::fs::read_to_string(file)?;
     let mut v: serde_json::Value = serde_json::from_str(&txt)?;
-    // permitir envoltura {"policy":{...}}
+    // permitir envoltura {"policy":{...}, "signature":{...}}
+    let signature = v.get("signature").cloned().unwrap_or(serde_json::Value::Null);
     if let Some(p) = v.get("policy").cloned() { v = p; }
-    // guardar a disco
     let paths = agent_core::paths::Paths::new()?;
+    let sig = serde_json::from_value::<agent_core::policy_sig::PolicySignature>(signature.clone()).ok();
+    if let Err(rej) = agent_core::policy_sig::TrustStore::load(&paths).admit(&v, sig.as_ref()).1 {
+        return Err(anyhow!("policy rechazada: {}", rej.reason));
+    }
+    // guardar a disco
     std::fs::write(paths.policy_file(), serde_json::to_vec_pretty(&v)?)?;
-    std::fs::write(paths.policy_meta_file(), serde_json::to_vec_pretty(&serde_json::json!({"etag": null}))?)?;
-    // notificar al agente local para hot-apply
-    let base = panel_base();
-    let url = format!("{}/policy/apply", base);
-    let resp = Client::new().post(url).json(&v).send()?;
-    if resp.status().is_success() { println!("[ok] policy aplicada y guardada"); Ok(()) }
-    else { Err(anyhow!("falló aplicar en agente: {}", resp.status())) }
+    std::fs::write(paths.policy_meta_file(), serde_json::to_vec_pretty(&serde_json::json!({"etag": null, "signature": signature}))?)?;
+    // notificar al agente local para hot-apply (vuelve a verificar la firma)
+    let resp = match Panel::new().post_json("/policy/apply", &serde_json::json!({"policy": v, "signature": signature})) {
+        Err(PanelError::Rejected(reason)) => return Err(PanelError::Rejected(format!("el agente rechazó la policy: {}", reason)).into()),
+        r => r?,
+    };
+    done(json, resp, "policy aplicada y guardada");
+    Ok(())
 }
 
-fn policy_edit() -> Result<()> {
+fn policy_validate(file: &str, json: bool) -> Result<()> {
+    let txt = std::fs::read_to_string(file)?;
+    let v: serde_json::Value = serde_json::from_str(&txt).map_err(|e| anyhow!("JSON inválido en {}: {}", file, e))?;
+    let signature = v.get("signature").and_then(|s| serde_json::from_value::<agent_core::policy_sig::PolicySignature>(s.clone()).ok());
+    let pol_v = v.get("policy").cloned().unwrap_or(v);
+    let report = agent_core::policy::Policy::validate(&pol_v);
+    let trust = agent_core::policy_sig::TrustStore::load(&agent_core::paths::Paths::new()?);
+    let (sig_status, sig_verdict) = trust.check(&pol_v, signature.as_ref());
+    if json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "valid": report.valid, "errors": report.errors, "warnings": report.warnings,
+            "signature": sig_status, "signature_error": sig_verdict.as_ref().err(),
+        }))?);
+    } else {
+        for e in &report.errors { println!("[error] {} ({}): {}", e.field, e.code, e.message); }
+        for w in &report.warnings { println!("[warn]  {} ({}): {}", w.field, w.code, w.message); }
+        if let Err(reason) = &sig_verdict { println!("[error] firma: {}", reason); }
+    }
+    if !report.valid { return Err(anyhow!("policy inválida: {} error(es)", report.errors.len())); }
+    if let Err(reason) = sig_verdict { return Err(anyhow!("firma rechazada: {}", reason)); }
+    if !json { println!("[ok] policy válida ({} aviso(s))", report.warnings.len()); }
+    Ok(())
+}
+
+fn policy_edit(json: bool) -> Result<()> {
     let paths = agent_core::paths::Paths::new()?;
     let f = paths.policy_file();
     if !f.exists() { std::fs::write(&f, b"{}")?; }
@@ -142,12 +479,347 @@ fn policy_edit() -> Result<()> {
     } else {
         webbrowser::open(f.to_str().unwrap_or("")).ok();
     }
-    policy_apply(f.to_str().unwrap_or(""))
+    policy_apply(f.to_str().unwrap_or(""), json)
+}
+
+fn policy_refresh(json: bool) -> Result<()> {
+    let v = Panel::new().post_json("/policy/refresh", &serde_json::json!({}))?;
+    done(json, v, "refresh solicitado");
+    Ok(())
+}
+
+fn status(json: bool, offline: bool) -> Result<()> {
+    let st = fetch(offline, "/state", offline::status)?;
+    if json { print_json(&st); return Ok(()); }
+    if offline { status_offline(&st); return Ok(()); }
+    let row = |k: &str, v: String| println!("{:<18}{}", k, v);
+    row("Agente", format!("v{} (device {})", cell(&st, "agent_version"), cell(&st, "device_id")));
+    row("Actividad", format!("{} (inactivo {})", cell(&st, "activity_state"), fmt_dur(u64_of(&st, "input_idle_ms"))));
+    let paused = u64_of(&st, "paused_until_ms");
+    row("Pausa", if paused > output::now_ms() { format!("hasta {} (quedan {})", fmt_ts(paused), fmt_dur(paused - output::now_ms())) } else { "no".to_string() });
+    row("Cola", format!("{} eventos", u64_of(&st, "queue_len")));
+    row("Último evento", fmt_age(u64_of(&st, "last_event_ts")));
+    row("Último envío", fmt_age(u64_of(&st, "last_ingest_ts")));
+    row("Último heartbeat", fmt_age(u64_of(&st, "last_heartbeat_ts")));
+    let auth = st.get("auth_state").cloned().unwrap_or_default();
+    let auth_err = auth.get("last_error").and_then(|x| x.as_str()).map(|e| format!(" ({})", e)).unwrap_or_default();
+    row("Auth", format!("{}{}", cell(&auth, "phase"), auth_err));
+    let cap = st.get("capture").cloned().unwrap_or_default();
+    let cap_ok = cap.get("ok").and_then(|b| b.as_bool()).unwrap_or(false);
+    row("Captura", format!("{} {}", cell(&cap, "backend"), if cap_ok { "ok".to_string() } else { format!("con errores: {}", cell(&cap, "last_error")) }));
+    let etag = st.get("policy_etag").and_then(|x| x.as_str()).unwrap_or("(local)");
+    match st.get("policy_locked").and_then(|x| x.as_str()) {
+        Some(reason) => row("Policy", format!("bloqueada, sin policy verificada ({})", reason)),
+        None => row("Policy", etag.to_string()),
+    }
+    let by_reason = st.get("dropped_by_reason").and_then(|x| x.as_object()).map(|m| {
+        m.iter().filter(|(_, n)| n.as_u64().unwrap_or(0) > 0).map(|(k, n)| format!("{} {}", k, n)).collect::<Vec<_>>().join(", ")
+    }).unwrap_or_default();
+    row("Descartes", if by_reason.is_empty() { u64_of(&st, "dropped_events").to_string() } else { format!("{} ({})", u64_of(&st, "dropped_events"), by_reason) });
+    row("CPU / RAM", format!("{:.1}% / {} MB", st.get("cpu_pct").and_then(|x| x.as_f64()).unwrap_or(0.0), u64_of(&st, "mem_mb")));
+    row("Uptime", fmt_dur(u64_of(&st, "uptime_seconds") * 1000));
+    Ok(())
+}
+
+fn status_offline(st: &serde_json::Value) {
+    let row = |k: &str, v: String| println!("{:<18}{}", k, v);
+    row("Agente", format!("sin conexión (lectura local de {})", cell(st, "data_dir")));
+    row("Device", format!("{} (v{})", cell(st, "device_id"), cell(st, "agent_version")));
+    if !cell(st, "user_email").is_empty() { row("Enrolado", format!("{} / {}", cell(st, "org_id"), cell(st, "user_email"))); }
+    match st.get("queue").filter(|q| !q.is_null()) {
+        Some(q) => row("Cola", format!("{} eventos, el más viejo {}", u64_of(q, "events"), fmt_age(u64_of(q, "oldest_created_ms")))),
+        None => row("Cola", format!("no disponible ({})", cell(st, "queue_error"))),
+    }
+    row("Último foco", fmt_age(u64_of(st, "last_focus_end_ms")));
+    let policy = match (st.get("policy_file").and_then(|b| b.as_bool()), st.get("policy_verified").and_then(|b| b.as_bool())) {
+        (Some(true), Some(true)) => st.get("policy_etag").and_then(|x| x.as_str()).unwrap_or("(local)").to_string(),
+        (Some(true), _) => "sin firma válida".to_string(),
+        _ => "ninguna en disco".to_string(),
+    };
+    row("Policy", policy);
+    row("Descartes", u64_of(st, "dropped_events").to_string());
+}
+
+fn health(json: bool) -> Result<()> {
+    let panel = Panel::new();
+    let v = panel.get_json("/healthz")?;
+    done(json, serde_json::json!({"ok": true, "version": v.get("version"), "panel": panel.base()}), format!("agente v{} responde en {}", cell(&v, "version"), panel.base()));
+    Ok(())
+}
+
+fn pause(minutes: u64, json: bool) -> Result<()> {
+    let v = Panel::new().get_json(&format!("/pause?minutes={}", minutes))?;
+    let msg = format!("captura en pausa hasta {} ({} min)", fmt_ts(u64_of(&v, "paused_until_ms")), minutes);
+    done(json, v, msg);
+    Ok(())
+}
+
+fn resume(json: bool) -> Result<()> {
+    let v = Panel::new().get_json("/pause/clear")?;
+    done(json, v, "captura reanudada");
+    Ok(())
+}
+
+fn queue(limit: usize, decrypt: bool, json: bool, offline: bool) -> Result<()> {
+    if decrypt && !offline { return Err(anyhow!("--decrypt solo aplica con --offline (el agente ya descifra /queue)")); }
+    let v = fetch(offline, &format!("/queue?limit={}", limit), |paths| offline::queue(paths, limit, decrypt))?;
+    if json { print_json(&v); return Ok(()); }
+    let items = v.get("top").and_then(|x| x.as_array()).cloned().unwrap_or_default();
+    println!("{} eventos en cola (mostrando {})", u64_of(&v, "queue_len"), items.len());
+    if items.is_empty() { return Ok(()); }
+    if offline && !decrypt {
+        let rows: Vec<Vec<String>> = items.iter().map(|e| vec![cell(e, "id"), fmt_ts(u64_of(e, "created_at")), cell(e, "attempts"), cell(e, "bytes")]).collect();
+        output::print_table(&["ID", "CREADO", "INTENTOS", "BYTES"], &rows);
+        println!("[info] contenido cifrado; --decrypt lo descifra con la clave local");
+        return Ok(());
+    }
+    let rows: Vec<Vec<String>> = items.iter().map(|e| vec![fmt_ts(u64_of(e, "ts_ms")), cell(e, "app_name"), cell(e, "window_title"), fmt_dur(u64_of(e, "input_idle_ms"))]).collect();
+    output::print_table(&["FECHA", "APP", "TÍTULO", "INACTIVO"], &rows);
+    Ok(())
+}
+
+fn focus_blocks(limit: usize, min_minutes: Option<u32>, format: Format, offline: bool) -> Result<()> {
+    let mut path = format!("/focus/blocks?limit={}", limit);
+    if let Some(m) = min_minutes { path.push_str(&format!("&min_minutes={}", m)); }
+    let v = fetch(offline, &path, |paths| offline::focus_blocks(paths, limit, min_minutes))?;
+    let items = v.get("items").and_then(|x| x.as_array()).cloned().unwrap_or_default();
+    match format {
+        Format::Json => print_json(&v),
+        Format::Csv => {
+            let rows: Vec<Vec<String>> = items.iter().map(|b| ["start_ms", "end_ms", "dur_ms", "app_name", "window_title"].iter().map(|k| cell(b, k)).collect()).collect();
+            output::print_csv(&["start_ms", "end_ms", "dur_ms", "app_name", "window_title"], &rows);
+        }
+        Format::Table => {
+            let rows: Vec<Vec<String>> = items.iter().map(|b| vec![fmt_ts(u64_of(b, "start_ms")), fmt_ts(u64_of(b, "end_ms")), fmt_dur(u64_of(b, "dur_ms")), cell(b, "app_name"), cell(b, "window_title")]).collect();
+            output::print_table(&["INICIO", "FIN", "DURACIÓN", "APP", "TÍTULO"], &rows);
+        }
+    }
+    Ok(())
+}
+
+fn focus_aggregate(days: u32, format: Format, offline: bool) -> Result<()> {
+    if format == Format::Csv && !offline {
+        print!("{}", Panel::new().get_text(&format!("/focus/aggregate.csv?days={}", days))?);
+        return Ok(());
+    }
+    let v = fetch(offline, &format!("/focus/aggregate?days={}", days), |paths| offline::focus_aggregate(paths, days))?;
+    let items = v.get("items").and_then(|x| x.as_array()).cloned().unwrap_or_default();
+    match format {
+        Format::Json => print_json(&v),
+        Format::Csv => {
+            // mismas columnas que /focus/aggregate.csv
+            let rows: Vec<Vec<String>> = items.iter().map(|r| {
+                let ms = r.get("dur_ms").and_then(|x| x.as_i64()).unwrap_or(0);
+                vec![cell(r, "day"), cell(r, "app_name"), ms.to_string(), format!("{}:{:02}", (ms / 60000).max(0), ((ms % 60000) / 1000).abs())]
+            }).collect();
+            output::print_csv(&["day", "app_name", "dur_ms", "dur_hhmm"], &rows);
+        }
+        Format::Table => {
+            let rows: Vec<Vec<String>> = items.iter().map(|r| vec![cell(r, "day"), cell(r, "app_name"), fmt_dur(u64_of(r, "dur_ms"))]).collect();
+            output::print_table(&["DÍA", "APP", "FOCO"], &rows);
+        }
+    }
+    Ok(())
+}
+
+fn drops(limit: usize, json: bool, offline: bool) -> Result<()> {
+    let v = fetch(offline, &format!("/debug/drops?limit={}", limit), offline::drops)?;
+    if json { print_json(&v); return Ok(()); }
+    if offline {
+        let by_reason = v.get("dropped_by_reason").and_then(|x| x.as_object()).cloned().unwrap_or_default();
+        let rows: Vec<Vec<String>> = by_reason.iter().map(|(k, n)| vec![k.clone(), n.to_string()]).collect();
+        output::print_table(&["MOTIVO", "DESCARTES"], &rows);
+        println!("[info] sin el agente solo hay contadores; el detalle reciente vive en su memoria");
+        return Ok(());
+    }
+    let items = v.get("items").and_then(|x| x.as_array()).cloned().unwrap_or_default();
+    let rows: Vec<Vec<String>> = items.iter().map(|d| vec![fmt_ts(u64_of(d, "ts_ms")), cell(d, "reason"), cell(d, "app"), cell(d, "title")]).collect();
+    output::print_table(&["FECHA", "MOTIVO", "APP", "TÍTULO"], &rows);
+    Ok(())
+}
+
+fn permissions(sub: Option<PermsSub>, json: bool) -> Result<()> {
+    let panel = Panel::new();
+    let path = match sub {
+        None => "/permissions",
+        Some(PermsSub::Prompt) => "/permissions/prompt",
+        Some(PermsSub::Open { pane: PermsPane::Accessibility }) => "/permissions/open/accessibility",
+        Some(PermsSub::Open { pane: PermsPane::Screen }) => "/permissions/open/screen",
+    };
+    let v = panel.get_json(path)?;
+    if v.get("unsupported").and_then(|b| b.as_bool()) == Some(true) {
+        return Err(anyhow!("los permisos de captura solo aplican en macOS"));
+    }
+    if json { print_json(&v); return Ok(()); }
+    if matches!(sub, Some(PermsSub::Open { .. })) {
+        println!("[ok] panel de Privacidad y Seguridad abierto");
+        return Ok(());
+    }
+    let mark = |k: &str| if v.get(k).and_then(|b| b.as_bool()) == Some(true) { "ok" } else { "falta" };
+    println!("{:<22}{}", "Accesibilidad", mark("accessibility_ok"));
+    println!("{:<22}{}", "Grabación de pantalla", mark("screen_recording_ok"));
+    Ok(())
+}
+
+/// Versión del agente que responde en PANEL_ADDR; `Ok(None)` si no hay nadie escuchando.
+fn probe_agent(panel: &Panel) -> Result<Option<String>> {
+    match panel.get_json("/healthz") {
+        Ok(v) => Ok(Some(cell(&v, "version"))),
+        Err(e) if e.is_unreachable() => Ok(None),
+        Err(e) => Err(anyhow!("{} está ocupado por algo que no es el agente: {}", panel.base(), e)),
+    }
+}
+
+/// Espera a que el panel responda (o deje de responder, con `up = false`).
+fn wait_agent(panel: &Panel, up: bool, timeout: std::time::Duration) -> Option<String> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        let v = probe_agent(panel).ok().flatten();
+        if v.is_some() == up { return v.or(Some(String::new())); }
+        if std::time::Instant::now() >= deadline { return None; }
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    }
+}
+
+fn daemon_start(spec: SpecArgs, json: bool) -> Result<()> {
+    let panel = Panel::with_timeout(std::time::Duration::from_secs(2));
+    if let Some(version) = probe_agent(&panel)? {
+        done(json, serde_json::json!({"ok": true, "already_running": true, "version": version, "panel": panel.base()}), format!("el agente ya escucha en {} (v{})", panel.base(), version));
+        return Ok(());
+    }
+    // con el lock tomado un daemon nuevo saldría al instante: avisar de quién lo tiene
+    if let Some(holder) = agent_core::instance::holder(&Paths::new()?) {
+        return Err(anyhow!("ya hay un agent-daemon con el lock de instancia ({}) pero su panel no responde en {}; `agent daemon stop` lo detiene", holder, panel.base()));
+    }
+    let svc = service::status();
+    let via = svc.manager;
+    let mut child = None;
+    if svc.installed {
+        service::start()?;
+    } else {
+        let spec = service::ServiceSpec::resolve(spec.daemon_path, spec.workdir)?;
+        child = Some(service::spawn_detached(&spec)?);
+    }
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(15);
+    let version = loop {
+        if let Some(v) = probe_agent(&panel)? { break v; }
+        if let Some(status) = child.as_mut().and_then(|c| c.try_wait().ok().flatten()) {
+            return Err(anyhow!("el daemon terminó al arrancar ({}); revisa los logs en {}", status, Paths::new()?.logs_dir().display()));
+        }
+        if std::time::Instant::now() >= deadline {
+            return Err(anyhow!("el daemon no respondió en {} tras 15 s; revisa los logs en {}", panel.base(), Paths::new()?.logs_dir().display()));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    };
+    let pid = child.as_ref().map(|c| c.id());
+    let how = pid.map(|p| format!("pid {}", p)).unwrap_or_else(|| format!("vía {}", via));
+    done(json, serde_json::json!({"ok": true, "already_running": false, "version": version, "panel": panel.base(), "pid": pid, "via": if pid.is_some() { "process" } else { via }}), format!("agente v{} escuchando en {} ({})", version, panel.base(), how));
+    Ok(())
 }
 
-fn policy_refresh() -> Result<()> {
-    let base = panel_base();
-    let url = format!("{}/policy/refresh", base);
-    let resp = Client::new().post(url).send()?;
-    if resp.status().is_success() { println!("[ok] refresh solicitado"); Ok(()) } else { Err(anyhow!("falló refresh: {}", resp.status())) }
+fn daemon_stop(json: bool) -> Result<()> {
+    let panel = Panel::with_timeout(std::time::Duration::from_secs(2));
+    let svc = service::status();
+    // con el servicio activo hay que pararlo por el gestor: matar el proceso solo provoca un reinicio
+    let service_stopped = svc.installed && svc.active != Some(false) && service::stop().is_ok();
+    let killed = service::kill_processes();
+    if !service_stopped && killed.is_empty() && probe_agent(&panel)?.is_none() {
+        done(json, serde_json::json!({"ok": true, "was_running": false}), "el agente no estaba corriendo");
+        return Ok(());
+    }
+    if wait_agent(&panel, false, std::time::Duration::from_secs(10)).is_none() {
+        return Err(anyhow!("el agente sigue respondiendo en {}; ¿lo lanzó otro usuario o un gestor distinto?", panel.base()));
+    }
+    // el panel cierra antes que el proceso: esperar a que termine de vaciar y salir
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while !service::find_processes().is_empty() && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    }
+    let how = if service_stopped { format!("servicio {} detenido", svc.manager) } else { format!("pid {:?}", killed) };
+    done(json, serde_json::json!({"ok": true, "was_running": true, "service_stopped": service_stopped, "killed": killed}), format!("agente detenido ({})", how));
+    Ok(())
+}
+
+fn daemon_status(json: bool) -> Result<()> {
+    let panel = Panel::with_timeout(std::time::Duration::from_secs(2));
+    let probe = probe_agent(&panel);
+    let svc = service::status();
+    let procs = service::find_processes();
+    let listening = matches!(probe, Ok(Some(_)));
+    let holder = agent_core::instance::holder(&Paths::new()?);
+    if json {
+        // sin el token de apagado
+        let instance = holder.as_ref().map(|i| {
+            serde_json::json!({"locked": true, "pid": i.pid, "version": i.version, "panel_addr": i.panel_addr, "started_at_ms": i.started_at_ms})
+        }).unwrap_or_else(|| serde_json::json!({"locked": false}));
+        print_json(&serde_json::json!({
+            "panel": {"addr": panel.base(), "listening": listening, "version": probe.as_ref().ok().cloned().flatten(), "error": probe.as_ref().err().map(|e| e.to_string())},
+            "instance": instance,
+            "processes": procs,
+            "service": svc,
+        }));
+    } else {
+        let row = |k: &str, v: String| println!("{:<18}{}", k, v);
+        row("Panel", match &probe {
+            Ok(Some(v)) => format!("{} (v{})", panel.base(), v),
+            Ok(None) => format!("{} sin respuesta", panel.base()),
+            Err(e) => e.to_string(),
+        });
+        row("Instancia", match &holder {
+            Some(i) => format!("pid {} (v{}, panel {}, desde {})", i.pid, i.version, i.panel_addr, fmt_ts(i.started_at_ms)),
+            None => "ninguna en marcha".to_string(),
+        });
+        row("Procesos", if procs.is_empty() { "ninguno".to_string() } else { procs.iter().map(|p| p.pid.to_string()).collect::<Vec<_>>().join(", ") });
+        let svc_state = match (svc.installed, svc.active) {
+            (false, _) => "no instalado".to_string(),
+            (true, Some(true)) => "activo".to_string(),
+            (true, Some(false)) => format!("inactivo ({})", svc.detail.clone().unwrap_or_default()),
+            (true, None) => "instalado".to_string(),
+        };
+        row("Servicio", format!("{}: {}", svc.manager, svc_state));
+        if let Some(f) = &svc.file { row("Archivo", f.display().to_string()); }
+        if procs.len() > 1 { println!("[warn] hay {} procesos agent-daemon; solo uno puede tener el panel", procs.len()); }
+    }
+    match probe {
+        // como `systemctl status`: 3 si no está corriendo
+        Ok(None) => Err(PanelError::Unreachable(panel.base().to_string()).into()),
+        Err(e) => Err(e),
+        Ok(Some(_)) => Ok(()),
+    }
+}
+
+fn daemon_install(spec: SpecArgs, start: bool, dry_run: bool, json: bool) -> Result<()> {
+    let spec = service::ServiceSpec::resolve(spec.daemon_path, spec.workdir)?;
+    if dry_run {
+        let file = service::render(&spec).ok_or_else(|| anyhow!("servicio no soportado en {}", std::env::consts::OS))?;
+        if json { print_json(&serde_json::json!({"ok": true, "manager": service::status().manager, "content": file})); } else { print!("{}", file); }
+        return Ok(());
+    }
+    let path = service::install(&spec)?;
+    let mut killed = Vec::new();
+    if start {
+        // un daemon suelto (lanzado a mano) tiene el puerto: se reemplaza por el del servicio
+        killed = service::kill_processes();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while !service::find_processes().is_empty() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(250));
+        }
+        service::start()?;
+    }
+    let mut v = serde_json::json!({"ok": true, "manager": service::status().manager, "file": path, "daemon": spec.daemon, "workdir": spec.workdir, "started": start, "replaced_pids": killed});
+    if start {
+        let panel = Panel::with_timeout(std::time::Duration::from_secs(2));
+        let version = wait_agent(&panel, true, std::time::Duration::from_secs(15)).ok_or_else(|| anyhow!("servicio instalado en {} pero el agente no respondió en {}", path.display(), panel.base()))?;
+        v["version"] = version.into();
+    }
+    done(json, v, format!("servicio instalado en {}{}", path.display(), if start { " y agente en marcha" } else { "" }));
+    Ok(())
+}
+
+fn daemon_uninstall(json: bool) -> Result<()> {
+    match service::uninstall()? {
+        Some(path) => done(json, serde_json::json!({"ok": true, "removed": path}), format!("servicio desinstalado ({})", path.display())),
+        None => done(json, serde_json::json!({"ok": true, "removed": null}), "no había servicio instalado"),
+    }
+    Ok(())
 }
@@ -0,0 +1,75 @@
+// `--offline`: lee los stores locales con agent-core, sin el panel del daemon. Las conexiones son
+// de solo lectura (`agent_core::queue::open_read_only`), así que conviven con un daemon corriendo.
+// Cada función arma el mismo JSON que el endpoint equivalente para compartir la salida.
+use agent_core::focus::FocusStore;
+use agent_core::paths::Paths;
+use agent_core::queue::QueueReader;
+use anyhow::Result;
+use serde_json::json;
+
+fn read_json(path: &std::path::Path) -> Option<serde_json::Value> {
+    std::fs::read_to_string(path).ok().and_then(|txt| serde_json::from_str(&txt).ok())
+}
+
+/// Lo que se puede saber sin el daemon: identidad, cola, último bloque de foco, policy y descartes.
+pub fn status(paths: &Paths) -> Result<serde_json::Value> {
+    let state = agent_core::state::AgentState::load(paths)?;
+    let queue = QueueReader::open(paths).and_then(|q| q.stats());
+    let last_focus = FocusStore::open_read_only(paths).and_then(|s| s.list_recent(1, 0)).ok().and_then(|v| v.into_iter().next());
+    let drops: std::collections::BTreeMap<String, u64> = read_json(&paths.drops_file()).and_then(|v| serde_json::from_value(v).ok()).unwrap_or_default();
+    let meta = read_json(&paths.policy_meta_file()).unwrap_or_default();
+    Ok(json!({
+        "offline": true,
+        "data_dir": paths.data_dir,
+        "device_id": state.as_ref().map(|s| s.device_id.clone()),
+        "agent_version": state.as_ref().map(|s| s.agent_version.clone()),
+        "org_id": state.as_ref().and_then(|s| s.org_id.clone()),
+        "user_email": state.as_ref().and_then(|s| s.user_email.clone()),
+        "queue_len": queue.as_ref().map(|q| q.events).unwrap_or(0),
+        "queue": queue.as_ref().ok(),
+        "queue_error": queue.as_ref().err().map(|e| e.to_string()),
+        "last_focus_end_ms": last_focus.map(|b| b.end_ms),
+        "policy_file": paths.policy_file().exists(),
+        "policy_etag": meta.get("etag"),
+        "policy_verified": agent_core::policy_sig::load_verified_policy(paths).is_some(),
+        "dropped_events": drops.values().sum::<u64>(),
+        "dropped_by_reason": drops,
+    }))
+}
+
+/// Igual que `/queue`; sin `decrypt` cada item trae solo metadatos (id, fecha, intentos, tamaño).
+pub fn queue(paths: &Paths, limit: usize, decrypt: bool) -> Result<serde_json::Value> {
+    let mut reader = QueueReader::open(paths)?;
+    if decrypt { reader = reader.with_key(paths)?; }
+    let stats = reader.stats()?;
+    let items = reader.list_desc(limit)?;
+    let top: Vec<serde_json::Value> = if decrypt {
+        items.into_iter().filter_map(|e| e.event).collect()
+    } else {
+        items.iter().map(|e| json!(e)).collect()
+    };
+    Ok(json!({ "queue_len": stats.events, "top": top, "stats": stats, "decrypted": decrypt }))
+}
+
+/// Igual que `/focus/blocks`: el mínimo por defecto sale de `focusMinMinutes` en `policy.json`.
+pub fn focus_blocks(paths: &Paths, limit: usize, min_minutes: Option<u32>) -> Result<serde_json::Value> {
+    let min_m = min_minutes
+        .or_else(|| read_json(&paths.policy_file()).and_then(|p| p.get("focusMinMinutes").and_then(|x| x.as_u64())).map(|m| m as u32))
+        .unwrap_or(5);
+    let rows = FocusStore::open_read_only(paths)?.list_recent(limit, 0)?;
+    let items: Vec<_> = rows.into_iter().filter(|r| (r.dur_ms as u64) >= (min_m as u64).saturating_mul(60_000)).collect();
+    Ok(json!({ "items": items }))
+}
+
+/// Igual que `/focus/aggregate`.
+pub fn focus_aggregate(paths: &Paths, days: u32) -> Result<serde_json::Value> {
+    let days = days.min(90);
+    let items = FocusStore::open_read_only(paths)?.aggregate_last_days_by_app(days)?;
+    Ok(json!({ "days": days, "items": items }))
+}
+
+/// Solo los contadores persistidos (`drops.json`); el detalle reciente vive en memoria del daemon.
+pub fn drops(paths: &Paths) -> Result<serde_json::Value> {
+    let by_reason: std::collections::BTreeMap<String, u64> = read_json(&paths.drops_file()).and_then(|v| serde_json::from_value(v).ok()).unwrap_or_default();
+    Ok(json!({ "dropped_events": by_reason.values().sum::<u64>(), "dropped_by_reason": by_reason, "items": [] }))
+}
@@ -0,0 +1,99 @@
+// Salida de los comandos: tablas alineadas, CSV y formateo de fechas/duraciones.
+
+/// Formato de listados (`--format`); `--json` equivale a `--format json`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl Format {
+    pub fn resolve(format: Option<Format>, json: bool) -> Format {
+        if json { Format::Json } else { format.unwrap_or(Format::Table) }
+    }
+}
+
+pub fn print_json(v: &serde_json::Value) {
+    println!("{}", serde_json::to_string_pretty(v).unwrap_or_default());
+}
+
+/// Tabla con columnas alineadas por ancho en caracteres; las celdas largas se recortan.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    const MAX_CELL: usize = 60;
+    let cells: Vec<Vec<String>> = rows.iter().map(|r| r.iter().map(|c| truncate(c, MAX_CELL)).collect()).collect();
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for r in &cells {
+        for (i, c) in r.iter().enumerate() {
+            if let Some(w) = widths.get_mut(i) { *w = (*w).max(c.chars().count()); }
+        }
+    }
+    let line = |r: &[String]| {
+        let parts: Vec<String> = r.iter().enumerate().map(|(i, c)| format!("{:<w$}", c, w = widths.get(i).copied().unwrap_or(0))).collect();
+        println!("{}", parts.join("  ").trim_end());
+    };
+    line(&headers.iter().map(|h| h.to_string()).collect::<Vec<_>>());
+    for r in &cells { line(r); }
+}
+
+pub fn print_csv(headers: &[&str], rows: &[Vec<String>]) {
+    println!("{}", headers.join(","));
+    for r in rows {
+        println!("{}", r.iter().map(|c| escape_csv(c)).collect::<Vec<_>>().join(","));
+    }
+}
+
+fn escape_csv(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else { s.to_string() }
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    let s = s.replace(['\n', '\t'], " ");
+    if s.chars().count() <= max { return s; }
+    let mut out: String = s.chars().take(max - 1).collect();
+    out.push('…');
+    out
+}
+
+/// Epoch ms → fecha local; `0` (nunca) → `-`.
+pub fn fmt_ts(ms: u64) -> String {
+    if ms == 0 { return "-".to_string(); }
+    chrono::DateTime::from_timestamp_millis(ms as i64)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| ms.to_string())
+}
+
+/// Duración compacta: `45s`, `12m05s`, `3h07m`.
+pub fn fmt_dur(ms: u64) -> String {
+    let s = ms / 1000;
+    match s {
+        0..=59 => format!("{}s", s),
+        60..=3599 => format!("{}m{:02}s", s / 60, s % 60),
+        _ => format!("{}h{:02}m", s / 3600, (s % 3600) / 60),
+    }
+}
+
+/// Antigüedad de un epoch ms respecto a ahora (`hace 12s`); `0` → `nunca`.
+pub fn fmt_age(ms: u64) -> String {
+    if ms == 0 { return "nunca".to_string(); }
+    format!("hace {}", fmt_dur(now_ms().saturating_sub(ms)))
+}
+
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Campo como texto para una celda (`null` → vacío).
+pub fn cell(v: &serde_json::Value, key: &str) -> String {
+    match v.get(key) {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(x) => x.to_string(),
+    }
+}
+
+pub fn u64_of(v: &serde_json::Value, key: &str) -> u64 {
+    v.get(key).and_then(|x| x.as_u64().or_else(|| x.as_i64().map(|i| i.max(0) as u64))).unwrap_or(0)
+}
@@ -0,0 +1,103 @@
+// Cliente del panel local del daemon (`PANEL_ADDR`, por defecto 127.0.0.1:49219).
+// Los errores se distinguen para el código de salida: agente caído (3) o agente que
+// respondió con error (2); el resto de fallos de la CLI sale con 1.
+use reqwest::blocking::{Client, RequestBuilder};
+use std::time::Duration;
+
+pub const EXIT_AGENT_ERROR: u8 = 2;
+pub const EXIT_UNREACHABLE: u8 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PanelError {
+    #[error("el agente no responde en {0} (¿está corriendo? con --offline se leen los datos locales)")]
+    Unreachable(String),
+    #[error("fallo de comunicación con el agente en {path}: {source}")]
+    Transport { path: String, source: reqwest::Error },
+    #[error("el agente respondió {status} en {path}: {body}")]
+    Status { path: String, status: reqwest::StatusCode, body: String },
+    #[error("{0}")]
+    Rejected(String),
+    #[error("respuesta inválida del agente en {path}: {source}")]
+    Invalid { path: String, source: reqwest::Error },
+}
+
+impl PanelError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            PanelError::Unreachable(_) => EXIT_UNREACHABLE,
+            _ => EXIT_AGENT_ERROR,
+        }
+    }
+
+    /// Código estable para la salida `--json`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PanelError::Unreachable(_) => "agent_unreachable",
+            PanelError::Transport { .. } => "agent_transport_error",
+            PanelError::Status { .. } => "agent_http_error",
+            PanelError::Rejected(_) => "agent_rejected",
+            PanelError::Invalid { .. } => "agent_invalid_response",
+        }
+    }
+
+    pub fn is_unreachable(&self) -> bool { matches!(self, PanelError::Unreachable(_)) }
+}
+
+pub struct Panel {
+    base: String,
+    client: Client,
+}
+
+impl Panel {
+    pub fn new() -> Self { Self::with_timeout(Duration::from_secs(10)) }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        let base = std::env::var("PANEL_ADDR").map(|a| format!("http://{}", a)).unwrap_or_else(|_| "http://127.0.0.1:49219".to_string());
+        // directo al loopback: sin proxies del entorno (reqwest no excluye 127.0.0.1 por sí solo)
+        Self { base, client: Client::builder().no_proxy().timeout(timeout).build().unwrap_or_default() }
+    }
+
+    pub fn base(&self) -> &str { &self.base }
+
+    pub fn url(&self, path: &str) -> String { format!("{}{}", self.base, path) }
+
+    pub fn get_json(&self, path: &str) -> Result<serde_json::Value, PanelError> {
+        self.json(path, self.client.get(self.url(path)))
+    }
+
+    pub fn post_json(&self, path: &str, body: &serde_json::Value) -> Result<serde_json::Value, PanelError> {
+        self.json(path, self.client.post(self.url(path)).json(body))
+    }
+
+    pub fn get_text(&self, path: &str) -> Result<String, PanelError> {
+        self.send(path, self.client.get(self.url(path)))?.text().map_err(|source| PanelError::Invalid { path: path.to_string(), source })
+    }
+
+    pub fn get_bytes(&self, path: &str) -> Result<Vec<u8>, PanelError> {
+        let resp = self.send(path, self.client.get(self.url(path)))?;
+        resp.bytes().map(|b| b.to_vec()).map_err(|source| PanelError::Invalid { path: path.to_string(), source })
+    }
+
+    /// JSON de la respuesta; `{"ok": false, "error": ...}` cuenta como error del agente.
+    fn json(&self, path: &str, req: RequestBuilder) -> Result<serde_json::Value, PanelError> {
+        let v: serde_json::Value = self.send(path, req)?.json().map_err(|source| PanelError::Invalid { path: path.to_string(), source })?;
+        if v.get("ok").and_then(|b| b.as_bool()) == Some(false) {
+            return Err(PanelError::Rejected(v.get("error").and_then(|x| x.as_str()).unwrap_or("error desconocido").to_string()));
+        }
+        Ok(v)
+    }
+
+    fn send(&self, path: &str, req: RequestBuilder) -> Result<reqwest::blocking::Response, PanelError> {
+        let resp = req.send().map_err(|e| if e.is_connect() { PanelError::Unreachable(self.base.clone()) } else { PanelError::Transport { path: path.to_string(), source: e } })?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().unwrap_or_default();
+            let detail = serde_json::from_str::<serde_json::Value>(&body).ok().and_then(|v| v.get("error").and_then(|x| x.as_str()).map(str::to_string));
+            // texto corto tal cual; HTML u otras páginas largas (otro servidor en el puerto) no
+            let plain = Some(body.trim()).filter(|b| !b.is_empty() && b.len() <= 200 && !b.contains('<') && !b.contains('\n')).map(str::to_string);
+            let body = detail.or(plain).unwrap_or_else(|| "sin detalle".to_string());
+            return Err(PanelError::Status { path: path.to_string(), status, body });
+        }
+        Ok(resp)
+    }
+}
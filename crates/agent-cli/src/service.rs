@@ -0,0 +1,396 @@
+// `agent daemon ...`: ciclo de vida del daemon. Con servicio instalado se delega en el gestor del
+// SO (unit de usuario de systemd, LaunchAgent o tarea programada de Windows), que lo relanza si
+// se cae; sin servicio la CLI lanza el binario en segundo plano y lo detiene por PID.
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Unit `riporagent.service` (systemd de usuario)
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const SYSTEMD_UNIT: &str = "riporagent.service";
+/// El mismo label que usa agent-ui-macos para su LaunchAgent
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+const LAUNCHD_LABEL: &str = "com.ripor.agent";
+/// El mismo nombre que la clave Run de agent-ui-windows
+#[cfg_attr(not(windows), allow(dead_code))]
+const WINDOWS_TASK: &str = "RiporAgent";
+
+const DAEMON_BIN: &str = "agent-daemon";
+
+/// Qué ejecuta el servicio: el binario del daemon y su directorio de trabajo (de donde lee `.env`).
+pub struct ServiceSpec {
+    pub daemon: PathBuf,
+    pub workdir: PathBuf,
+}
+
+impl ServiceSpec {
+    /// `--daemon-path` o `agent-daemon` junto a la CLI o en el `PATH`; `--workdir` o el directorio actual.
+    pub fn resolve(daemon: Option<PathBuf>, workdir: Option<PathBuf>) -> Result<Self> {
+        let daemon = match daemon {
+            Some(p) => p,
+            None => find_daemon_binary().ok_or_else(|| anyhow!("no se encontró {}{} junto a la CLI ni en el PATH; usa --daemon-path", DAEMON_BIN, std::env::consts::EXE_SUFFIX))?,
+        };
+        let daemon = daemon.canonicalize().with_context(|| format!("binario del daemon {}", daemon.display()))?;
+        let workdir = match workdir {
+            Some(w) => w,
+            None => std::env::current_dir()?,
+        };
+        let workdir = workdir.canonicalize().with_context(|| format!("directorio de trabajo {}", workdir.display()))?;
+        Ok(Self { daemon, workdir })
+    }
+}
+
+fn find_daemon_binary() -> Option<PathBuf> {
+    let name = format!("{}{}", DAEMON_BIN, std::env::consts::EXE_SUFFIX);
+    let sibling = std::env::current_exe().ok().map(|exe| exe.with_file_name(&name)).filter(|p| p.is_file());
+    sibling.or_else(|| std::env::split_paths(&std::env::var_os("PATH")?).map(|d| d.join(&name)).find(|p| p.is_file()))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceStatus {
+    /// `systemd` | `launchd` | `schtasks` | `none`
+    pub manager: &'static str,
+    pub installed: bool,
+    pub file: Option<PathBuf>,
+    /// `None` si no se pudo consultar al gestor
+    pub active: Option<bool>,
+    pub detail: Option<String>,
+}
+
+/// Ejecuta un comando del gestor; error con su stderr si termina mal.
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos", windows)), allow(dead_code))]
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let out = Command::new(program).args(args).output().with_context(|| format!("no se pudo ejecutar {}", program))?;
+    if !out.status.success() {
+        let err = String::from_utf8_lossy(&out.stderr);
+        let err = if err.trim().is_empty() { String::from_utf8_lossy(&out.stdout) } else { err };
+        return Err(anyhow!("{} {} falló ({}): {}", program, args.join(" "), out.status, err.trim()));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+
+    fn unit_path() -> Result<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+        Ok(base.ok_or_else(|| anyhow!("sin HOME ni XDG_CONFIG_HOME"))?.join("systemd/user").join(SYSTEMD_UNIT))
+    }
+
+    /// `Restart=always` + `RestartSec=1` y sin límite de arranques: MTTR ≤ 5 s tras cualquier salida
+    /// que no sea `systemctl stop`. Si otra instancia tiene el lock (p. ej. tras un `--replace`), el
+    /// relanzado sale con 75 y `RestartPreventExitStatus` corta el ciclo.
+    pub fn unit_file(spec: &ServiceSpec) -> String {
+        format!(
+            "[Unit]\n\
+             Description=RiporAgent (agente de tiempo)\n\
+             After=network-online.target\n\
+             StartLimitIntervalSec=0\n\
+             \n\
+             [Service]\n\
+             Type=simple\n\
+             ExecStart=\"{daemon}\"\n\
+             WorkingDirectory={workdir}\n\
+             Restart=always\n\
+             RestartSec=1\n\
+             RestartPreventExitStatus={busy}\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n",
+            daemon = spec.daemon.display(),
+            workdir = spec.workdir.display(),
+            busy = agent_core::instance::EXIT_ALREADY_RUNNING,
+        )
+    }
+
+    fn systemctl(args: &[&str]) -> Result<String> {
+        let mut all = vec!["--user"];
+        all.extend_from_slice(args);
+        run("systemctl", &all)
+    }
+
+    pub fn install(spec: &ServiceSpec) -> Result<PathBuf> {
+        let path = unit_path()?;
+        if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+        std::fs::write(&path, unit_file(spec))?;
+        let enabled = systemctl(&["daemon-reload"]).and_then(|_| systemctl(&["enable", SYSTEMD_UNIT]));
+        if let Err(e) = enabled {
+            // sin bus de usuario (p. ej. sesión SSH sin `loginctl enable-linger`) la unit no sirve
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok(path)
+    }
+
+    pub fn uninstall() -> Result<Option<PathBuf>> {
+        let path = unit_path()?;
+        if !path.exists() { return Ok(None); }
+        // sin bus de usuario igual se borra el archivo
+        let _ = systemctl(&["disable", "--now", SYSTEMD_UNIT]);
+        std::fs::remove_file(&path)?;
+        let _ = systemctl(&["daemon-reload"]);
+        Ok(Some(path))
+    }
+
+    pub fn start() -> Result<()> { systemctl(&["start", SYSTEMD_UNIT]).map(|_| ()) }
+
+    pub fn stop() -> Result<()> { systemctl(&["stop", SYSTEMD_UNIT]).map(|_| ()) }
+
+    pub fn status() -> ServiceStatus {
+        let file = unit_path().ok().filter(|p| p.exists());
+        // `is-active` sale con != 0 si no está activo: se lee stdout igual
+        let out = Command::new("systemctl").args(["--user", "is-active", SYSTEMD_UNIT]).output();
+        let state = out.ok().map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string()).filter(|s| !s.is_empty());
+        ServiceStatus { manager: "systemd", installed: file.is_some(), active: state.as_deref().map(|s| s == "active"), detail: state, file }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use super::*;
+
+    fn plist_path() -> Result<PathBuf> {
+        let home = std::env::var_os("HOME").ok_or_else(|| anyhow!("sin HOME"))?;
+        Ok(PathBuf::from(home).join("Library/LaunchAgents").join(format!("{}.plist", LAUNCHD_LABEL)))
+    }
+
+    fn domain() -> String {
+        let uid = run("/usr/bin/id", &["-u"]).unwrap_or_default();
+        format!("gui/{}", uid.trim())
+    }
+
+    fn xml_escape(s: &str) -> String { s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;") }
+
+    /// `KeepAlive` relanza el daemon si termina con error; `ThrottleInterval` baja la espera por
+    /// defecto (10 s). launchd no distingue códigos de salida: con otra instancia con el lock el
+    /// daemon sale con 0 para no quedar en un ciclo de relanzamientos.
+    pub fn plist(spec: &ServiceSpec) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0"><dict>
+  <key>Label</key><string>{label}</string>
+  <key>RunAtLoad</key><true/>
+  <key>KeepAlive</key><dict><key>SuccessfulExit</key><false/></dict>
+  <key>ThrottleInterval</key><integer>1</integer>
+  <key>ProcessType</key><string>Background</string>
+  <key>WorkingDirectory</key><string>{workdir}</string>
+  <key>ProgramArguments</key>
+  <array>
+    <string>{daemon}</string>
+    <string>{exit_ok}</string>
+  </array>
+</dict></plist>
+"#,
+            label = LAUNCHD_LABEL,
+            workdir = xml_escape(&spec.workdir.display().to_string()),
+            daemon = xml_escape(&spec.daemon.display().to_string()),
+            exit_ok = agent_core::instance::EXIT_OK_IF_RUNNING_ARG,
+        )
+    }
+
+    pub fn install(spec: &ServiceSpec) -> Result<PathBuf> {
+        let path = plist_path()?;
+        if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+        std::fs::write(&path, plist(spec))?;
+        // si ya estaba cargado, se descarga para que `start` tome el plist nuevo
+        let _ = run("/bin/launchctl", &["bootout", &format!("{}/{}", domain(), LAUNCHD_LABEL)]);
+        Ok(path)
+    }
+
+    pub fn uninstall() -> Result<Option<PathBuf>> {
+        let path = plist_path()?;
+        if !path.exists() { return Ok(None); }
+        let _ = run("/bin/launchctl", &["bootout", &format!("{}/{}", domain(), LAUNCHD_LABEL)]);
+        std::fs::remove_file(&path)?;
+        Ok(Some(path))
+    }
+
+    pub fn start() -> Result<()> {
+        let domain = domain();
+        // si ya estaba cargado `bootstrap` falla; `kickstart` lo arranca en ambos casos
+        let _ = run("/bin/launchctl", &["bootstrap", &domain, &plist_path()?.display().to_string()]);
+        run("/bin/launchctl", &["kickstart", &format!("{}/{}", domain, LAUNCHD_LABEL)]).map(|_| ())
+    }
+
+    /// `bootout`: con `KeepAlive` matar el proceso no alcanza, launchd lo relanza.
+    pub fn stop() -> Result<()> { run("/bin/launchctl", &["bootout", &format!("{}/{}", domain(), LAUNCHD_LABEL)]).map(|_| ()) }
+
+    pub fn status() -> ServiceStatus {
+        let file = plist_path().ok().filter(|p| p.exists());
+        let printed = run("/bin/launchctl", &["print", &format!("{}/{}", domain(), LAUNCHD_LABEL)]);
+        let state = printed.as_ref().ok().and_then(|out| out.lines().map(str::trim).find(|l| l.starts_with("state = ")).map(|l| l.trim_start_matches("state = ").to_string()));
+        let (active, detail) = match (&printed, state) {
+            (Ok(_), Some(s)) => (Some(s == "running"), Some(s)),
+            (Ok(_), None) => (None, Some("cargado".to_string())),
+            (Err(_), _) => (Some(false), Some("no cargado".to_string())),
+        };
+        ServiceStatus { manager: "launchd", installed: file.is_some(), file, active, detail }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::*;
+
+    fn xml_escape(s: &str) -> String { s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;") }
+
+    /// Tarea al iniciar sesión del usuario actual, sin elevación. El Programador de tareas no
+    /// reintenta antes de 1 minuto, así que aquí el MTTR es de hasta un minuto. Con otra instancia
+    /// con el lock el daemon sale con 0: no cuenta como fallo.
+    pub fn task_xml(spec: &ServiceSpec) -> String {
+        let user = format!("{}\\{}", std::env::var("USERDOMAIN").unwrap_or_default(), std::env::var("USERNAME").unwrap_or_default());
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo><Description>RiporAgent (agente de tiempo)</Description></RegistrationInfo>
+  <Triggers><LogonTrigger><Enabled>true</Enabled><UserId>{user}</UserId></LogonTrigger></Triggers>
+  <Principals><Principal id="Author"><UserId>{user}</UserId><LogonType>InteractiveToken</LogonType><RunLevel>LeastPrivilege</RunLevel></Principal></Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <RestartOnFailure><Interval>PT1M</Interval><Count>999</Count></RestartOnFailure>
+  </Settings>
+  <Actions Context="Author"><Exec><Command>{daemon}</Command><Arguments>{exit_ok}</Arguments><WorkingDirectory>{workdir}</WorkingDirectory></Exec></Actions>
+</Task>
+"#,
+            user = xml_escape(&user),
+            daemon = xml_escape(&spec.daemon.display().to_string()),
+            workdir = xml_escape(&spec.workdir.display().to_string()),
+            exit_ok = agent_core::instance::EXIT_OK_IF_RUNNING_ARG,
+        )
+    }
+
+    pub fn install(spec: &ServiceSpec) -> Result<PathBuf> {
+        // schtasks espera el XML en UTF-16 con BOM
+        let file = std::env::temp_dir().join("riporagent_task.xml");
+        let mut bytes = vec![0xFF, 0xFE];
+        for u in task_xml(spec).encode_utf16() { bytes.extend_from_slice(&u.to_le_bytes()); }
+        std::fs::write(&file, bytes)?;
+        let created = run("schtasks", &["/Create", "/TN", WINDOWS_TASK, "/XML", &file.display().to_string(), "/F"]);
+        let _ = std::fs::remove_file(&file);
+        created?;
+        Ok(PathBuf::from(format!("Task Scheduler\\{}", WINDOWS_TASK)))
+    }
+
+    pub fn uninstall() -> Result<Option<PathBuf>> {
+        if !status().installed { return Ok(None); }
+        let _ = stop();
+        run("schtasks", &["/Delete", "/TN", WINDOWS_TASK, "/F"])?;
+        Ok(Some(PathBuf::from(format!("Task Scheduler\\{}", WINDOWS_TASK))))
+    }
+
+    pub fn start() -> Result<()> { run("schtasks", &["/Run", "/TN", WINDOWS_TASK]).map(|_| ()) }
+
+    pub fn stop() -> Result<()> { run("schtasks", &["/End", "/TN", WINDOWS_TASK]).map(|_| ()) }
+
+    pub fn status() -> ServiceStatus {
+        // /XML: salida sin localizar; el estado en vivo sale de los procesos y del panel
+        let installed = run("schtasks", &["/Query", "/TN", WINDOWS_TASK, "/XML"]).is_ok();
+        let file = installed.then(|| PathBuf::from(format!("Task Scheduler\\{}", WINDOWS_TASK)));
+        ServiceStatus { manager: "schtasks", installed, file, active: None, detail: None }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod imp {
+    use super::*;
+
+    pub fn install(_spec: &ServiceSpec) -> Result<PathBuf> { Err(anyhow!("servicio no soportado en {}", std::env::consts::OS)) }
+    pub fn uninstall() -> Result<Option<PathBuf>> { Ok(None) }
+    pub fn start() -> Result<()> { Err(anyhow!("servicio no soportado en {}", std::env::consts::OS)) }
+    pub fn stop() -> Result<()> { Err(anyhow!("servicio no soportado en {}", std::env::consts::OS)) }
+    pub fn status() -> ServiceStatus { ServiceStatus { manager: "none", installed: false, file: None, active: None, detail: None } }
+}
+
+/// Instala (o reescribe) y habilita el servicio de usuario, sin arrancarlo. Devuelve dónde quedó.
+pub fn install(spec: &ServiceSpec) -> Result<PathBuf> { imp::install(spec) }
+
+/// Detiene y borra el servicio; `None` si no estaba instalado.
+pub fn uninstall() -> Result<Option<PathBuf>> { imp::uninstall() }
+
+pub fn start() -> Result<()> { imp::start() }
+
+pub fn stop() -> Result<()> { imp::stop() }
+
+pub fn status() -> ServiceStatus { imp::status() }
+
+#[derive(Debug, Serialize)]
+pub struct DaemonProcess {
+    pub pid: u32,
+    pub exe: Option<PathBuf>,
+}
+
+/// Procesos `agent-daemon` del usuario actual.
+pub fn find_processes() -> Vec<DaemonProcess> {
+    use sysinfo::{ProcessRefreshKind, System, UpdateKind};
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(ProcessRefreshKind::new().with_user(UpdateKind::Always).with_exe(UpdateKind::Always));
+    let me = sysinfo::get_current_pid().ok().and_then(|pid| sys.process(pid)).and_then(|p| p.user_id().cloned());
+    let name = format!("{}{}", DAEMON_BIN, std::env::consts::EXE_SUFFIX);
+    let mut out: Vec<DaemonProcess> = sys
+        .processes()
+        .values()
+        // en Linux sysinfo lista también los hilos; los zombies ya no cuentan
+        .filter(|p| p.thread_kind().is_none() && p.status() != sysinfo::ProcessStatus::Zombie)
+        // `name` se trunca a 15 caracteres en Linux: se compara también el ejecutable
+        .filter(|p| p.name() == name || p.exe().and_then(Path::file_name).is_some_and(|f| f == name.as_str()))
+        .filter(|p| me.is_none() || p.user_id() == me.as_ref())
+        .map(|p| DaemonProcess { pid: p.pid().as_u32(), exe: p.exe().map(Path::to_path_buf) })
+        .collect();
+    out.sort_by_key(|p| p.pid);
+    out
+}
+
+/// SIGTERM (cierre ordenado del panel y la cola); en Windows, terminación directa.
+pub fn kill_processes() -> Vec<u32> {
+    use sysinfo::{Pid, ProcessRefreshKind, Signal, System};
+    let pids = find_processes();
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(ProcessRefreshKind::new());
+    pids.into_iter()
+        .filter(|d| {
+            sys.process(Pid::from_u32(d.pid)).is_some_and(|p| p.kill_with(Signal::Term).unwrap_or_else(|| p.kill()))
+        })
+        .map(|d| d.pid)
+        .collect()
+}
+
+/// Lanza el daemon desacoplado de la terminal (sin stdin/stdout; los logs van a su archivo).
+pub fn spawn_detached(spec: &ServiceSpec) -> Result<std::process::Child> {
+    let mut cmd = Command::new(&spec.daemon);
+    cmd.current_dir(&spec.workdir).stdin(std::process::Stdio::null()).stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // grupo propio: no recibe el Ctrl-C de la terminal que lo lanzó
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+    cmd.spawn().with_context(|| format!("no se pudo lanzar {}", spec.daemon.display()))
+}
+
+/// Contenido del archivo de servicio que escribiría `install` (para `--dry-run`).
+pub fn render(spec: &ServiceSpec) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    return Some(imp::unit_file(spec));
+    #[cfg(target_os = "macos")]
+    return Some(imp::plist(spec));
+    #[cfg(windows)]
+    return Some(imp::task_xml(spec));
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = spec;
+        None
+    }
+}
@@ -0,0 +1,61 @@
+// Costo por muestra de las exclusiones de policy: matchers precompilados vs. reconstruir
+// los globsets en cada tick (comportamiento anterior de `drop_reason`).
+// cargo bench -p agent-core --bench policy_match
+use agent_core::policy::{CompiledPolicy, Policy};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use globset::{Glob, GlobSetBuilder};
+
+fn sample_policy() -> Policy {
+    Policy {
+        excludeApps: (0..50).map(|i| format!("App {}", i)).chain(["1Password".to_string(), "Signal".to_string()]).collect(),
+        excludePatterns: (0..30).map(|i| format!("*proyecto-{}*", i)).chain(["*Incognito*".to_string(), "*banco*".to_string()]).collect(),
+        excludeExePaths: (0..20).map(|i| format!("/Applications/Tool{}.app/**", i)).collect(),
+        excludeTitleRegex: vec![r"(?i)\bpassword\b".into(), r"\d{4}-\d{4}-\d{4}-\d{4}".into(), r"^Private:".into()],
+        ..Policy::default()
+    }
+}
+
+const SAMPLES: &[(&str, &str, &str)] = &[
+    ("Google Chrome", "Pull requests · gerswin/timeTracker — Google Chrome", "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome"),
+    ("Signal", "Signal", "/Applications/Signal.app/Contents/MacOS/Signal"),
+    ("Safari", "Cuenta banco — extracto", "/Applications/Safari.app/Contents/MacOS/Safari"),
+    ("Code", "main.rs — timeTracker", "/Applications/Visual Studio Code.app/Contents/MacOS/Electron"),
+];
+
+fn compiled(c: &mut Criterion) {
+    let p = sample_policy();
+    c.bench_function("compile", |b| b.iter(|| CompiledPolicy::compile(black_box(&p))));
+    let cp = CompiledPolicy::compile(&p);
+    c.bench_function("evaluate_compiled", |b| {
+        b.iter(|| {
+            for (app, title, exe) in SAMPLES {
+                black_box(cp.excludes_app(app) || cp.excludes_title(title) || cp.excludes_exe(exe));
+            }
+        })
+    });
+    let key = agent_core::redact::TitleKey::from_bytes([7u8; 32]);
+    c.bench_function("redact_title", |b| {
+        b.iter(|| {
+            for (_, title, _) in SAMPLES {
+                black_box(cp.redact(title, &key));
+            }
+        })
+    });
+    c.bench_function("evaluate_rebuild_each_tick", |b| {
+        b.iter(|| {
+            for (app, title, exe) in SAMPLES {
+                let app_hit = p.excludeApps.iter().any(|a| a == app);
+                let mut tb = GlobSetBuilder::new();
+                for pat in &p.excludePatterns { if let Ok(g) = Glob::new(pat) { tb.add(g); } }
+                let title_hit = tb.build().map(|gs| gs.is_match(title)).unwrap_or(false);
+                let mut eb = GlobSetBuilder::new();
+                for pat in &p.excludeExePaths { if let Ok(g) = Glob::new(pat) { eb.add(g); } }
+                let exe_hit = eb.build().map(|gs| gs.is_match(exe)).unwrap_or(false);
+                black_box(app_hit || title_hit || exe_hit);
+            }
+        })
+    });
+}
+
+criterion_group!(benches, compiled);
+criterion_main!(benches);
@@ -8,6 +8,11 @@ pub struct AgentSecrets {
     pub agent_token: String,
     pub server_salt: String,
     pub device_id: Option<String>,
+    /// Expiración del `agent_token` (epoch ms); ausente en backends sin expiración
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
 }
 
 impl AgentSecrets {
@@ -19,6 +24,17 @@ impl AgentSecrets {
         Ok(Some(s))
     }
 
+    /// El token vence dentro de `margin_ms` (o ya venció). Sin `expires_at` nunca vence.
+    pub fn expires_within(&self, now_ms: u64, margin_ms: u64) -> bool {
+        self.expires_at.is_some_and(|exp| now_ms.saturating_add(margin_ms) >= exp)
+    }
+
+    /// Credenciales distintas de las que el llamador vio vencer (`stale_token`; `None` si no tenía):
+    /// otro loop ya las renovó y no hace falta volver a llamar al backend.
+    pub fn supersedes(&self, stale_token: Option<&str>) -> bool {
+        stale_token != Some(self.agent_token.as_str())
+    }
+
     pub fn save(&self, paths: &Paths) -> Result<()> {
         let f = paths.secrets_file();
         if let Some(p) = f.parent() { if !p.exists() { fs::create_dir_all(p)?; } }
@@ -0,0 +1,801 @@
+// Cliente único hacia el backend: URLs, cabeceras de autenticación, firma HMAC,
+// timeouts y política de re-autenticación viven aquí. Daemon y CLI lo comparten.
+use crate::auth::AgentSecrets;
+use crate::http::ClientFactory;
+use crate::paths::Paths;
+use crate::policy_sig::{PolicySignature, TrustStore, TrustedKey};
+use crate::state::AgentState;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 15;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackendError {
+    #[error("configuración incompleta: {0}")]
+    Config(String),
+    #[error("no autorizado (401)")]
+    Unauthorized,
+    #[error("acceso denegado (403)")]
+    Forbidden,
+    #[error("rate limit (429), reintentar en {retry_after:?}")]
+    RateLimited { retry_after: Option<Duration> },
+    /// Cualquier otro status no exitoso (5xx u otros 4xx inesperados)
+    #[error("respuesta del backend con status {status}")]
+    Server { status: u16 },
+    #[error("error de red: {0}")]
+    Network(reqwest::Error),
+    #[error("respuesta inválida: {0}")]
+    Decode(String),
+    #[error("secrets locales: {0}")]
+    Secrets(String),
+    #[error("dispositivo pendiente de enrolamiento (agent enroll --code ...)")]
+    EnrollmentRequired,
+}
+
+impl BackendError {
+    /// El error de red proviene de un pin SPKI que no coincide.
+    pub fn is_pin_mismatch(&self) -> bool {
+        matches!(self, BackendError::Network(e) if crate::http::is_pin_mismatch(e))
+    }
+
+    /// El backend rechazó el refresh token (o respondió algo ilegible): queda el bootstrap completo.
+    pub fn rejects_refresh(&self) -> bool {
+        matches!(self, BackendError::Unauthorized | BackendError::Forbidden | BackendError::Decode(_))
+    }
+
+    /// Errores transitorios que merecen reintento con backoff.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, BackendError::RateLimited { .. } | BackendError::Server { .. } | BackendError::Network(_))
+    }
+}
+
+impl From<reqwest::Error> for BackendError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() { BackendError::Decode(e.to_string()) } else { BackendError::Network(e) }
+    }
+}
+
+pub type BackendResult<T> = std::result::Result<T, BackendError>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootstrapRequest {
+    pub org_id: String,
+    pub user_email: String,
+    pub mac_address: String,
+    pub agent_version: String,
+}
+
+impl BootstrapRequest {
+    /// Identidad desde env/.env (`ORG_ID`, `USER_EMAIL`) + MAC primaria.
+    pub fn from_env(agent_version: &str) -> BackendResult<Self> {
+        let org_id = non_empty_env("ORG_ID")?;
+        let user_email = non_empty_env("USER_EMAIL")?;
+        Ok(Self { org_id, user_email, mac_address: primary_mac().unwrap_or_default(), agent_version: agent_version.to_string() })
+    }
+}
+
+/// Respuesta de bootstrap y refresh. `expiresAt` (epoch ms) / `expiresIn` (s)
+/// y `refreshToken` son opcionales: backends sin expiración no los envían.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootstrapResponse {
+    #[serde(default)]
+    pub agent_token: String,
+    #[serde(default)]
+    pub server_salt: String,
+    #[serde(default)]
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Solo en enrolamiento: identidad asignada por el backend
+    #[serde(default)]
+    pub org_id: Option<String>,
+    #[serde(default)]
+    pub user_email: Option<String>,
+    /// Solo en enrolamiento: claves públicas para verificar policies firmadas
+    #[serde(default)]
+    pub policy_keys: Option<Vec<TrustedKey>>,
+}
+
+impl BootstrapResponse {
+    fn expires_at_ms(&self) -> Option<u64> {
+        self.expires_at.or_else(|| self.expires_in.map(|s| now_ms().saturating_add(s.saturating_mul(1000))))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EnrollRequest {
+    pub code: String,
+    pub device_id: String,
+    pub mac_address: String,
+    pub hostname: String,
+    pub os: String,
+    pub agent_version: String,
+}
+
+/// Identidad efectiva (org/usuario) y de dónde sale.
+#[derive(Debug, Clone, Serialize)]
+pub struct Identity {
+    pub org_id: String,
+    pub user_email: String,
+    /// `enrollment` (agent_state.json) o `env` (ORG_ID/USER_EMAIL)
+    pub source: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+    pub device_id: Option<String>,
+    pub agent_version: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HeartbeatRequest {
+    pub status: String,
+    pub uptime_seconds: u64,
+    pub last_activity_ms: u64,
+    pub agent_version: String,
+    pub device_id: String,
+    pub queue_len: i64,
+    /// Edad del evento pendiente más antiguo (None con cola vacía)
+    pub oldest_event_age_ms: Option<u64>,
+    pub cpu_pct: f32,
+    pub mem_mb: u64,
+    pub cpu_p95_pct: f32,
+    pub mem_p95_mb: u64,
+    pub dropped_total: u64,
+    pub dropped_by_reason: std::collections::BTreeMap<String, u64>,
+    pub policy_etag: Option<String>,
+    pub capture: CaptureHealthReport,
+    /// Permisos de captura (macOS); `{"unsupported": true}` en otros sistemas
+    pub permissions: serde_json::Value,
+    pub last_ingest_ms: Option<u64>,
+    /// Publicación → aplicación de la última policy recibida
+    pub policy_apply_latency_ms: Option<u64>,
+    /// Resultado de comandos remotos ejecutados desde el último heartbeat entregado
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub acks: Vec<CommandAck>,
+}
+
+/// Salud del backend de captura (AX/NSWorkspace en macOS, Win32 en Windows).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CaptureHealthReport {
+    pub backend: String,
+    pub ok: bool,
+    pub last_sample_ms: Option<u64>,
+    pub consecutive_errors: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestEvent {
+    pub org_id: String,
+    pub user_email: String,
+    pub device_id: String,
+    pub mac_address: String,
+    pub os: String,
+    pub app_name: String,
+    pub window_title: String,
+    pub state: String,
+    pub timestamp_ms: u64,
+    pub dur_ms: u64,
+    pub category: String,
+    pub focus: bool,
+    pub focus_start_ms: u64,
+    pub focus_end_ms: u64,
+    pub input_idle_ms: u64,
+    pub media_hint: String,
+    pub agent_version: String,
+    /// Solo en eventos `state: "excluded"` (tiempo descartado por la policy, sin app ni título)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dropped_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestRequest {
+    pub events: Vec<IngestEvent>,
+    /// Heartbeat adjunto cuando coincide con un envío de eventos
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat: Option<HeartbeatRequest>,
+}
+
+/// Comando servidor → agente recibido en la respuesta de un heartbeat.
+/// `sig` = Ed25519 (base64) de [`RemoteCommand::signing_input`] con una clave de firma de policy
+/// (`keyId` opcional). `deviceId` (y `orgId` si viene) atan el comando a un dispositivo: firmado
+/// para otro, no se ejecuta aquí. Ver `commands.rs`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteCommand {
+    pub id: String,
+    #[serde(default)]
+    pub device_id: String,
+    #[serde(default)]
+    pub org_id: Option<String>,
+    pub action: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+    #[serde(default)]
+    pub issued_at: u64,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub key_id: Option<String>,
+    #[serde(default)]
+    pub sig: String,
+}
+
+impl RemoteCommand {
+    /// `deviceId\norgId\nid\naction\nissuedAt\nexpiresAt\nargs` con `args` en JSON compacto y
+    /// claves ordenadas (`orgId` y `expiresAt` vacíos si no vienen).
+    pub fn signing_input(&self) -> String {
+        let args = if self.args.is_null() { String::new() } else { self.args.to_string() };
+        let exp = self.expires_at.map(|e| e.to_string()).unwrap_or_default();
+        let org = self.org_id.as_deref().unwrap_or_default();
+        format!("{}\n{}\n{}\n{}\n{}\n{}\n{}", self.device_id, org, self.id, self.action, self.issued_at, exp, args)
+    }
+
+    pub fn verify(&self, trust: &TrustStore) -> bool {
+        if self.sig.trim().is_empty() { return false; }
+        let sig = PolicySignature { key_id: self.key_id.clone(), sig: self.sig.clone() };
+        trust.verify_bytes(self.signing_input().as_bytes(), &sig).is_valid()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    Done,
+    Failed,
+    /// Firma inválida, para otro destinatario, expirado o acción fuera de la allowlist: no se ejecutó
+    Rejected,
+}
+
+/// Acuse de un comando, enviado en el siguiente heartbeat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandAck {
+    pub id: String,
+    pub action: String,
+    pub status: CommandStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub finished_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct HeartbeatResponse {
+    #[serde(default)]
+    commands: Vec<RemoteCommand>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PolicyFetch {
+    NotModified,
+    /// Policy cruda (ya sin la envoltura `{"policy": ...}`), su ETag, cuándo se publicó
+    /// (`X-Policy-Published-At` o `publishedAt` en la envoltura, epoch ms) y su firma
+    /// (`X-Policy-Signature` o `signature` en la envoltura)
+    Updated { policy: serde_json::Value, etag: Option<String>, published_at: Option<u64>, signature: Option<PolicySignature> },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthPhase {
+    #[default]
+    Unknown,
+    Unconfigured,
+    PendingEnrollment,
+    PendingBootstrap,
+    Authenticated,
+    Reauthenticating,
+    Failed,
+}
+
+/// Estado de autenticación publicado en `/state` → `auth_state`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuthReport {
+    pub phase: AuthPhase,
+    pub last_auth_ms: Option<u64>,
+    pub reauth_count: u64,
+    pub refresh_count: u64,
+    pub expires_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub identity: Option<Identity>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Renewal {
+    Bootstrap,
+    Reauth,
+    Refresh,
+}
+
+/// Serializa bootstrap/re-bootstrap: un solo intento en vuelo; los demás esperan
+/// y reciben las credenciales nuevas.
+#[derive(Default)]
+struct AuthCoordinator {
+    lock: tokio::sync::Mutex<()>,
+    report: Mutex<AuthReport>,
+}
+
+impl AuthCoordinator {
+    fn set_phase(&self, phase: AuthPhase) {
+        self.report.lock().unwrap().phase = phase;
+    }
+
+    /// Una petición autenticada tuvo éxito: sale de `Failed`/`PendingBootstrap`.
+    fn mark_ok(&self) {
+        let mut r = self.report.lock().unwrap();
+        if r.phase != AuthPhase::Authenticated { r.phase = AuthPhase::Authenticated; r.last_error = None; }
+    }
+
+    fn record(&self, res: &BackendResult<AgentSecrets>, kind: Renewal) {
+        let mut r = self.report.lock().unwrap();
+        match res {
+            Ok(s) => {
+                r.phase = AuthPhase::Authenticated;
+                r.last_auth_ms = Some(now_ms());
+                r.expires_at = s.expires_at;
+                r.last_error = None;
+                match kind {
+                    Renewal::Bootstrap => {}
+                    Renewal::Reauth => r.reauth_count += 1,
+                    Renewal::Refresh => r.refresh_count += 1,
+                }
+            }
+            Err(BackendError::Config(reason)) => {
+                r.phase = AuthPhase::PendingBootstrap;
+                r.last_error = Some(reason.clone());
+            }
+            Err(e @ BackendError::EnrollmentRequired) => {
+                r.phase = AuthPhase::PendingEnrollment;
+                r.last_error = Some(e.to_string());
+            }
+            Err(e) => {
+                r.phase = AuthPhase::Failed;
+                r.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+struct BackendInner {
+    base: Option<String>,
+    http: ClientFactory,
+    paths: Paths,
+    agent_version: String,
+    timeout: Duration,
+    auth: AuthCoordinator,
+}
+
+/// Cliente del backend. Clonable; comparte la [`ClientFactory`] (TLS/proxy).
+/// - `API_BASE_URL`: raíz del backend (sin él, todas las llamadas devuelven `Config`)
+/// - `BACKEND_TIMEOUT_SECS`: timeout por petición (por defecto 15 s)
+#[derive(Clone)]
+pub struct BackendClient {
+    inner: Arc<BackendInner>,
+}
+
+impl BackendClient {
+    pub fn from_env(http: ClientFactory, paths: Paths, agent_version: &str) -> Self {
+        let base = std::env::var("API_BASE_URL")
+            .ok()
+            .map(|s| s.trim().trim_end_matches('/').to_string())
+            .filter(|s| !s.is_empty());
+        let timeout = std::env::var("BACKEND_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .filter(|s| *s > 0)
+            .unwrap_or(DEFAULT_TIMEOUT_SECS);
+        Self {
+            inner: Arc::new(BackendInner {
+                base,
+                http,
+                paths,
+                agent_version: agent_version.to_string(),
+                timeout: Duration::from_secs(timeout),
+                auth: AuthCoordinator::default(),
+            }),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool { self.inner.base.is_some() }
+
+    pub fn base_url(&self) -> Option<&str> { self.inner.base.as_deref() }
+
+    pub fn http(&self) -> &ClientFactory { &self.inner.http }
+
+    /// Aplica los `tlsPins` de una policy verificada, comprobándolos antes contra `API_BASE_URL`.
+    pub async fn apply_tls_pins(&self, pins: &[String]) {
+        let probe = self.inner.base.as_deref().map(|b| format!("{}/", b));
+        self.inner.http.set_policy_pins(pins, probe.as_deref()).await;
+    }
+
+    fn url(&self, path: &str) -> BackendResult<String> {
+        let base = self.inner.base.as_deref().ok_or_else(|| BackendError::Config("API_BASE_URL no configurado".into()))?;
+        Ok(format!("{}{}", base, path))
+    }
+
+    pub fn secrets(&self) -> BackendResult<Option<AgentSecrets>> {
+        AgentSecrets::load(&self.inner.paths).map_err(|e| BackendError::Secrets(e.to_string()))
+    }
+
+    pub fn auth_report(&self) -> AuthReport {
+        let mut r = self.inner.auth.report.lock().unwrap().clone();
+        if !self.is_configured() {
+            r.phase = AuthPhase::Unconfigured;
+        } else if r.phase == AuthPhase::Unknown {
+            match self.secrets() {
+                Ok(Some(s)) => { r.phase = AuthPhase::Authenticated; r.expires_at = s.expires_at; }
+                _ if self.bootstrap_request().is_ok() => r.phase = AuthPhase::PendingBootstrap,
+                _ => r.phase = AuthPhase::PendingEnrollment,
+            }
+        }
+        r.identity = self.identity();
+        r
+    }
+
+    /// Identidad enrolada (agent_state.json) o, en su defecto, ORG_ID/USER_EMAIL de env.
+    pub fn identity(&self) -> Option<Identity> {
+        if let Some(st) = AgentState::load(&self.inner.paths).ok().flatten() {
+            if let (Some(org_id), Some(user_email)) = (st.org_id, st.user_email) {
+                return Some(Identity { org_id, user_email, source: "enrollment" });
+            }
+        }
+        let org_id = non_empty_env("ORG_ID").ok()?;
+        let user_email = non_empty_env("USER_EMAIL").ok()?;
+        Some(Identity { org_id, user_email, source: "env" })
+    }
+
+    /// Bootstrap por email (legado): solo con ORG_ID/USER_EMAIL en env, sin
+    /// `ENROLLMENT_REQUIRED=1` y si el dispositivo no fue enrolado con código.
+    fn bootstrap_request(&self) -> BackendResult<BootstrapRequest> {
+        if std::env::var("ENROLLMENT_REQUIRED").ok().as_deref() == Some("1") {
+            return Err(BackendError::EnrollmentRequired);
+        }
+        if AgentState::load(&self.inner.paths).ok().flatten().is_some_and(|st| st.is_enrolled()) {
+            return Err(BackendError::EnrollmentRequired);
+        }
+        BootstrapRequest::from_env(&self.inner.agent_version).map_err(|_| BackendError::EnrollmentRequired)
+    }
+
+    /// POST /v1/agents/enroll: canjea un código de un solo uso emitido por un admin
+    /// por credenciales y guarda org/usuario en el estado del agente.
+    pub async fn enroll(&self, code: &str) -> BackendResult<Identity> {
+        let code = code.trim();
+        if code.is_empty() { return Err(BackendError::Config("código de enrolamiento vacío".into())); }
+        let auth = &self.inner.auth;
+        let _guard = auth.lock.lock().await;
+        let mut st = AgentState::load_or_init(&self.inner.paths, &self.inner.agent_version)
+            .map_err(|e| BackendError::Secrets(e.to_string()))?;
+        let req = EnrollRequest {
+            code: code.to_string(),
+            device_id: st.device_id.clone(),
+            mac_address: primary_mac().unwrap_or_default(),
+            hostname: sysinfo::System::host_name().unwrap_or_default(),
+            os: std::env::consts::OS.to_string(),
+            agent_version: self.inner.agent_version.clone(),
+        };
+        let url = self.url("/v1/agents/enroll")?;
+        let res: BackendResult<(AgentSecrets, Identity)> = async {
+            let v: BootstrapResponse = self.send(self.inner.http.client().post(url).json(&req)).await?.json().await?;
+            let (Some(org_id), Some(user_email)) = (v.org_id.clone(), v.user_email.clone()) else {
+                return Err(BackendError::Decode("enroll sin orgId/userEmail".into()));
+            };
+            if let Some(keys) = &v.policy_keys {
+                crate::policy_sig::save_provisioned(&self.inner.paths, keys).map_err(|e| BackendError::Secrets(e.to_string()))?;
+            }
+            let secrets = self.store_secrets(v, None)?;
+            st.set_enrollment(&self.inner.paths, &org_id, &user_email).map_err(|e| BackendError::Secrets(e.to_string()))?;
+            Ok((secrets, Identity { org_id, user_email, source: "enrollment" }))
+        }
+        .await;
+        match res {
+            Ok((secrets, identity)) => {
+                auth.record(&Ok(secrets), Renewal::Bootstrap);
+                info!(org=%identity.org_id, user=%identity.user_email, "enrolamiento ok");
+                Ok(identity)
+            }
+            Err(e) => {
+                warn!(error=%e, "enrolamiento falló");
+                Err(e)
+            }
+        }
+    }
+
+    /// Construye y guarda secrets desde una respuesta de bootstrap/refresh/enroll.
+    /// Campos ausentes conservan el valor de `prev`.
+    fn store_secrets(&self, v: BootstrapResponse, prev: Option<&AgentSecrets>) -> BackendResult<AgentSecrets> {
+        if v.agent_token.is_empty() || (v.server_salt.is_empty() && prev.is_none()) {
+            return Err(BackendError::Decode("respuesta sin agentToken/serverSalt".into()));
+        }
+        let secrets = AgentSecrets {
+            expires_at: v.expires_at_ms(),
+            agent_token: v.agent_token,
+            server_salt: if v.server_salt.is_empty() { prev.map(|p| p.server_salt.clone()).unwrap_or_default() } else { v.server_salt },
+            device_id: v.device_id.or_else(|| prev.and_then(|p| p.device_id.clone())),
+            refresh_token: v.refresh_token.or_else(|| prev.and_then(|p| p.refresh_token.clone())),
+        };
+        secrets.save(&self.inner.paths).map_err(|e| BackendError::Secrets(e.to_string()))?;
+        Ok(secrets)
+    }
+
+    /// POST /v1/agents/bootstrap con la identidad de env; guarda los secrets obtenidos.
+    async fn bootstrap(&self) -> BackendResult<AgentSecrets> {
+        let url = self.url("/v1/agents/bootstrap")?;
+        let req = self.bootstrap_request()?;
+        let resp = self.send(self.inner.http.client().post(url).json(&req)).await?;
+        let v: BootstrapResponse = resp.json().await?;
+        self.store_secrets(v, None)
+    }
+
+    /// POST /v1/agents/refresh con el `refresh_token`. Campos ausentes en la
+    /// respuesta (salt, refresh token, device) conservan el valor anterior.
+    async fn refresh(&self, cur: &AgentSecrets, refresh_token: &str) -> BackendResult<AgentSecrets> {
+        let req = RefreshRequest {
+            refresh_token: refresh_token.to_string(),
+            device_id: cur.device_id.clone(),
+            agent_version: self.inner.agent_version.clone(),
+        };
+        let url = self.url("/v1/agents/refresh")?;
+        let http = self.inner.http.client().post(url).header("Agent-Token", &cur.agent_token).json(&req);
+        let v: BootstrapResponse = self.send(http).await?.json().await?;
+        self.store_secrets(v, Some(cur))
+    }
+
+    /// Secrets existentes o bootstrap si aún no hay. Un token ya vencido se renueva antes de usarlo.
+    pub async fn ensure_bootstrapped(&self) -> BackendResult<AgentSecrets> {
+        match self.secrets()? {
+            Some(s) if s.expires_within(now_ms(), 0) => self.renew(Some(&s.agent_token), Renewal::Refresh).await,
+            Some(s) => Ok(s),
+            None => self.renew(None, Renewal::Bootstrap).await,
+        }
+    }
+
+    /// Renueva credenciales tras un 401 con `stale`. Si otro loop ya las renovó
+    /// mientras esperábamos, se devuelven esas sin volver a llamar al backend.
+    pub async fn reauthenticate(&self, stale: &AgentSecrets) -> BackendResult<AgentSecrets> {
+        self.renew(Some(&stale.agent_token), Renewal::Reauth).await
+    }
+
+    /// Renovación proactiva: si el token vence dentro de `margin`, lo renueva.
+    /// Devuelve `None` si no hacía falta (o el backend no informa expiración).
+    pub async fn refresh_if_expiring(&self, margin: Duration) -> BackendResult<Option<AgentSecrets>> {
+        match self.secrets()? {
+            Some(s) if s.expires_within(now_ms(), margin.as_millis() as u64) => {
+                self.renew(Some(&s.agent_token), Renewal::Refresh).await.map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn renew(&self, stale_token: Option<&str>, kind: Renewal) -> BackendResult<AgentSecrets> {
+        let auth = &self.inner.auth;
+        let _guard = auth.lock.lock().await;
+        let current = self.secrets()?;
+        if let Some(cur) = current.as_ref().filter(|c| c.supersedes(stale_token)) {
+            return Ok(cur.clone());
+        }
+        auth.set_phase(if kind == Renewal::Bootstrap { AuthPhase::PendingBootstrap } else { AuthPhase::Reauthenticating });
+        // Con refresh_token se intenta /refresh; si el backend lo rechaza, bootstrap completo.
+        let res = match current.as_ref().and_then(|c| c.refresh_token.as_deref().map(|t| (c, t))) {
+            Some((cur, token)) => match self.refresh(cur, token).await {
+                Err(e) if e.rejects_refresh() => {
+                    warn!(error=%e, "refresh rechazado; se intenta re-bootstrap");
+                    self.bootstrap().await
+                }
+                r => r,
+            },
+            None => self.bootstrap().await,
+        };
+        auth.record(&res, kind);
+        match &res {
+            Ok(_) if kind == Renewal::Bootstrap => info!("bootstrap ok: token guardado"),
+            Ok(_) => info!("renovación ok: token actualizado"),
+            Err(e) if kind != Renewal::Bootstrap => warn!(error=%e, "renovación de token falló"),
+            Err(_) => {}
+        }
+        res
+    }
+
+    /// Envía el heartbeat; devuelve los comandos que traiga la respuesta (sin verificar).
+    pub async fn heartbeat(&self, req: &HeartbeatRequest) -> BackendResult<Vec<RemoteCommand>> {
+        let body = serde_json::to_string(req).map_err(|e| BackendError::Decode(e.to_string()))?;
+        let url = self.url("/v1/agents/heartbeat")?;
+        if debug_payloads() { debug!(payload=%body, url=%url, "heartbeat payload"); }
+        let resp = self.send_signed(&url, body).await?;
+        Ok(read_commands(resp).await)
+    }
+
+    /// Envía eventos; si llevaban heartbeat adjunto, la respuesta puede traer comandos.
+    pub async fn ingest(&self, req: &IngestRequest) -> BackendResult<Vec<RemoteCommand>> {
+        let body = serde_json::to_string(req).map_err(|e| BackendError::Decode(e.to_string()))?;
+        let url = self.url("/v1/events:ingest")?;
+        if debug_payloads() { debug!(payload=%body, url=%url, count=%req.events.len(), "ingest payload"); }
+        let resp = self.send_signed(&url, body).await?;
+        Ok(if req.heartbeat.is_some() { read_commands(resp).await } else { Vec::new() })
+    }
+
+    /// POST /v1/agents/diagnostics (firmado), disparado por comando remoto.
+    pub async fn upload_diagnostics(&self, diag: &serde_json::Value) -> BackendResult<()> {
+        let url = self.url("/v1/agents/diagnostics")?;
+        self.send_signed(&url, diag.to_string()).await.map(|_| ())
+    }
+
+    /// Fuerza la renovación del token actual (refresh o re-bootstrap), aunque no haya vencido.
+    pub async fn rotate_credentials(&self) -> BackendResult<AgentSecrets> {
+        let cur = self.secrets()?.ok_or(BackendError::EnrollmentRequired)?;
+        self.renew(Some(&cur.agent_token), Renewal::Refresh).await
+    }
+
+    /// Olvida credenciales e identidad enrolada: el dispositivo vuelve a `pending_enrollment`
+    /// (o a bootstrap por env si está permitido).
+    pub async fn reset_enrollment(&self) -> BackendResult<()> {
+        let auth = &self.inner.auth;
+        let _guard = auth.lock.lock().await;
+        let f = self.inner.paths.secrets_file();
+        if f.exists() { std::fs::remove_file(&f).map_err(|e| BackendError::Secrets(e.to_string()))?; }
+        if let Some(mut st) = AgentState::load(&self.inner.paths).map_err(|e| BackendError::Secrets(e.to_string()))? {
+            st.clear_enrollment(&self.inner.paths).map_err(|e| BackendError::Secrets(e.to_string()))?;
+        }
+        *auth.report.lock().unwrap() = AuthReport::default();
+        info!("credenciales y enrolamiento descartados");
+        Ok(())
+    }
+
+    /// GET /v1/policy/{user_email}, con `If-None-Match` si hay ETag.
+    pub async fn fetch_policy(&self, etag: Option<&str>) -> BackendResult<PolicyFetch> {
+        self.fetch_policy_wait(etag, None).await
+    }
+
+    /// Long-poll: `?wait=N` pide al backend retener la respuesta hasta que cambie el ETag
+    /// o pasen N segundos (304). El timeout de la petición se extiende en consecuencia.
+    pub async fn fetch_policy_wait(&self, etag: Option<&str>, wait: Option<Duration>) -> BackendResult<PolicyFetch> {
+        let user = self.identity().ok_or(BackendError::EnrollmentRequired)?.user_email;
+        let mut url = self.url(&format!("/v1/policy/{}", urlencoding::encode(&user)))?;
+        if let Some(w) = wait { url = format!("{}?wait={}", url, w.as_secs()); }
+        let timeout = self.inner.timeout + wait.unwrap_or_default();
+        let resp = self
+            .send_authed_timeout(timeout, |client, secrets| {
+                let mut req = client.get(&url).header("Agent-Token", &secrets.agent_token);
+                if let Some(tag) = etag { req = req.header("If-None-Match", tag); }
+                req
+            })
+            .await?;
+        if resp.status().as_u16() == 304 { return Ok(PolicyFetch::NotModified); }
+        let header = |name: &str| resp.headers().get(name).and_then(|v| v.to_str().ok()).map(|s| s.to_string());
+        let etag = header("etag");
+        let published_hdr = header("x-policy-published-at").and_then(|s| s.trim().parse::<u64>().ok());
+        let sig_hdr = header("x-policy-signature").and_then(|s| PolicySignature::from_header(&s));
+        let v: serde_json::Value = resp.json().await?;
+        let published_at = published_hdr.or_else(|| v.get("publishedAt").and_then(|p| p.as_u64()));
+        let signature = sig_hdr.or_else(|| v.get("signature").and_then(|s| serde_json::from_value(s.clone()).ok()));
+        // soporta respuesta con campo policy o directamente la policy rica
+        let policy = v.get("policy").cloned().unwrap_or(v);
+        Ok(PolicyFetch::Updated { policy, etag, published_at, signature })
+    }
+
+    /// POST con `Agent-Token` + `X-Body-HMAC` (firma del cuerpo con `server_salt`).
+    async fn send_signed(&self, url: &str, body: String) -> BackendResult<reqwest::Response> {
+        self.send_authed(|client, secrets| {
+            client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header("Agent-Token", &secrets.agent_token)
+                .header("X-Body-HMAC", hmac_hex(&secrets.server_salt, body.as_bytes()))
+                .body(body.clone())
+        })
+        .await
+    }
+
+    /// Única política de re-autenticación: ante 401 se renuevan credenciales y se reintenta una vez.
+    async fn send_authed<F>(&self, build: F) -> BackendResult<reqwest::Response>
+    where
+        F: Fn(&reqwest::Client, &AgentSecrets) -> reqwest::RequestBuilder,
+    {
+        self.send_authed_timeout(self.inner.timeout, build).await
+    }
+
+    async fn send_authed_timeout<F>(&self, timeout: Duration, build: F) -> BackendResult<reqwest::Response>
+    where
+        F: Fn(&reqwest::Client, &AgentSecrets) -> reqwest::RequestBuilder,
+    {
+        let secrets = self.ensure_bootstrapped().await?;
+        let client = self.inner.http.client();
+        match self.send_with(build(&client, &secrets), timeout).await {
+            Err(BackendError::Unauthorized) => {
+                let fresh = self.reauthenticate(&secrets).await?;
+                let res = self.send_with(build(&client, &fresh), timeout).await;
+                if let Err(BackendError::Unauthorized) = res {
+                    let mut r = self.inner.auth.report.lock().unwrap();
+                    r.phase = AuthPhase::Failed;
+                    r.last_error = Some("401 tras re-autenticación".into());
+                }
+                res
+            }
+            Ok(resp) => {
+                self.inner.auth.mark_ok();
+                Ok(resp)
+            }
+            r => r,
+        }
+    }
+
+    async fn send(&self, req: reqwest::RequestBuilder) -> BackendResult<reqwest::Response> {
+        self.send_with(req, self.inner.timeout).await
+    }
+
+    async fn send_with(&self, req: reqwest::RequestBuilder, timeout: Duration) -> BackendResult<reqwest::Response> {
+        let resp = req.timeout(timeout).send().await?;
+        classify(resp)
+    }
+}
+
+/// Comandos en el cuerpo `{"commands": [...]}`; cuerpo vacío o sin ese campo = ninguno.
+async fn read_commands(resp: reqwest::Response) -> Vec<RemoteCommand> {
+    let text = resp.text().await.unwrap_or_default();
+    if text.trim().is_empty() { return Vec::new(); }
+    match serde_json::from_str::<HeartbeatResponse>(&text) {
+        Ok(r) => r.commands,
+        Err(e) => {
+            debug!(error=%e, "respuesta sin comandos interpretables");
+            Vec::new()
+        }
+    }
+}
+
+fn classify(resp: reqwest::Response) -> BackendResult<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() || status.as_u16() == 304 { return Ok(resp); }
+    Err(match status.as_u16() {
+        401 => BackendError::Unauthorized,
+        403 => BackendError::Forbidden,
+        429 => {
+            let retry_after = resp
+                .headers()
+                .get("retry-after")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|s| s.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            BackendError::RateLimited { retry_after }
+        }
+        s => BackendError::Server { status: s },
+    })
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+fn non_empty_env(key: &str) -> BackendResult<String> {
+    match std::env::var(key) {
+        Ok(v) if !v.trim().is_empty() => Ok(v),
+        _ => Err(BackendError::Config(format!("{} no configurado", key))),
+    }
+}
+
+fn debug_payloads() -> bool {
+    std::env::var("RIPOR_DEBUG_INGEST").ok().as_deref() == Some("1")
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn hmac_hex(key: &str, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("hmac key");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn primary_mac() -> Option<String> {
+    match mac_address::get_mac_address() {
+        Ok(Some(ma)) => Some(format!("{}", ma)),
+        _ => None,
+    }
+}
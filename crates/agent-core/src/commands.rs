@@ -0,0 +1,121 @@
+// Admisión de comandos servidor → agente: firma Ed25519 con las claves de firma de policy,
+// destinatario (dispositivo y org), vigencia, allowlist local y una sola ejecución por `id`.
+// La ejecución vive en el daemon.
+use crate::backend::{CommandAck, RemoteCommand};
+use crate::paths::Paths;
+use crate::policy_sig::TrustStore;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Acciones que el agente sabe ejecutar.
+pub const KNOWN_ACTIONS: &[&str] = &["refresh_policy", "flush_queue", "upload_diagnostics", "pause", "rotate_keys", "reenroll"];
+/// Sin `REMOTE_COMMANDS`: solo acciones que no borran credenciales, no detienen la captura ni sacan datos.
+pub const DEFAULT_ALLOWED: &[&str] = &["refresh_policy", "flush_queue"];
+/// Comandos recordados para idempotencia (persistidos en `commands.json`)
+pub const LEDGER_CAP: usize = 500;
+/// Sin `expiresAt`, un comando emitido hace más de esto se rechaza
+pub const MAX_AGE_MS: u64 = 24 * 3_600_000;
+
+/// `REMOTE_COMMANDS`: lista separada por comas; sin definir, [`DEFAULT_ALLOWED`]; `none` desactiva el canal.
+pub fn allowed_from(raw: Option<&str>) -> Vec<&'static str> {
+    let Some(raw) = raw else { return DEFAULT_ALLOWED.to_vec() };
+    let mut out = Vec::new();
+    for name in raw.split(',').map(str::trim).filter(|s| !s.is_empty() && *s != "none") {
+        match KNOWN_ACTIONS.iter().find(|a| **a == name) {
+            Some(a) if !out.contains(a) => out.push(*a),
+            Some(_) => {}
+            None => tracing::warn!(action=%name, "REMOTE_COMMANDS: acción desconocida ignorada"),
+        }
+    }
+    out
+}
+
+pub fn allowed_from_env() -> Vec<&'static str> {
+    allowed_from(std::env::var("REMOTE_COMMANDS").ok().as_deref())
+}
+
+/// Este agente: el `device_id` que reporta el heartbeat (el de las credenciales, `AgentSecrets`;
+/// el de `agent_state.json` solo si aún no hay) y la org de la identidad efectiva, si hay.
+#[derive(Debug, Clone, Copy)]
+pub struct Recipient<'a> {
+    pub device_id: &'a str,
+    pub org_id: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Ya procesado: se re-envía el mismo acuse (el anterior pudo perderse)
+    Replay(CommandAck),
+    /// No se ejecuta. `remember: false` para firmas inválidas y comandos de otro destinatario:
+    /// no deben bloquear al legítimo con el mismo `id`.
+    Reject { detail: &'static str, remember: bool },
+    Run,
+}
+
+pub struct CommandGate {
+    pub allowed: Vec<&'static str>,
+    ledger: Mutex<VecDeque<CommandAck>>,
+    file: Option<PathBuf>,
+}
+
+impl CommandGate {
+    /// Registro persistido en `commands.json`.
+    pub fn load(paths: &Paths, allowed: Vec<&'static str>) -> Self {
+        let file = paths.commands_file();
+        let ledger = std::fs::read_to_string(&file)
+            .ok()
+            .and_then(|txt| serde_json::from_str::<VecDeque<CommandAck>>(&txt).ok())
+            .unwrap_or_default();
+        Self { allowed, ledger: Mutex::new(ledger), file: Some(file) }
+    }
+
+    /// Sin persistencia.
+    pub fn in_memory(allowed: Vec<&'static str>) -> Self {
+        Self { allowed, ledger: Mutex::new(VecDeque::new()), file: None }
+    }
+
+    pub fn check(&self, cmd: &RemoteCommand, to: Recipient, trust: &TrustStore, now_ms: u64) -> Verdict {
+        if let Some(prev) = self.ledger.lock().unwrap().iter().find(|a| a.id == cmd.id) {
+            return Verdict::Replay(prev.clone());
+        }
+        if !cmd.verify(trust) {
+            return Verdict::Reject { detail: "bad_signature", remember: false };
+        }
+        // la firma cubre deviceId/orgId: un comando capturado en otro equipo no vale aquí
+        if cmd.device_id != to.device_id {
+            return Verdict::Reject { detail: "wrong_device", remember: false };
+        }
+        if cmd.org_id.is_some() && cmd.org_id.as_deref() != to.org_id {
+            return Verdict::Reject { detail: "wrong_org", remember: false };
+        }
+        let expired = match cmd.expires_at {
+            Some(exp) => exp < now_ms,
+            None => now_ms.saturating_sub(cmd.issued_at) > MAX_AGE_MS,
+        };
+        if expired {
+            return Verdict::Reject { detail: "expired", remember: true };
+        }
+        if !self.allowed.contains(&cmd.action.as_str()) {
+            let detail = if KNOWN_ACTIONS.contains(&cmd.action.as_str()) { "not_allowed" } else { "unknown_action" };
+            return Verdict::Reject { detail, remember: true };
+        }
+        Verdict::Run
+    }
+
+    /// Recuerda el acuse para que el mismo `id` no se vuelva a ejecutar.
+    pub fn record(&self, ack: &CommandAck) {
+        let mut l = self.ledger.lock().unwrap();
+        l.push_back(ack.clone());
+        while l.len() > LEDGER_CAP { l.pop_front(); }
+        let Some(file) = &self.file else { return };
+        if let Err(e) = std::fs::write(file, serde_json::to_vec(&*l).unwrap_or_default()) {
+            tracing::warn!(error=%e, "no se pudo guardar el registro de comandos");
+        }
+    }
+
+    /// Últimos `n` acuses, el más reciente primero.
+    pub fn recent(&self, n: usize) -> Vec<CommandAck> {
+        self.ledger.lock().unwrap().iter().rev().take(n).cloned().collect()
+    }
+}
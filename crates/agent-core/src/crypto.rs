@@ -9,23 +9,28 @@ const NONCE_LEN: usize = 12; // 96-bit nonce
 const MAGIC: &[u8] = b"EV1"; // formato cifrado versión 1
 
 pub fn load_or_create_key(paths: &Paths) -> Result<[u8; KEY_LEN]> {
+    if let Some(k) = load_key(paths)? { return Ok(k); }
     let key_path = paths.key_file();
-    if key_path.exists() {
-        let data = fs::read(&key_path)?;
-        if data.len() != KEY_LEN {
-            return Err(anyhow!("tamaño de clave inválido"));
-        }
-        let mut k = [0u8; KEY_LEN];
-        k.copy_from_slice(&data);
-        return Ok(k);
-    }
     let mut k = [0u8; KEY_LEN];
     rand::thread_rng().fill_bytes(&mut k);
     ensure_parent(&key_path)?;
-    fs::write(&key_path, &k)?;
+    fs::write(&key_path, k)?;
     Ok(k)
 }
 
+/// Clave existente, sin crearla (lectores fuera del daemon).
+pub fn load_key(paths: &Paths) -> Result<Option<[u8; KEY_LEN]>> {
+    let key_path = paths.key_file();
+    if !key_path.exists() { return Ok(None); }
+    let data = fs::read(&key_path)?;
+    if data.len() != KEY_LEN {
+        return Err(anyhow!("tamaño de clave inválido"));
+    }
+    let mut k = [0u8; KEY_LEN];
+    k.copy_from_slice(&data);
+    Ok(Some(k))
+}
+
 pub fn encrypt_compress(key: &[u8; KEY_LEN], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
     let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("clave AES inválida"))?;
     let mut nonce_bytes = [0u8; NONCE_LEN];
@@ -19,8 +19,8 @@ impl FocusStore {
     pub fn open(paths: &crate::paths::Paths) -> Result<Self> {
         // Reutilizamos queue.sqlite para simplificar despliegue
         let conn = Connection::open(paths.queue_db())?;
-        conn.pragma_update(None, "journal_mode", &"WAL")?;
-        conn.pragma_update(None, "synchronous", &"NORMAL")?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
         conn.execute_batch(
             "CREATE TABLE IF NOT EXISTS focus_blocks (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -36,6 +36,11 @@ impl FocusStore {
         Ok(Self { conn })
     }
 
+    /// Solo lectura, para la CLI sin daemon (ver `queue::open_read_only`).
+    pub fn open_read_only(paths: &crate::paths::Paths) -> Result<Self> {
+        Ok(Self { conn: crate::queue::open_read_only(paths)? })
+    }
+
     pub fn insert_block(&self, b: &FocusBlockRow) -> Result<i64> {
         self.conn.execute(
             "INSERT INTO focus_blocks(start_ms,end_ms,dur_ms,app_name,window_title) VALUES (?1,?2,?3,?4,?5)",
@@ -0,0 +1,82 @@
+// Agenda de heartbeats del daemon: intervalo de la policy ± 10% de jitter y resultados recientes.
+// La comparten el loop de heartbeat y el sender de eventos; el tiempo se pasa explícito.
+use rand::Rng;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+pub const DEFAULT_INTERVAL_SECS: u64 = 60;
+/// Ventana para la tasa de éxito de heartbeats publicada en `/state`
+pub const WINDOW_MS: u64 = 3_600_000;
+
+/// Intervalo efectivo: `heartbeatIntervalSecs` de la policy acotado a 15 s..1 h, o 60 s.
+pub fn interval_secs(policy: Option<u32>) -> u64 {
+    policy.map(|s| (s as u64).clamp(15, 3600)).unwrap_or(DEFAULT_INTERVAL_SECS)
+}
+
+/// El heartbeat sale siempre a su hora, haya o no captura; si coincide con un envío de eventos,
+/// el sender lo "reclama" y lo adjunta al ingest.
+#[derive(Default)]
+pub struct HeartbeatTracker {
+    next_due_ms: AtomicU64,
+    interval_secs: AtomicU64,
+    window: Mutex<VecDeque<(u64, bool)>>,
+}
+
+#[derive(Serialize)]
+pub struct HeartbeatReport {
+    pub interval_secs: u64,
+    pub next_due_ms: u64,
+    pub window_secs: u64,
+    pub attempts: usize,
+    pub successes: usize,
+    pub success_rate: Option<f64>,
+}
+
+impl HeartbeatTracker {
+    pub fn next_due_ms(&self) -> u64 { self.next_due_ms.load(Ordering::Relaxed) }
+
+    /// Programa el siguiente a `interval_secs` ± 10% de `now`.
+    pub fn schedule_next(&self, interval_secs: u64, now: u64) -> u64 {
+        let interval_ms = interval_secs * 1000;
+        let jitter = rand::thread_rng().gen_range(0..=interval_ms / 5);
+        let next = now + interval_ms - interval_ms / 10 + jitter;
+        self.interval_secs.store(interval_secs, Ordering::Relaxed);
+        self.next_due_ms.store(next, Ordering::Relaxed);
+        next
+    }
+
+    /// Si el heartbeat está vencido, lo reprograma y devuelve `true` (quien llama debe enviarlo).
+    pub fn try_claim(&self, interval_secs: u64, now: u64) -> bool {
+        let due = self.next_due_ms.load(Ordering::Relaxed);
+        if now < due { return false; }
+        // reserva provisional para que el otro loop no lo envíe en paralelo
+        if self.next_due_ms.compare_exchange(due, u64::MAX, Ordering::AcqRel, Ordering::Relaxed).is_err() { return false; }
+        self.schedule_next(interval_secs, now);
+        true
+    }
+
+    /// Un heartbeat reclamado no se pudo enviar: queda vencido otra vez.
+    pub fn release(&self, now: u64) { self.next_due_ms.store(now, Ordering::Relaxed); }
+
+    pub fn record(&self, ok: bool, now: u64) {
+        let mut w = self.window.lock().unwrap();
+        w.push_back((now, ok));
+        while w.front().is_some_and(|(ts, _)| now.saturating_sub(*ts) > WINDOW_MS) { w.pop_front(); }
+    }
+
+    pub fn report(&self, now: u64) -> HeartbeatReport {
+        let w = self.window.lock().unwrap();
+        let recent = w.iter().filter(|(ts, _)| now.saturating_sub(*ts) <= WINDOW_MS);
+        let (attempts, successes) = recent.fold((0, 0), |(a, s), (_, ok)| (a + 1, s + *ok as usize));
+        HeartbeatReport {
+            interval_secs: self.interval_secs.load(Ordering::Relaxed),
+            next_due_ms: self.next_due_ms.load(Ordering::Relaxed),
+            window_secs: WINDOW_MS / 1000,
+            attempts,
+            successes,
+            success_rate: (attempts > 0).then(|| successes as f64 / attempts as f64),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+// Fábrica compartida de clientes HTTP hacia el backend.
+// Todos los loops (bootstrap, heartbeat, ingest, policy) y la CLI construyen
+// sus clientes aquí para compartir raíces TLS, bundle corporativo, pinning SPKI y proxy.
+use crate::proxy::{ProxyRoute, ProxySettings};
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// Marca incluida en el error TLS cuando ningún certificado del camino validado coincide con los pins.
+pub const PIN_MISMATCH: &str = "spki pin mismatch";
+
+/// Configuración local (env/.env). Si es inválida, [`ClientFactory::from_env`] falla: el agente
+/// no arranca con un pinning o un bundle que no puede aplicar.
+/// - `TLS_SPKI_PINS`: lista separada por comas de `sha256/<base64>` (o solo `<base64>`)
+/// - `TLS_CA_BUNDLE`: ruta a un PEM con CAs adicionales (proxies con inspección TLS)
+/// - proxy: ver [`ProxySettings`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpSettings {
+    pub spki_pins: Vec<String>,
+    pub ca_bundle: Option<PathBuf>,
+    pub proxy: ProxySettings,
+}
+
+impl HttpSettings {
+    pub fn from_env() -> Result<Self> {
+        let spki_pins = std::env::var("TLS_SPKI_PINS")
+            .ok()
+            .map(|s| s.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect())
+            .unwrap_or_default();
+        let ca_bundle = std::env::var("TLS_CA_BUNDLE")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(PathBuf::from);
+        Ok(Self { spki_pins, ca_bundle, proxy: ProxySettings::from_env()? })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PinMismatch {
+    pub ts_ms: u64,
+    pub host: String,
+}
+
+/// Estado TLS publicado en `/state`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TlsReport {
+    pub pinning: bool,
+    pub local_pins: usize,
+    /// Pins de política aplicados (los rechazados o pendientes no cuentan)
+    pub policy_pins: usize,
+    /// Pins de política a la espera de poder comprobarlos contra el backend
+    pub policy_pins_pending: bool,
+    pub ca_bundle: Option<String>,
+    /// Último rechazo de pins de política; el cliente anterior sigue en uso
+    pub config_error: Option<String>,
+    /// Conexiones en uso rechazadas por pinning (posible MITM)
+    pub pin_mismatches: u64,
+    pub last_pin_mismatch: Option<PinMismatch>,
+    /// Conexiones de prueba de pins de política que no coincidieron (pins rechazados, no un MITM)
+    pub pin_probe_rejections: u64,
+}
+
+#[derive(Default)]
+struct TlsStatus {
+    mismatches: u64,
+    last: Option<PinMismatch>,
+    probe_rejections: u64,
+    config_error: Option<String>,
+}
+
+#[derive(Default)]
+struct PolicyPins {
+    applied: Vec<String>,
+    /// Último juego rechazado: no se vuelve a probar hasta que la política cambie
+    rejected: Option<Vec<String>>,
+    pending: bool,
+}
+
+struct FactoryInner {
+    local: HttpSettings,
+    policy_pins: Mutex<PolicyPins>,
+    /// Serializa las comprobaciones de pins nuevos
+    apply: tokio::sync::Mutex<()>,
+    client: RwLock<reqwest::Client>,
+    status: Arc<Mutex<TlsStatus>>,
+}
+
+/// Fábrica de clientes: reconstruye el cliente cuando cambian los pins de la política.
+///
+/// Pins efectivos: unión de `TLS_SPKI_PINS` y `tlsPins` de la política (ya verificada). Basta con
+/// que un certificado del camino validado coincida con cualquiera de ellos, así la política puede
+/// añadir la clave siguiente antes de rotar sin redeploy. Sin pins, solo la validación WebPKI.
+#[derive(Clone)]
+pub struct ClientFactory {
+    inner: Arc<FactoryInner>,
+}
+
+/// Tiempo máximo de la conexión de prueba con pins nuevos
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl ClientFactory {
+    pub fn from_env() -> Result<Self> {
+        Self::new(HttpSettings::from_env()?)
+    }
+
+    pub fn new(local: HttpSettings) -> Result<Self> {
+        let status = Arc::new(Mutex::new(TlsStatus::default()));
+        let client = build_client(&local, &[], status.clone(), Arc::new(AtomicBool::new(false)))?;
+        Ok(Self {
+            inner: Arc::new(FactoryInner {
+                local,
+                policy_pins: Mutex::new(PolicyPins::default()),
+                apply: tokio::sync::Mutex::new(()),
+                client: RwLock::new(client),
+                status,
+            }),
+        })
+    }
+
+    /// Cliente async compartido (clonar es barato: internamente es un Arc).
+    pub fn client(&self) -> reqwest::Client {
+        self.inner.client.read().unwrap().clone()
+    }
+
+    /// Ruta de proxy efectiva hacia `target` (para `/state`).
+    pub fn proxy_route(&self, target: &str) -> ProxyRoute {
+        self.inner.local.proxy.route_for(target)
+    }
+
+    /// Evalúa el PAC (si lo hay) para `target` antes de la primera conexión. Bloquea: desde el
+    /// runtime, vía `spawn_blocking`.
+    pub fn prime_proxy(&self, target: &str) {
+        self.inner.local.proxy.prime(target)
+    }
+
+    /// Aplica los pins de la política. Antes de reemplazar el cliente se conecta a `probe_url`
+    /// (sin credenciales) con los pins nuevos: si la cadena del backend no coincide, se rechazan y
+    /// se conserva el cliente actual, para que un pin erróneo no corte el canal por el que llega
+    /// la política que lo corrige. Si el backend no responde, quedan pendientes y se reintentan en
+    /// la siguiente llamada. Sin `probe_url` (backend sin configurar) solo se valida el formato.
+    pub async fn set_policy_pins(&self, pins: &[String], probe_url: Option<&str>) {
+        let _guard = self.inner.apply.lock().await;
+        {
+            let cur = self.inner.policy_pins.lock().unwrap();
+            if cur.applied.as_slice() == pins || cur.rejected.as_deref() == Some(pins) { return; }
+        }
+        // hasta aceptarlo, los fallos de pinning del candidato cuentan como rechazos de prueba
+        let probing = Arc::new(AtomicBool::new(true));
+        let candidate = validate_pins(pins).and_then(|_| build_client(&self.inner.local, pins, self.inner.status.clone(), probing.clone()));
+        let candidate = match candidate {
+            Ok(c) => c,
+            Err(e) => return self.reject_policy_pins(pins, e.to_string()),
+        };
+        if let Some(url) = probe_url.filter(|_| !pins.is_empty()) {
+            match candidate.get(url).timeout(PROBE_TIMEOUT).send().await {
+                Ok(_) => {}
+                Err(e) if is_pin_mismatch(&e) => {
+                    return self.reject_policy_pins(pins, format!("ningún pin coincide con la cadena de {}", url));
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "no se pudieron comprobar los pins de política; se reintentará");
+                    self.inner.policy_pins.lock().unwrap().pending = true;
+                    return;
+                }
+            }
+        }
+        probing.store(false, Ordering::Relaxed);
+        *self.inner.policy_pins.lock().unwrap() = PolicyPins { applied: pins.to_vec(), rejected: None, pending: false };
+        *self.inner.client.write().unwrap() = candidate;
+        self.inner.status.lock().unwrap().config_error = None;
+        tracing::info!(policy_pins = pins.len(), "cliente HTTP reconstruido con pins de política");
+    }
+
+    fn reject_policy_pins(&self, pins: &[String], reason: String) {
+        tracing::error!(%reason, "pins de política rechazados; se conserva el cliente anterior");
+        let mut cur = self.inner.policy_pins.lock().unwrap();
+        cur.rejected = Some(pins.to_vec());
+        cur.pending = false;
+        self.inner.status.lock().unwrap().config_error = Some(format!("tlsPins de política rechazados: {}", reason));
+    }
+
+    pub fn report(&self) -> TlsReport {
+        let (policy_pins, policy_pins_pending) = {
+            let cur = self.inner.policy_pins.lock().unwrap();
+            (cur.applied.len(), cur.pending)
+        };
+        let st = self.inner.status.lock().unwrap();
+        TlsReport {
+            pinning: !self.inner.local.spki_pins.is_empty() || policy_pins > 0,
+            local_pins: self.inner.local.spki_pins.len(),
+            policy_pins,
+            policy_pins_pending,
+            ca_bundle: self.inner.local.ca_bundle.as_ref().map(|p| p.display().to_string()),
+            config_error: st.config_error.clone(),
+            pin_mismatches: st.mismatches,
+            last_pin_mismatch: st.last.clone(),
+            pin_probe_rejections: st.probe_rejections,
+        }
+    }
+}
+
+/// true si el error (o alguna de sus causas) proviene de un fallo de pinning.
+pub fn is_pin_mismatch(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut cur: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(e) = cur {
+        if e.to_string().contains(PIN_MISMATCH) { return true; }
+        cur = e.source();
+    }
+    false
+}
+
+fn build_client(local: &HttpSettings, policy_pins: &[String], status: Arc<Mutex<TlsStatus>>, probing: Arc<AtomicBool>) -> Result<reqwest::Client> {
+    let tls = tls_config(local, policy_pins, status, probing)?;
+    let builder = reqwest::Client::builder().use_preconfigured_tls(tls);
+    Ok(local.proxy.apply(builder)?.build()?)
+}
+
+fn tls_config(local: &HttpSettings, policy_pins: &[String], status: Arc<Mutex<TlsStatus>>, probing: Arc<AtomicBool>) -> Result<ClientConfig> {
+    let mut roots = default_roots();
+    if let Some(path) = &local.ca_bundle {
+        let pem = std::fs::read(path).map_err(|e| anyhow!("no se pudo leer TLS_CA_BUNDLE {}: {}", path.display(), e))?;
+        let certs = rustls_pemfile::certs(&mut &pem[..]).map_err(|e| anyhow!("PEM inválido en {}: {}", path.display(), e))?;
+        if certs.is_empty() {
+            return Err(anyhow!("TLS_CA_BUNDLE {} no contiene certificados", path.display()));
+        }
+        for der in certs {
+            roots.add(&Certificate(der)).map_err(|e| anyhow!("CA inválida en {}: {}", path.display(), e))?;
+        }
+    }
+    let mut pins = Vec::new();
+    for p in local.spki_pins.iter().chain(policy_pins.iter()) {
+        pins.push(parse_pin(p)?);
+    }
+    Ok(tls_config_with(roots, pins, status, probing))
+}
+
+fn tls_config_with(roots: RootCertStore, pins: Vec<[u8; 32]>, status: Arc<Mutex<TlsStatus>>, probing: Arc<AtomicBool>) -> ClientConfig {
+    let verifier = PinnedVerifier { inner: WebPkiVerifier::new(roots, None), pins, status, probing };
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth()
+}
+
+fn default_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+    }));
+    roots
+}
+
+/// Comprueba el formato de todos los pins (`sha256/<base64>` de 32 bytes).
+pub fn validate_pins(pins: &[String]) -> Result<()> {
+    pins.iter().try_for_each(|p| parse_pin(p).map(|_| ()))
+}
+
+pub fn parse_pin(p: &str) -> Result<[u8; 32]> {
+    let b64 = p.strip_prefix("sha256/").unwrap_or(p);
+    let raw = base64::engine::general_purpose::STANDARD
+        .decode(b64)
+        .map_err(|_| anyhow!("pin SPKI inválido (base64): {}", p))?;
+    raw.try_into().map_err(|_| anyhow!("pin SPKI inválido (se esperan 32 bytes SHA-256): {}", p))
+}
+
+struct PinnedVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<[u8; 32]>,
+    status: Arc<Mutex<TlsStatus>>,
+    /// Cliente candidato en prueba: sus fallos no son de una conexión en uso
+    probing: Arc<AtomicBool>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // Primero la validación normal de cadena; el pinning es adicional, nunca sustituto.
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)?;
+        if self.pins.is_empty() { return Ok(verified); }
+        if chain_matches_pins(end_entity, intermediates, server_name, &self.pins, now) { return Ok(verified); }
+        let host = match server_name {
+            ServerName::DnsName(d) => d.as_ref().to_string(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => String::new(),
+        };
+        let mut st = self.status.lock().unwrap();
+        if self.probing.load(Ordering::Relaxed) {
+            st.probe_rejections += 1;
+        } else {
+            tracing::warn!(%host, "pin SPKI no coincide con la cadena del servidor");
+            st.mismatches += 1;
+            st.last = Some(PinMismatch { ts_ms: now_ms(), host });
+        }
+        Err(rustls::Error::General(PIN_MISMATCH.into()))
+    }
+}
+
+/// true si algún certificado del camino validado tiene un SPKI cuyo SHA-256 está en `pins`: la hoja,
+/// o un certificado enviado por el servidor del que la hoja desciende con firmas válidas. Un
+/// certificado añadido a la cadena que no la firma no cuenta; si no, bastaría una hoja de confianza
+/// (un proxy con inspección TLS) con el certificado real del backend anexado. Un certificado que no
+/// se puede interpretar no coincide con nada.
+pub fn chain_matches_pins(end_entity: &Certificate, intermediates: &[Certificate], server_name: &ServerName, pins: &[[u8; 32]], now: SystemTime) -> bool {
+    let pinned = |c: &Certificate| spki_sha256(&c.0).is_some_and(|h| pins.contains(&h));
+    if pinned(end_entity) { return true; }
+    // Con el certificado fijado como única raíz, la validación solo pasa si la hoja desciende de él.
+    intermediates.iter().filter(|c| pinned(c)).any(|anchor| {
+        let mut roots = RootCertStore::empty();
+        roots.add(anchor).is_ok()
+            && WebPkiVerifier::new(roots, None)
+                .verify_server_cert(end_entity, intermediates, server_name, &mut std::iter::empty(), &[], now)
+                .is_ok()
+    })
+}
+
+/// SHA-256 del SubjectPublicKeyInfo de un certificado X.509 en DER (lo que fija un pin).
+pub fn spki_sha256(cert: &[u8]) -> Option<[u8; 32]> {
+    spki_der(cert).map(|spki| Sha256::digest(spki).into())
+}
+
+// Extrae el SubjectPublicKeyInfo (TLV completo) de un certificado X.509 en DER.
+fn spki_der(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert_body, _) = der_next(cert)?;
+    let (_, tbs, _) = der_next(cert_body)?;
+    let mut rest = tbs;
+    // version [0] EXPLICIT es opcional
+    if *rest.first()? == 0xA0 { rest = der_next(rest)?.2; }
+    // serialNumber, signature, issuer, validity, subject
+    for _ in 0..5 { rest = der_next(rest)?.2; }
+    let (_, _, after) = der_next(rest)?;
+    Some(&rest[..rest.len() - after.len()])
+}
+
+// Devuelve (tag, contenido, resto) del siguiente TLV.
+fn der_next(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *buf.first()?;
+    let first = *buf.get(1)? as usize;
+    let (len, hdr) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        if n == 0 || n > 4 { return None; }
+        let mut len = 0usize;
+        for i in 0..n { len = (len << 8) | *buf.get(2 + i)? as usize; }
+        (len, 2 + n)
+    };
+    let end = hdr.checked_add(len)?;
+    if end > buf.len() { return None; }
+    Some((tag, &buf[hdr..end], &buf[end..]))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,116 @@
+// Instancia única del daemon: lock exclusivo del SO sobre `agent.lock` en el data dir. Lo libera
+// el propio SO al terminar el proceso, así que un crash no deja un lock huérfano. Los datos de quien
+// lo tiene van aparte en `agent_instance.json` (en Windows el archivo bloqueado no se puede leer).
+// `flock` en Unix y `LockFileEx` en Windows: `File::try_lock` de std pide Rust 1.89.
+use crate::paths::{ensure_parent, Paths};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::path::PathBuf;
+
+/// Código de salida del daemon cuando otra instancia tiene el lock (`EX_TEMPFAIL`). Los servicios
+/// lo marcan como no relanzable: relanzarlo solo volvería a chocar con el lock.
+pub const EXIT_ALREADY_RUNNING: i32 = 75;
+
+/// Con este argumento el daemon sale con 0 en vez de [`EXIT_ALREADY_RUNNING`], para gestores que
+/// solo distinguen éxito de fallo (launchd con `SuccessfulExit`, Programador de tareas).
+pub const EXIT_OK_IF_RUNNING_ARG: &str = "--exit-ok-if-running";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InstanceInfo {
+    pub pid: u32,
+    pub started_at_ms: u64,
+    pub version: String,
+    pub panel_addr: String,
+    /// Requerido por `POST /shutdown`: solo quien puede leer el data dir apaga la instancia.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub shutdown_token: String,
+}
+
+impl std::fmt::Display for InstanceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pid {}, v{}, panel {}", self.pid, self.version, self.panel_addr)
+    }
+}
+
+/// Lock tomado; se mantiene mientras viva el valor.
+pub struct InstanceLock {
+    _file: File,
+    info_file: PathBuf,
+    pub info: InstanceInfo,
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // antes de soltar el lock (el archivo se cierra después): el siguiente escribe el suyo
+        let _ = fs::remove_file(&self.info_file);
+    }
+}
+
+pub enum Acquire {
+    Acquired(InstanceLock),
+    /// Otro proceso tiene el lock; `None` si aún no escribió (o no se pudo leer) su `agent_instance.json`.
+    Busy(Option<InstanceInfo>),
+}
+
+pub fn try_acquire(paths: &Paths, info: InstanceInfo) -> Result<Acquire> {
+    let file = open_lock(paths)?;
+    if !try_lock_exclusive(&file)? {
+        return Ok(Acquire::Busy(read_info(paths)));
+    }
+    let info_file = paths.instance_file();
+    fs::write(&info_file, serde_json::to_vec_pretty(&info)?)?;
+    #[cfg(unix)] {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = fs::set_permissions(&info_file, fs::Permissions::from_mode(0o600));
+    }
+    Ok(Acquire::Acquired(InstanceLock { _file: file, info_file, info }))
+}
+
+/// Daemon vigente según `agent_instance.json`, si su proceso sigue vivo. Para la CLI: no toca el
+/// lock (un sondeo que lo tomara, aunque fuera un instante, podría hacer fallar a un daemon que
+/// arranca justo entonces). Tras un crash el archivo queda, pero su pid ya no existe o es de otro
+/// proceso posterior.
+pub fn holder(paths: &Paths) -> Option<InstanceInfo> {
+    let info = read_info(paths)?;
+    let pid = sysinfo::Pid::from_u32(info.pid);
+    let mut sys = sysinfo::System::new();
+    if !sys.refresh_process_specifics(pid, sysinfo::ProcessRefreshKind::new()) { return None; }
+    let proc = sys.process(pid).filter(|p| p.status() != sysinfo::ProcessStatus::Zombie)?;
+    // `started_at_ms` se toma después de que el proceso arrancó
+    (proc.start_time() <= info.started_at_ms / 1000 + 1).then_some(info)
+}
+
+pub fn read_info(paths: &Paths) -> Option<InstanceInfo> {
+    fs::read_to_string(paths.instance_file()).ok().and_then(|txt| serde_json::from_str(&txt).ok())
+}
+
+/// `Ok(false)` si otro proceso tiene el lock.
+#[cfg(unix)]
+fn try_lock_exclusive(file: &File) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 { return Ok(true); }
+    let err = std::io::Error::last_os_error();
+    if err.kind() == std::io::ErrorKind::WouldBlock { Ok(false) } else { Err(err) }
+}
+
+#[cfg(windows)]
+fn try_lock_exclusive(file: &File) -> std::io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::{ERROR_LOCK_VIOLATION, HANDLE};
+    use windows::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY};
+    use windows::Win32::System::IO::OVERLAPPED;
+    let mut overlapped = OVERLAPPED::default();
+    let handle = HANDLE(file.as_raw_handle() as isize);
+    match unsafe { LockFileEx(handle, LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY, 0, u32::MAX, u32::MAX, &mut overlapped) } {
+        Ok(()) => Ok(true),
+        Err(e) if e.code() == ERROR_LOCK_VIOLATION.to_hresult() => Ok(false),
+        Err(e) => Err(std::io::Error::other(e.to_string())),
+    }
+}
+
+fn open_lock(paths: &Paths) -> Result<File> {
+    let path = paths.lock_file();
+    ensure_parent(&path)?;
+    Ok(OpenOptions::new().create(true).truncate(false).read(true).write(true).open(&path)?)
+}
@@ -5,5 +5,17 @@ pub mod queue;
 pub mod metrics;
 pub mod auth;
 pub mod focus;
+pub mod http;
+pub mod proxy;
+pub mod pac;
+pub mod backend;
+pub mod heartbeat;
+pub mod commands;
+pub mod policy;
+pub mod policy_sig;
+pub mod rules;
+pub mod redact;
+pub mod diag;
+pub mod instance;
 
 pub const DEFAULT_PANEL_ADDR: &str = "127.0.0.1:49219";
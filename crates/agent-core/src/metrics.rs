@@ -1,4 +1,5 @@
 use serde::Serialize;
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use sysinfo::{CpuRefreshKind, Pid, ProcessRefreshKind, RefreshKind, System};
 use tokio::time::{sleep, Duration};
@@ -11,20 +12,43 @@ pub struct AgentMetrics {
     pub mem_mb: u64,
 }
 
-#[derive(Clone)]
+/// Ventana de muestras para percentiles: 720 × 5 s = 1 h
+const WINDOW: usize = 720;
+
+/// p95 de CPU/RAM sobre la última hora de muestras.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MetricsP95 {
+    pub cpu_pct: f32,
+    pub mem_mb: u64,
+    pub samples: usize,
+}
+
+#[derive(Clone, Default)]
 pub struct MetricsHandle {
     inner: Arc<Mutex<AgentMetrics>>,
+    window: Arc<Mutex<VecDeque<AgentMetrics>>>,
 }
 
 impl MetricsHandle {
     pub fn new() -> Self {
-        Self { inner: Arc::new(Mutex::new(AgentMetrics::default())) }
+        Self::default()
     }
 
     pub fn get(&self) -> AgentMetrics {
         self.inner.lock().unwrap().clone()
     }
 
+    pub fn p95(&self) -> MetricsP95 {
+        let w = self.window.lock().unwrap();
+        if w.is_empty() { return MetricsP95::default(); }
+        let mut cpu: Vec<f32> = w.iter().map(|m| m.cpu_pct).collect();
+        let mut mem: Vec<u64> = w.iter().map(|m| m.mem_mb).collect();
+        cpu.sort_by(|a, b| a.total_cmp(b));
+        mem.sort_unstable();
+        let idx = ((w.len() as f64) * 0.95).ceil() as usize - 1;
+        MetricsP95 { cpu_pct: cpu[idx], mem_mb: mem[idx], samples: w.len() }
+    }
+
     pub async fn run_sampler(self) {
         let pid = std::process::id();
         let mut sys = System::new_with_specifics(
@@ -54,8 +78,407 @@ impl MetricsHandle {
                 m.cpu_pct = cpu_pct;
                 m.mem_mb = mem_mb;
             } // liberar el lock antes de await
+            {
+                let mut w = self.window.lock().unwrap();
+                if w.len() >= WINDOW { w.pop_front(); }
+                w.push_back(AgentMetrics { cpu_pct, mem_mb });
+            }
 
             sleep(Duration::from_secs(5)).await;
         }
     }
 }
+
+// --- Registro de series (contadores, gauges, histogramas) con rollups por minuto ---
+
+/// Límites de los buckets de histograma (ms; el último bucket es +Inf).
+pub const HISTOGRAM_BOUNDS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 30000.0, 60000.0];
+/// Tope de muestras de gauge por minuto (se muestrean cada ~10 s)
+const GAUGE_SAMPLES_PER_MINUTE: usize = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind { Counter, Gauge, Histogram }
+
+impl MetricKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "counter" => Some(MetricKind::Counter),
+            "gauge" => Some(MetricKind::Gauge),
+            "histogram" => Some(MetricKind::Histogram),
+            _ => None,
+        }
+    }
+}
+
+/// Agregado del minuto en curso de una serie.
+#[derive(Debug, Clone)]
+struct Minute {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    samples: Vec<f64>,
+    buckets: Vec<u64>,
+}
+
+impl Minute {
+    fn new(kind: MetricKind) -> Self {
+        let buckets = if kind == MetricKind::Histogram { vec![0; HISTOGRAM_BOUNDS.len() + 1] } else { Vec::new() };
+        Self { count: 0, sum: 0.0, min: f64::INFINITY, max: f64::NEG_INFINITY, samples: Vec::new(), buckets }
+    }
+
+    fn add(&mut self, v: f64) {
+        self.count += 1;
+        self.sum += v;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+    }
+}
+
+/// Acumulado desde el arranque (exposición `/metrics`): contadores y histogramas no se
+/// reinician por minuto; en gauges solo interesa el último valor.
+#[derive(Debug, Clone)]
+struct Total {
+    count: u64,
+    sum: f64,
+    last: f64,
+    buckets: Vec<u64>,
+}
+
+#[derive(Debug)]
+struct Series { kind: MetricKind, minute: Minute, total: Total }
+
+/// Registro en memoria. Las etiquetas se guardan en forma canónica (`k="v",k2="v2"`, ordenadas).
+#[derive(Debug, Default)]
+pub struct Registry {
+    series: Mutex<std::collections::BTreeMap<(String, String), Series>>,
+}
+
+/// Un minuto de una serie, tal como se guarda en SQLite.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rollup {
+    pub minute_ms: u64,
+    pub name: String,
+    pub labels: String,
+    pub kind: MetricKind,
+    /// Contador: incremento del minuto; gauge: muestras; histograma: observaciones
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p50: f64,
+    pub p95: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub buckets: Vec<u64>,
+}
+
+impl Registry {
+    pub fn new() -> Arc<Self> { Arc::new(Self::default()) }
+
+    fn with(&self, name: &str, labels: &[(&str, &str)], kind: MetricKind, f: impl FnOnce(&mut Minute, &mut Total)) {
+        let key = (name.to_string(), canonical_labels(labels));
+        let mut map = self.series.lock().unwrap();
+        let s = map.entry(key).or_insert_with(|| {
+            let minute = Minute::new(kind);
+            let total = Total { count: 0, sum: 0.0, last: 0.0, buckets: minute.buckets.clone() };
+            Series { kind, minute, total }
+        });
+        f(&mut s.minute, &mut s.total);
+    }
+
+    pub fn inc(&self, name: &str, labels: &[(&str, &str)], by: u64) {
+        self.with(name, labels, MetricKind::Counter, |m, t| {
+            m.count += by;
+            m.sum += by as f64;
+            t.count += by;
+        });
+    }
+
+    pub fn set(&self, name: &str, labels: &[(&str, &str)], v: f64) {
+        self.with(name, labels, MetricKind::Gauge, |m, t| {
+            m.add(v);
+            if m.samples.len() < GAUGE_SAMPLES_PER_MINUTE { m.samples.push(v); }
+            t.last = v;
+        });
+    }
+
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], v: f64) {
+        self.with(name, labels, MetricKind::Histogram, |m, t| {
+            m.add(v);
+            t.count += 1;
+            t.sum += v;
+            let i = HISTOGRAM_BOUNDS.iter().position(|b| v <= *b).unwrap_or(HISTOGRAM_BOUNDS.len());
+            if let Some(b) = m.buckets.get_mut(i) { *b += 1; }
+            if let Some(b) = t.buckets.get_mut(i) { *b += 1; }
+        });
+    }
+
+    /// Escribe las series en texto OpenMetrics con `prefix` (`riporagent_`), omitiendo `skip`.
+    /// Contadores como `<name>_total`; los histogramas en ms (`*_ms`) se exponen en segundos.
+    /// No agrega `# EOF`: quien arma la respuesta puede sumar más familias.
+    pub fn write_openmetrics(&self, out: &mut String, prefix: &str, skip: &[&str]) {
+        use std::fmt::Write;
+        let map = self.series.lock().unwrap();
+        let mut family = "";
+        for ((name, labels), s) in map.iter().filter(|((n, _), _)| !skip.contains(&n.as_str())) {
+            let (base, scale) = match name.strip_suffix("_ms") {
+                Some(b) if s.kind == MetricKind::Histogram => (format!("{}{}_seconds", prefix, b), 1000.0),
+                _ => (format!("{}{}", prefix, name), 1.0),
+            };
+            if name != family {
+                let _ = writeln!(out, "# TYPE {} {}", base, s.kind.as_str());
+                family = name;
+            }
+            let t = &s.total;
+            match s.kind {
+                MetricKind::Counter => { let _ = writeln!(out, "{}_total{} {}", base, braces(labels, None), t.count); }
+                MetricKind::Gauge => { let _ = writeln!(out, "{}{} {}", base, braces(labels, None), t.last); }
+                MetricKind::Histogram => {
+                    let mut acc = 0u64;
+                    for (i, n) in t.buckets.iter().enumerate() {
+                        acc += n;
+                        let le = HISTOGRAM_BOUNDS.get(i).map(|b| (b / scale).to_string()).unwrap_or_else(|| "+Inf".into());
+                        let _ = writeln!(out, "{}_bucket{} {}", base, braces(labels, Some(&le)), acc);
+                    }
+                    let _ = writeln!(out, "{}_count{} {}", base, braces(labels, None), t.count);
+                    let _ = writeln!(out, "{}_sum{} {}", base, braces(labels, None), t.sum / scale);
+                }
+            }
+        }
+    }
+
+    /// Cierra el minuto: devuelve los rollups de las series con datos y los reinicia.
+    pub fn take_minute(&self, minute_ms: u64) -> Vec<Rollup> { self.rollups(minute_ms, true) }
+
+    /// Rollups del minuto en curso sin reiniciarlo (para incluirlo en los resúmenes).
+    pub fn peek_minute(&self, minute_ms: u64) -> Vec<Rollup> { self.rollups(minute_ms, false) }
+
+    fn rollups(&self, minute_ms: u64, reset: bool) -> Vec<Rollup> {
+        let mut map = self.series.lock().unwrap();
+        let mut out = Vec::new();
+        for ((name, labels), s) in map.iter_mut() {
+            if s.minute.count == 0 { continue; }
+            let m = &s.minute;
+            let (p50, p95) = match s.kind {
+                MetricKind::Counter => (0.0, 0.0),
+                MetricKind::Gauge => {
+                    let mut v = m.samples.clone();
+                    v.sort_by(|a, b| a.total_cmp(b));
+                    (percentile(&v, 0.50), percentile(&v, 0.95))
+                }
+                MetricKind::Histogram => (bucket_quantile(&m.buckets, 0.50, m.min, m.max), bucket_quantile(&m.buckets, 0.95, m.min, m.max)),
+            };
+            let (min, max) = if s.kind == MetricKind::Counter { (0.0, 0.0) } else { (m.min, m.max) };
+            out.push(Rollup { minute_ms, name: name.clone(), labels: labels.clone(), kind: s.kind, count: m.count, sum: m.sum, min, max, p50, p95, buckets: m.buckets.clone() });
+            if reset { s.minute = Minute::new(s.kind); }
+        }
+        out
+    }
+}
+
+/// `[("rule","x"),("reason","rule")]` → `reason="rule",rule="x"` (escapado estilo OpenMetrics).
+pub fn canonical_labels(labels: &[(&str, &str)]) -> String {
+    let mut l: Vec<_> = labels.to_vec();
+    l.sort_by(|a, b| a.0.cmp(b.0));
+    l.iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// `{labels}` (o nada) con `le` opcional al final.
+fn braces(labels: &str, le: Option<&str>) -> String {
+    let le = le.map(|v| format!("le=\"{}\"", v));
+    let all: Vec<&str> = [Some(labels).filter(|l| !l.is_empty()), le.as_deref()].into_iter().flatten().collect();
+    if all.is_empty() { String::new() } else { format!("{{{}}}", all.join(",")) }
+}
+
+/// Percentil sobre muestras ordenadas (nearest-rank, como `MetricsHandle::p95`).
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() { return 0.0; }
+    let idx = ((sorted.len() as f64) * q).ceil() as usize;
+    sorted[idx.clamp(1, sorted.len()) - 1]
+}
+
+/// Cuantil estimado desde buckets (interpolación lineal dentro del bucket, acotado a min/max).
+fn bucket_quantile(buckets: &[u64], q: f64, min: f64, max: f64) -> f64 {
+    let total: u64 = buckets.iter().sum();
+    if total == 0 { return 0.0; }
+    let rank = q * total as f64;
+    let mut acc = 0u64;
+    for (i, n) in buckets.iter().enumerate() {
+        if *n == 0 { continue; }
+        if (acc + n) as f64 >= rank {
+            let lo = if i == 0 { min } else { HISTOGRAM_BOUNDS[i - 1].max(min) };
+            let hi = HISTOGRAM_BOUNDS.get(i).copied().unwrap_or(max).min(max);
+            let frac = ((rank - acc as f64) / *n as f64).clamp(0.0, 1.0);
+            return (lo + (hi - lo) * frac).clamp(min, max);
+        }
+        acc += n;
+    }
+    max
+}
+
+/// Rollups por minuto en `queue.sqlite` (tabla `metric_rollups`).
+pub struct MetricsStore {
+    conn: rusqlite::Connection,
+}
+
+impl MetricsStore {
+    pub fn open(paths: &crate::paths::Paths) -> anyhow::Result<Self> {
+        let conn = rusqlite::Connection::open(paths.queue_db())?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS metric_rollups (
+                minute_ms INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                labels TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                count INTEGER NOT NULL,
+                sum REAL NOT NULL,
+                min REAL NOT NULL,
+                max REAL NOT NULL,
+                p50 REAL NOT NULL,
+                p95 REAL NOT NULL,
+                buckets TEXT
+            );
+            CREATE INDEX IF NOT EXISTS idx_metric_minute ON metric_rollups(minute_ms);
+            ",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Solo lectura (ver `queue::open_read_only`).
+    pub fn open_read_only(paths: &crate::paths::Paths) -> anyhow::Result<Self> {
+        Ok(Self { conn: crate::queue::open_read_only(paths)? })
+    }
+
+    pub fn insert(&mut self, rows: &[Rollup]) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO metric_rollups(minute_ms,name,labels,kind,count,sum,min,max,p50,p95,buckets) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11)",
+            )?;
+            for r in rows {
+                let buckets = (!r.buckets.is_empty()).then(|| serde_json::to_string(&r.buckets).unwrap_or_default());
+                stmt.execute(rusqlite::params![r.minute_ms as i64, r.name, r.labels, r.kind.as_str(), r.count as i64, r.sum, r.min, r.max, r.p50, r.p95, buckets])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn prune_before(&self, minute_ms: u64) -> anyhow::Result<usize> {
+        Ok(self.conn.execute("DELETE FROM metric_rollups WHERE minute_ms < ?1", [minute_ms as i64])?)
+    }
+
+    pub fn since(&self, minute_ms: u64) -> anyhow::Result<Vec<Rollup>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT minute_ms,name,labels,kind,count,sum,min,max,p50,p95,buckets FROM metric_rollups WHERE minute_ms >= ?1 ORDER BY minute_ms",
+        )?;
+        let rows = stmt.query_map([minute_ms as i64], |row| {
+            let kind: String = row.get(3)?;
+            let buckets: Option<String> = row.get(10)?;
+            Ok((
+                kind,
+                Rollup {
+                    minute_ms: row.get::<_, i64>(0)? as u64,
+                    name: row.get(1)?,
+                    labels: row.get(2)?,
+                    kind: MetricKind::Counter,
+                    count: row.get::<_, i64>(4)? as u64,
+                    sum: row.get(5)?,
+                    min: row.get(6)?,
+                    max: row.get(7)?,
+                    p50: row.get(8)?,
+                    p95: row.get(9)?,
+                    buckets: buckets.and_then(|b| serde_json::from_str(&b).ok()).unwrap_or_default(),
+                },
+            ))
+        })?;
+        let mut out = Vec::new();
+        for r in rows {
+            let (kind, mut rollup) = r?;
+            let Some(kind) = MetricKind::parse(&kind) else { continue };
+            rollup.kind = kind;
+            out.push(rollup);
+        }
+        Ok(out)
+    }
+}
+
+/// Resumen de una serie sobre una ventana.
+#[derive(Debug, Clone, Serialize)]
+pub struct SeriesSummary {
+    pub name: String,
+    pub labels: String,
+    pub kind: MetricKind,
+    /// Minutos con datos dentro de la ventana
+    pub minutes: usize,
+    pub count: u64,
+    pub sum: f64,
+    /// Solo contadores: incremento por segundo sobre la ventana completa
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_per_s: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95: Option<f64>,
+}
+
+/// Agrega rollups por serie. Histogramas: cuantiles sobre los buckets combinados.
+/// Gauges: p50 = mediana de las medianas por minuto, p95 = p95 de los p95 por minuto
+/// (conservador: nunca por debajo del p95 real de las muestras).
+pub fn summarize(rows: &[Rollup], window_ms: u64) -> Vec<SeriesSummary> {
+    let mut groups: std::collections::BTreeMap<(&str, &str), Vec<&Rollup>> = std::collections::BTreeMap::new();
+    for r in rows {
+        groups.entry((r.name.as_str(), r.labels.as_str())).or_default().push(r);
+    }
+    groups
+        .into_iter()
+        .map(|((name, labels), rs)| {
+            let kind = rs[0].kind;
+            let count: u64 = rs.iter().map(|r| r.count).sum();
+            let sum: f64 = rs.iter().map(|r| r.sum).sum();
+            let min = rs.iter().map(|r| r.min).fold(f64::INFINITY, f64::min);
+            let max = rs.iter().map(|r| r.max).fold(f64::NEG_INFINITY, f64::max);
+            let mut s = SeriesSummary { name: name.to_string(), labels: labels.to_string(), kind, minutes: rs.len(), count, sum, rate_per_s: None, avg: None, min: None, max: None, p50: None, p95: None };
+            match kind {
+                MetricKind::Counter => s.rate_per_s = Some(sum / (window_ms.max(1) as f64 / 1000.0)),
+                MetricKind::Gauge => {
+                    let mut p50s: Vec<f64> = rs.iter().map(|r| r.p50).collect();
+                    let mut p95s: Vec<f64> = rs.iter().map(|r| r.p95).collect();
+                    p50s.sort_by(|a, b| a.total_cmp(b));
+                    p95s.sort_by(|a, b| a.total_cmp(b));
+                    (s.avg, s.min, s.max) = (Some(sum / count.max(1) as f64), Some(min), Some(max));
+                    (s.p50, s.p95) = (Some(percentile(&p50s, 0.50)), Some(percentile(&p95s, 0.95)));
+                }
+                MetricKind::Histogram => {
+                    let mut buckets = vec![0u64; HISTOGRAM_BOUNDS.len() + 1];
+                    for r in &rs {
+                        for (b, n) in buckets.iter_mut().zip(&r.buckets) { *b += n; }
+                    }
+                    (s.avg, s.min, s.max) = (Some(sum / count.max(1) as f64), Some(min), Some(max));
+                    (s.p50, s.p95) = (Some(bucket_quantile(&buckets, 0.50, min, max)), Some(bucket_quantile(&buckets, 0.95, min, max)));
+                }
+            }
+            s
+        })
+        .collect()
+}
@@ -0,0 +1,294 @@
+// Archivos PAC (`PROXY_MODE=pac`, `PROXY_PAC_FILE`) evaluados con QuickJS (feature `pac`).
+// El script corre en un hilo propio (el runtime JS no es Send) con límite de memoria y de tiempo
+// por llamada. Las conexiones no lo esperan: usan el resultado en caché por destino (ver
+// `PacScript::cached_route`) y el hilo lo recalcula en segundo plano. Las funciones estándar (`isInNet`, `shExpMatch`, `dnsDomainIs`, `timeRange`...)
+// están en JS sobre dos nativas: `dnsResolve` y `myIpAddress`.
+// Sin la feature, `PacScript::load` falla y `ProxySettings` vuelve a `system`.
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Vigencia de un resultado de `FindProxyForURL`; pasado este tiempo se sigue usando mientras se
+/// recalcula en el hilo del PAC.
+pub const CACHE_TTL: Duration = Duration::from_secs(300);
+
+type Route = std::result::Result<Option<String>, String>;
+
+#[derive(Default)]
+struct Cache {
+    routes: HashMap<String, (Route, Instant)>,
+    /// Destinos con una evaluación en cola (no se encola otra)
+    refreshing: std::collections::HashSet<String>,
+}
+
+pub struct PacScript {
+    path: PathBuf,
+    cache: Arc<Mutex<Cache>>,
+    #[cfg(feature = "pac")]
+    jobs: std::sync::mpsc::Sender<engine::Job>,
+}
+
+impl PacScript {
+    /// Lee y ejecuta el script; falla si no define `FindProxyForURL`.
+    #[cfg(feature = "pac")]
+    pub fn load(path: &Path) -> Result<Self> {
+        let src = std::fs::read_to_string(path).map_err(|e| anyhow!("no se pudo leer {}: {}", path.display(), e))?;
+        Ok(Self { path: path.to_path_buf(), cache: Default::default(), jobs: engine::spawn(src)? })
+    }
+
+    #[cfg(not(feature = "pac"))]
+    pub fn load(_path: &Path) -> Result<Self> {
+        Err(anyhow!("el binario se compiló sin la feature `pac`"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Resultado crudo de `FindProxyForURL(url, host)`, p. ej. `"PROXY a:3128; DIRECT"`.
+    /// Bloquea hasta que responde el script: no llamar desde el runtime.
+    #[cfg(feature = "pac")]
+    pub fn find_proxy(&self, url: &str, host: &str) -> Result<String> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let reply = Box::new(move |r| { let _ = tx.send(r); });
+        self.jobs.send((url.to_string(), host.to_string(), reply)).map_err(|_| anyhow!("el hilo del PAC terminó"))?;
+        // el intérprete corta a CALL_TIMEOUT; el margen cubre dnsResolve, que no se puede interrumpir
+        match rx.recv_timeout(engine::CALL_TIMEOUT * 3) {
+            Ok(r) => r.map_err(|e| anyhow!("FindProxyForURL: {}", e)),
+            Err(_) => Err(anyhow!("FindProxyForURL no respondió")),
+        }
+    }
+
+    #[cfg(not(feature = "pac"))]
+    pub fn find_proxy(&self, _url: &str, _host: &str) -> Result<String> {
+        Err(anyhow!("el binario se compiló sin la feature `pac`"))
+    }
+
+    /// Proxy para `url` (`None` = directo), evaluando el script y guardando el resultado.
+    /// Bloquea: para arrancar (ver [`crate::http::ClientFactory::prime_proxy`]), la CLI y tests.
+    pub fn route(&self, url: &reqwest::Url) -> Result<Option<String>> {
+        let (key, host) = target(url);
+        let route = self.find_proxy(&key, &host).map_err(|e| e.to_string()).and_then(parse_result);
+        self.cache.lock().unwrap().routes.insert(key, (route.clone(), Instant::now()));
+        route.map_err(|e| anyhow!(e))
+    }
+
+    /// Último resultado para el destino de `url`, sin esperar al script. Si falta o venció se
+    /// encola una evaluación en el hilo del PAC y se devuelve el anterior (o `None` si no hay).
+    pub fn cached_route(&self, url: &reqwest::Url) -> Option<Result<Option<String>>> {
+        let (key, host) = target(url);
+        let mut cache = self.cache.lock().unwrap();
+        let hit = cache.routes.get(&key).cloned();
+        if hit.as_ref().is_none_or(|(_, at)| at.elapsed() >= CACHE_TTL) && cache.refreshing.insert(key.clone()) {
+            drop(cache);
+            self.refresh(key, host);
+        }
+        hit.map(|(r, _)| r.map_err(|e| anyhow!(e)))
+    }
+
+    #[cfg(feature = "pac")]
+    fn refresh(&self, key: String, host: String) {
+        let cache = self.cache.clone();
+        let k = key.clone();
+        let reply = Box::new(move |r: std::result::Result<String, String>| {
+            let route = r.map_err(|e| format!("FindProxyForURL: {}", e)).and_then(parse_result);
+            let mut c = cache.lock().unwrap();
+            c.refreshing.remove(&k);
+            c.routes.insert(k, (route, Instant::now()));
+        });
+        if self.jobs.send((key.clone(), host, reply)).is_err() {
+            self.cache.lock().unwrap().refreshing.remove(&key);
+        }
+    }
+
+    #[cfg(not(feature = "pac"))]
+    fn refresh(&self, key: String, _host: String) {
+        self.cache.lock().unwrap().refreshing.remove(&key);
+    }
+}
+
+/// URL que ve el script (y clave de la caché) y host. Como los navegadores con https, el script no
+/// ve ruta ni query; aquí tampoco en http, para que el resultado valga para todo el destino.
+fn target(url: &reqwest::Url) -> (String, String) {
+    let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+    (format!("{}://{}/", url.scheme(), url.authority()), host.to_string())
+}
+
+fn parse_result(raw: String) -> Route {
+    first_route(&raw).ok_or_else(|| format!("FindProxyForURL devolvió '{}' sin entradas utilizables", raw))
+}
+
+impl PartialEq for PacScript {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for PacScript {}
+
+impl std::fmt::Debug for PacScript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PacScript").field(&self.path).finish()
+    }
+}
+
+/// Primera entrada utilizable de un resultado PAC: `Some(None)` = `DIRECT`, `Some(Some(url))` =
+/// proxy. reqwest usa un solo proxy por petición, así que las siguientes no sirven de respaldo;
+/// se saltan las que no soporta (`SOCKS4`, entradas sin dirección).
+pub fn first_route(result: &str) -> Option<Option<String>> {
+    for entry in result.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.split_whitespace();
+        let kind = parts.next().unwrap_or_default().to_ascii_uppercase();
+        let scheme = match kind.as_str() {
+            "DIRECT" => return Some(None),
+            "PROXY" | "HTTP" => "http",
+            "HTTPS" => "https",
+            "SOCKS" | "SOCKS5" => "socks5",
+            _ => continue,
+        };
+        if let Some(addr) = parts.next() {
+            return Some(Some(format!("{}://{}", scheme, addr)));
+        }
+    }
+    None
+}
+
+#[cfg(feature = "pac")]
+mod engine {
+    use anyhow::{anyhow, Result};
+    use rquickjs::{CaughtError, Context, Ctx, Function, Runtime};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    pub type Job = (String, String, Box<dyn FnOnce(Result<String, String>) + Send>);
+
+    pub const CALL_TIMEOUT: Duration = Duration::from_secs(1);
+    const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+    /// Arranca el hilo del intérprete y espera a que el script cargue.
+    pub fn spawn(src: String) -> Result<mpsc::Sender<Job>> {
+        let (jobs, rx) = mpsc::channel::<Job>();
+        let (ready_tx, ready_rx) = mpsc::sync_channel::<Result<(), String>>(1);
+        std::thread::Builder::new().name("pac".into()).spawn(move || {
+            let deadline: Rc<Cell<Option<Instant>>> = Rc::new(Cell::new(None));
+            let (_rt, ctx) = match setup(&src, deadline.clone()) {
+                Ok(v) => {
+                    let _ = ready_tx.send(Ok(()));
+                    v
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            for (url, host, reply) in rx {
+                deadline.set(Some(Instant::now() + CALL_TIMEOUT));
+                let res = ctx.with(|ctx| {
+                    let call = ctx.globals().get::<_, Function>("FindProxyForURL").and_then(|f| f.call::<_, String>((url, host)));
+                    call.map_err(|e| caught(&ctx, e))
+                });
+                deadline.set(None);
+                reply(res);
+            }
+        })?;
+        ready_rx.recv().map_err(|_| anyhow!("el hilo del PAC terminó al cargar"))?.map_err(|e| anyhow!(e))?;
+        Ok(jobs)
+    }
+
+    fn setup(src: &str, deadline: Rc<Cell<Option<Instant>>>) -> Result<(Runtime, Context), String> {
+        let rt = Runtime::new().map_err(|e| e.to_string())?;
+        rt.set_memory_limit(MEMORY_LIMIT);
+        rt.set_interrupt_handler(Some(Box::new(move || deadline.get().is_some_and(|d| Instant::now() > d))));
+        let ctx = Context::full(&rt).map_err(|e| e.to_string())?;
+        ctx.with(|ctx| {
+            let g = ctx.globals();
+            let natives = g
+                .set("__dnsResolve", Function::new(ctx.clone(), dns_resolve))
+                .and_then(|_| g.set("myIpAddress", Function::new(ctx.clone(), my_ip_address)));
+            natives.map_err(|e| e.to_string())?;
+            ctx.eval::<(), _>(UTILS).map_err(|e| caught(&ctx, e))?;
+            ctx.eval::<(), _>(src).map_err(|e| caught(&ctx, e))?;
+            match ctx.eval::<bool, _>("typeof FindProxyForURL === 'function'") {
+                Ok(true) => Ok(()),
+                _ => Err("el script no define FindProxyForURL".to_string()),
+            }
+        })?;
+        Ok((rt, ctx))
+    }
+
+    fn caught(ctx: &Ctx, e: rquickjs::Error) -> String {
+        CaughtError::from_error(ctx, e).to_string().trim().to_string()
+    }
+
+    /// Primera IPv4 del host (`None` llega a JS como `undefined`; `dnsResolve` lo pasa a `null`).
+    fn dns_resolve(host: String) -> Option<String> {
+        use std::net::ToSocketAddrs;
+        (host.as_str(), 0).to_socket_addrs().ok()?.find(|a| a.is_ipv4()).map(|a| a.ip().to_string())
+    }
+
+    /// IP local de la interfaz con ruta por defecto (UDP `connect` no envía paquetes).
+    fn my_ip_address() -> String {
+        std::net::UdpSocket::bind("0.0.0.0:0")
+            .and_then(|s| s.connect("192.0.2.1:80").and_then(|_| s.local_addr()))
+            .map(|a| a.ip().to_string())
+            .unwrap_or_else(|_| "127.0.0.1".into())
+    }
+
+    /// Funciones auxiliares de PAC sobre `dnsResolve`/`myIpAddress`.
+    const UTILS: &str = r#"
+var __days = ['SUN', 'MON', 'TUE', 'WED', 'THU', 'FRI', 'SAT'];
+var __months = ['JAN', 'FEB', 'MAR', 'APR', 'MAY', 'JUN', 'JUL', 'AUG', 'SEP', 'OCT', 'NOV', 'DEC'];
+function __args(a) { a = Array.prototype.slice.call(a); var gmt = a[a.length - 1] === 'GMT'; if (gmt) a.pop(); return [a, gmt]; }
+function __now(gmt) {
+  var d = new Date();
+  return gmt
+    ? { day: d.getUTCDay(), date: d.getUTCDate(), month: d.getUTCMonth(), year: d.getUTCFullYear(), secs: d.getUTCHours() * 3600 + d.getUTCMinutes() * 60 + d.getUTCSeconds() }
+    : { day: d.getDay(), date: d.getDate(), month: d.getMonth(), year: d.getFullYear(), secs: d.getHours() * 3600 + d.getMinutes() * 60 + d.getSeconds() };
+}
+function __within(v, lo, hi) { return lo <= hi ? v >= lo && v <= hi : v >= lo || v <= hi; }
+function alert() {}
+function dnsResolve(h) { var ip = __dnsResolve(String(h)); return ip === undefined ? null : ip; }
+function isPlainHostName(h) { return h.indexOf('.') < 0; }
+function dnsDomainIs(h, d) { return h.length >= d.length && h.substring(h.length - d.length) === d; }
+function localHostOrDomainIs(h, hd) { return h === hd || (isPlainHostName(h) && hd.lastIndexOf(h + '.', 0) === 0); }
+function isResolvable(h) { return dnsResolve(h) !== null; }
+function dnsDomainLevels(h) { return h.split('.').length - 1; }
+function convert_addr(ip) { var p = ip.split('.'); return ((p[0] & 255) << 24 | (p[1] & 255) << 16 | (p[2] & 255) << 8 | (p[3] & 255)) >>> 0; }
+function isInNet(h, pat, mask) {
+  var ip = /^\d+\.\d+\.\d+\.\d+$/.test(h) ? h : dnsResolve(h);
+  if (ip === null) return false;
+  var m = convert_addr(mask);
+  return ((convert_addr(ip) & m) >>> 0) === ((convert_addr(pat) & m) >>> 0);
+}
+function shExpMatch(s, p) {
+  return new RegExp('^' + p.replace(/[.+^${}()|[\]\\]/g, '\\$&').replace(/\*/g, '.*').replace(/\?/g, '.') + '$').test(s);
+}
+function weekdayRange() {
+  var a = __args(arguments), w1 = __days.indexOf(a[0][0]), w2 = a[0].length > 1 ? __days.indexOf(a[0][1]) : w1;
+  return w1 >= 0 && w2 >= 0 && __within(__now(a[1]).day, w1, w2);
+}
+function dateRange() {
+  var a = __args(arguments), n = __now(a[1]);
+  var ks = a[0].map(function (v) { return typeof v === 'string' ? ['month', __months.indexOf(v)] : [v > 31 ? 'year' : 'date', v]; });
+  if (ks.length === 1) return n[ks[0][0]] === ks[0][1];
+  if (ks.length % 2) return false;
+  var lo = {}, hi = {}, half = ks.length / 2;
+  ks.forEach(function (k, i) { (i < half ? lo : hi)[k[0]] = k[1]; });
+  var fields = ['year', 'month', 'date'].filter(function (f) { return f in lo; });
+  var num = function (o) { return fields.reduce(function (acc, f) { return acc * 100 + o[f]; }, 0); };
+  return __within(num(n), num(lo), num(hi));
+}
+function timeRange() {
+  var a = __args(arguments), v = a[0], s = __now(a[1]).secs;
+  if (v.length === 1) return Math.floor(s / 3600) === v[0];
+  if (v.length === 2) return __within(s, v[0] * 3600, v[1] * 3600 - 1);
+  if (v.length === 4) return __within(s, v[0] * 3600 + v[1] * 60, v[2] * 3600 + v[3] * 60 - 1);
+  if (v.length === 6) return __within(s, v[0] * 3600 + v[1] * 60 + v[2], v[3] * 3600 + v[4] * 60 + v[5]);
+  return false;
+}
+"#;
+}
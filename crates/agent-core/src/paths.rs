@@ -50,6 +50,26 @@ impl Paths {
     pub fn policy_meta_file(&self) -> PathBuf {
         self.data_dir.join("policy_meta.json")
     }
+
+    pub fn commands_file(&self) -> PathBuf {
+        self.data_dir.join("commands.json")
+    }
+
+    pub fn policy_keys_file(&self) -> PathBuf {
+        self.data_dir.join("policy_keys.json")
+    }
+
+    pub fn drops_file(&self) -> PathBuf {
+        self.data_dir.join("drops.json")
+    }
+
+    pub fn lock_file(&self) -> PathBuf {
+        self.data_dir.join("agent.lock")
+    }
+
+    pub fn instance_file(&self) -> PathBuf {
+        self.data_dir.join("agent_instance.json")
+    }
 }
 
 pub fn ensure_parent(p: &Path) -> Result<()> {
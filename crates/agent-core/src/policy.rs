@@ -0,0 +1,320 @@
+// Documento de policy (compartido por daemon y CLI) y su validación.
+use crate::redact::{RedactionPolicy, Redactor, TitleKey};
+use crate::rules::{CompiledRules, Moment, Rule, RuleHit};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{RegexBuilder, RegexSet, RegexSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Policy {
+    #[serde(default)]
+    pub killSwitch: bool,
+    #[serde(default)]
+    pub pauseCapture: bool,
+    #[serde(default = "default_true")]
+    pub titleCapture: bool,
+    #[serde(default)]
+    pub excludeApps: Vec<String>,
+    #[serde(default)]
+    pub excludePatterns: Vec<String>,
+    #[serde(default)]
+    pub excludeExePaths: Vec<String>,
+    /// Expresiones regulares sobre el título (sintaxis de la crate `regex`)
+    #[serde(default)]
+    pub excludeTitleRegex: Vec<String>,
+    /// Exclusiones sin distinguir mayúsculas (apps, globs y regex)
+    #[serde(default)]
+    pub excludeCaseInsensitive: bool,
+    #[serde(default)]
+    pub updateChannel: Option<String>,
+    #[serde(default)]
+    pub titleSampleHz: Option<u32>,
+    #[serde(default)]
+    pub titleBurstPerMinute: Option<u32>,
+    #[serde(default)]
+    pub focusMinMinutes: Option<u32>,
+    /// Intervalo de heartbeat en segundos (por defecto 60; acotado a 15..3600)
+    #[serde(default)]
+    pub heartbeatIntervalSecs: Option<u32>,
+    /// Pins SPKI adicionales (`sha256/<base64>`) que se suman a `TLS_SPKI_PINS`
+    #[serde(default)]
+    pub tlsPins: Vec<String>,
+    /// Reglas ordenadas (ver `rules.rs`); se evalúan antes que las exclusiones planas
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Encolar el tiempo excluido como eventos anónimos (motivo y duración, sin app ni título)
+    #[serde(default)]
+    pub reportDrops: bool,
+    /// Enmascarado de datos personales en títulos (ver `redact.rs`)
+    #[serde(default)]
+    pub redaction: RedactionPolicy,
+    /// Filtro de logs del agente (sintaxis de `RUST_LOG`, ej. `info,agent_daemon=debug`); reemplaza al de `RUST_LOG`
+    #[serde(default)]
+    pub logLevel: Option<String>,
+}
+
+fn default_true() -> bool { true }
+
+/// Campos conocidos y su tipo esperado (para errores por campo y avisos de campos desconocidos).
+const FIELDS: &[(&str, FieldKind)] = &[
+    ("killSwitch", FieldKind::Bool),
+    ("pauseCapture", FieldKind::Bool),
+    ("titleCapture", FieldKind::Bool),
+    ("excludeApps", FieldKind::StrList),
+    ("excludePatterns", FieldKind::StrList),
+    ("excludeExePaths", FieldKind::StrList),
+    ("excludeTitleRegex", FieldKind::StrList),
+    ("excludeCaseInsensitive", FieldKind::Bool),
+    ("updateChannel", FieldKind::Str),
+    ("titleSampleHz", FieldKind::U32),
+    ("titleBurstPerMinute", FieldKind::U32),
+    ("focusMinMinutes", FieldKind::U32),
+    ("heartbeatIntervalSecs", FieldKind::U32),
+    ("tlsPins", FieldKind::StrList),
+    ("reportDrops", FieldKind::Bool),
+    ("rules", FieldKind::List),
+    ("redaction", FieldKind::Object),
+    ("logLevel", FieldKind::Str),
+];
+
+#[derive(Clone, Copy)]
+enum FieldKind { Bool, Str, U32, StrList, List, Object }
+
+/// Un problema en un campo: `field` usa notación `excludePatterns[2]`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyIssue {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    /// Impiden aplicar la policy
+    pub errors: Vec<PolicyIssue>,
+    /// Se aplica igual, pero algo no se comportará como está escrito
+    pub warnings: Vec<PolicyIssue>,
+}
+
+impl ValidationReport {
+    pub(crate) fn error(&mut self, field: impl Into<String>, code: &'static str, message: impl Into<String>) {
+        self.errors.push(PolicyIssue { field: field.into(), code, message: message.into() });
+    }
+
+    pub(crate) fn warn(&mut self, field: impl Into<String>, code: &'static str, message: impl Into<String>) {
+        self.warnings.push(PolicyIssue { field: field.into(), code, message: message.into() });
+    }
+
+    /// Resumen en una línea de los errores (para logs y respuestas de rechazo).
+    pub fn summary(&self) -> String {
+        self.errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect::<Vec<_>>().join("; ")
+    }
+}
+
+impl Policy {
+    /// Valida el documento crudo (ya sin la envoltura `{"policy": ...}`).
+    pub fn validate(raw: &serde_json::Value) -> ValidationReport {
+        let mut r = ValidationReport::default();
+        let Some(obj) = raw.as_object() else {
+            r.error("$", "not_an_object", "la policy debe ser un objeto JSON");
+            return r;
+        };
+        for key in obj.keys() {
+            if FIELDS.iter().any(|(f, _)| f == key) { continue; }
+            match FIELDS.iter().find(|(f, _)| f.eq_ignore_ascii_case(key) || edit_distance(f, key) <= 2) {
+                Some((f, _)) => r.warn(key.as_str(), "unknown_field", format!("campo desconocido; ¿quisiste decir '{}'?", f)),
+                None => r.warn(key.as_str(), "unknown_field", "campo desconocido; se ignora"),
+            }
+        }
+        let mut typed = obj.clone();
+        for (name, kind) in FIELDS {
+            let Some(v) = obj.get(*name) else { continue };
+            if v.is_null() { continue; }
+            let ok = match kind {
+                FieldKind::Bool => v.is_boolean(),
+                FieldKind::Str => v.is_string(),
+                FieldKind::U32 => v.as_u64().is_some_and(|n| n <= u32::MAX as u64),
+                FieldKind::StrList => v.as_array().is_some_and(|a| a.iter().all(|x| x.is_string())),
+                FieldKind::List => v.is_array(),
+                FieldKind::Object => v.is_object(),
+            };
+            if !ok {
+                let expected = match kind {
+                    FieldKind::Bool => "booleano",
+                    FieldKind::Str => "texto",
+                    FieldKind::U32 => "entero no negativo",
+                    FieldKind::StrList => "lista de textos",
+                    FieldKind::List => "lista",
+                    FieldKind::Object => "objeto",
+                };
+                r.error(*name, "invalid_type", format!("se esperaba {}", expected));
+                // el resto de chequeos sigue sobre los campos con tipo correcto
+                typed.remove(*name);
+            }
+        }
+        // las reglas se validan una a una (errores en `rules[i]` en lugar de fallar todo el documento)
+        if let Some(rules) = typed.remove("rules").filter(|v| !v.is_null()) {
+            Rule::validate_list(&rules, &mut r);
+        }
+        if let Some(redaction) = typed.remove("redaction").filter(|v| !v.is_null()) {
+            RedactionPolicy::validate(&redaction, &mut r);
+        }
+        let p: Policy = match serde_json::from_value(serde_json::Value::Object(typed)) {
+            Ok(p) => p,
+            Err(e) => {
+                r.error("$", "invalid_policy", e.to_string());
+                return r;
+            }
+        };
+        p.check_semantics(&mut r);
+        r.valid = r.errors.is_empty();
+        r
+    }
+
+    fn check_semantics(&self, r: &mut ValidationReport) {
+        let lists = [
+            ("excludeApps", &self.excludeApps),
+            ("excludePatterns", &self.excludePatterns),
+            ("excludeExePaths", &self.excludeExePaths),
+            ("excludeTitleRegex", &self.excludeTitleRegex),
+        ];
+        for (name, list) in lists {
+            for (i, item) in list.iter().enumerate() {
+                let field = format!("{}[{}]", name, i);
+                if item.trim().is_empty() {
+                    r.warn(field, "empty_entry", "entrada vacía; no excluye nada");
+                    continue;
+                }
+                if list[..i].contains(item) { r.warn(field.clone(), "duplicate", format!("'{}' está repetido", item)); }
+                match name {
+                    "excludeApps" => {}
+                    "excludeTitleRegex" => {
+                        if let Err(e) = regex::Regex::new(item) { r.error(field, "invalid_regex", format!("regex inválida: {}", e)); }
+                    }
+                    _ => {
+                        if let Err(e) = globset::Glob::new(item) { r.error(field, "invalid_glob", format!("glob inválido: {}", e)); }
+                    }
+                }
+            }
+        }
+        for (i, pin) in self.tlsPins.iter().enumerate() {
+            if let Err(e) = crate::http::parse_pin(pin) { r.error(format!("tlsPins[{}]", i), "invalid_pin", e.to_string()); }
+        }
+        match self.titleSampleHz {
+            Some(0) => r.error("titleSampleHz", "out_of_range", "debe ser al menos 1"),
+            Some(hz) if hz > 10 => r.warn("titleSampleHz", "clamped", format!("{} Hz se limita a 10 Hz (un muestreo cada 100 ms)", hz)),
+            _ => {}
+        }
+        if self.titleBurstPerMinute == Some(0) { r.error("titleBurstPerMinute", "out_of_range", "debe ser al menos 1"); }
+        if let Some(s) = self.heartbeatIntervalSecs {
+            if !(15..=3600).contains(&s) { r.warn("heartbeatIntervalSecs", "clamped", format!("{} s se acota a {} s", s, s.clamp(15, 3600))); }
+        }
+        if self.focusMinMinutes == Some(0) { r.warn("focusMinMinutes", "suspicious", "0 cuenta cualquier bloque como foco"); }
+        if let Some(l) = &self.logLevel {
+            if let Err(e) = check_log_filter(l) { r.error("logLevel", "invalid_log_filter", e); }
+            else if l.split(',').any(|d| d.trim().ends_with("trace")) { r.warn("logLevel", "verbose", "nivel trace: logs muy voluminosos"); }
+        }
+        if self.killSwitch { r.warn("killSwitch", "capture_disabled", "killSwitch activo: no se captura ni se envía nada"); }
+    }
+}
+
+const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error", "off"];
+
+/// Chequeo liviano de un filtro estilo `RUST_LOG` (`nivel`, `target`, `target=nivel`, separados por coma).
+pub fn check_log_filter(filter: &str) -> Result<(), String> {
+    if filter.trim().is_empty() { return Err("filtro vacío".into()); }
+    for d in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let level = match d.rsplit_once('=') {
+            Some((target, level)) if !target.is_empty() => level,
+            Some(_) => return Err(format!("'{}': falta el target antes de '='", d)),
+            None if LOG_LEVELS.contains(&d.to_ascii_lowercase().as_str()) => d,
+            // un target solo habilita todos sus niveles
+            None if d.chars().all(|c| c.is_alphanumeric() || "_:-[]{}.".contains(c)) => continue,
+            None => return Err(format!("'{}' no es un nivel ni un target", d)),
+        };
+        if !LOG_LEVELS.contains(&level.to_ascii_lowercase().as_str()) {
+            return Err(format!("'{}' no es un nivel ({})", level, LOG_LEVELS.join(", ")));
+        }
+    }
+    Ok(())
+}
+
+/// Matchers de exclusión precompilados. Se construyen una vez por cambio de policy
+/// (no en cada muestra); patrones inválidos se omiten (`Policy::validate` los reporta).
+#[derive(Debug, Clone)]
+pub struct CompiledPolicy {
+    apps: HashSet<String>,
+    titles: GlobSet,
+    title_regex: RegexSet,
+    exe_paths: GlobSet,
+    case_insensitive: bool,
+    rules: CompiledRules,
+    redactor: Redactor,
+}
+
+impl Default for CompiledPolicy {
+    fn default() -> Self { Self::compile(&Policy::default()) }
+}
+
+impl CompiledPolicy {
+    pub fn compile(p: &Policy) -> Self {
+        let ci = p.excludeCaseInsensitive;
+        let globs = |pats: &[String]| {
+            let mut b = GlobSetBuilder::new();
+            for pat in pats {
+                if let Ok(g) = GlobBuilder::new(pat).case_insensitive(ci).build() { b.add(g); }
+            }
+            b.build().unwrap_or_else(|_| GlobSet::empty())
+        };
+        let valid_regex: Vec<&String> = p.excludeTitleRegex.iter().filter(|r| RegexBuilder::new(r).case_insensitive(ci).build().is_ok()).collect();
+        Self {
+            apps: p.excludeApps.iter().map(|a| if ci { a.to_lowercase() } else { a.clone() }).collect(),
+            titles: globs(&p.excludePatterns),
+            title_regex: RegexSetBuilder::new(valid_regex).case_insensitive(ci).build().unwrap_or_else(|_| RegexSet::empty()),
+            exe_paths: globs(&p.excludeExePaths),
+            case_insensitive: ci,
+            rules: CompiledRules::compile(&p.rules),
+            redactor: Redactor::compile(&p.redaction),
+        }
+    }
+
+    pub fn excludes_app(&self, app: &str) -> bool {
+        if self.apps.is_empty() { return false; }
+        if self.case_insensitive { self.apps.contains(&app.to_lowercase()) } else { self.apps.contains(app) }
+    }
+
+    pub fn excludes_title(&self, title: &str) -> bool {
+        self.titles.is_match(title) || self.title_regex.is_match(title)
+    }
+
+    /// Hay reglas por ejecutable (evita resolver la identidad del proceso si no hacen falta).
+    pub fn has_exe_rules(&self) -> bool { !self.exe_paths.is_empty() }
+
+    pub fn excludes_exe(&self, exe: &str) -> bool { self.exe_paths.is_match(exe) }
+
+    pub fn has_rules(&self) -> bool { !self.rules.is_empty() }
+
+    /// Primera regla que coincide con la muestra (`None` si ninguna).
+    pub fn match_rule(&self, app: &str, title: &str, at: Moment) -> Option<RuleHit<'_>> { self.rules.evaluate(app, title, at) }
+
+    /// Título listo para persistir: datos personales enmascarados según `redaction`.
+    pub fn redact<'a>(&self, title: &'a str, key: &TitleKey) -> Cow<'a, str> { self.redactor.redact(title, key) }
+}
+
+/// Distancia de Levenshtein (para sugerir el campo correcto ante un typo).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            cur[j + 1] = (prev[j] + (ca != *cb) as usize).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
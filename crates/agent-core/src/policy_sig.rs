@@ -0,0 +1,271 @@
+// Firmas Ed25519 desacopladas para documentos de policy.
+// Lo firmado es la forma canónica de la policy: JSON compacto con claves ordenadas
+// (lo que produce `serde_json` para un `Value`, o en Python
+// `json.dumps(p, sort_keys=True, separators=(",", ":"), ensure_ascii=False)`).
+use crate::paths::Paths;
+use crate::policy::{Policy, PolicyIssue};
+use anyhow::Result;
+use base64::Engine;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+/// Claves embebidas en el binario en tiempo de compilación:
+/// `RIPOR_POLICY_PUBKEYS="keyId=<base64>,otra=<base64>" cargo build ...`
+const EMBEDDED_KEYS: Option<&str> = option_env!("RIPOR_POLICY_PUBKEYS");
+
+/// Modo de verificación fijado al compilar (`RIPOR_POLICY_SIGNATURE=off|warn|enforce`; por defecto
+/// `enforce` si hay claves embebidas y `warn` si no). No hay forma de cambiarlo en tiempo de
+/// ejecución: ni env ni archivos del data dir.
+const EMBEDDED_MODE: Option<&str> = option_env!("RIPOR_POLICY_SIGNATURE");
+
+/// Variables que versiones anteriores leían en tiempo de ejecución; ahora se ignoran.
+const IGNORED_ENV: &[&str] = &["POLICY_SIGNATURE", "POLICY_PUBKEYS"];
+
+/// Firma de una policy: `{"keyId": "...", "sig": "<base64>"}` en la envoltura o
+/// `X-Policy-Signature: <keyId>:<base64>` en la respuesta del backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicySignature {
+    #[serde(default)]
+    pub key_id: Option<String>,
+    pub sig: String,
+}
+
+impl PolicySignature {
+    pub fn from_header(v: &str) -> Option<Self> {
+        let v = v.trim();
+        if v.is_empty() { return None; }
+        Some(match v.split_once(':') {
+            Some((kid, sig)) => Self { key_id: Some(kid.trim().to_string()), sig: sig.trim().to_string() },
+            None => Self { key_id: None, sig: v.to_string() },
+        })
+    }
+}
+
+/// Clave pública de confianza (`publicKey` en base64, 32 bytes).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedKey {
+    pub key_id: String,
+    pub public_key: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureMode {
+    /// No se verifica
+    Off,
+    /// Se verifica y se informa, pero se aplica igual
+    Warn,
+    /// Policies sin firma o con firma inválida se rechazan
+    Enforce,
+}
+
+impl SignatureMode {
+    /// Modo con el que se compiló el binario.
+    pub fn compiled() -> Self {
+        Self::for_build(EMBEDDED_MODE, EMBEDDED_KEYS)
+    }
+
+    /// Modo para `RIPOR_POLICY_SIGNATURE` / `RIPOR_POLICY_PUBKEYS`. Sin modo explícito solo se exige
+    /// firma si el binario trae claves: un build sin claves rechazaría toda policy y no capturaría
+    /// nada. Un valor no reconocido cuenta como `enforce`.
+    pub fn for_build(mode: Option<&str>, embedded_keys: Option<&str>) -> Self {
+        match mode.map(str::trim) {
+            Some("off") => SignatureMode::Off,
+            Some("warn") => SignatureMode::Warn,
+            None if embedded_keys.unwrap_or_default().trim().is_empty() => SignatureMode::Warn,
+            _ => SignatureMode::Enforce,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SigStatus {
+    Valid { key_id: String },
+    Unsigned,
+    UnknownKey { key_id: String },
+    Invalid { reason: String },
+    /// Verificación desactivada en este binario (`RIPOR_POLICY_SIGNATURE=off`)
+    NotChecked,
+}
+
+impl SigStatus {
+    pub fn is_valid(&self) -> bool { matches!(self, SigStatus::Valid { .. }) }
+}
+
+/// Claves de confianza: las embebidas en el binario y las recibidas al enrolar (`policy_keys.json`).
+/// En `enforce` sin ninguna clave se rechaza toda policy.
+pub struct TrustStore {
+    keys: Vec<(String, VerifyingKey)>,
+    pub mode: SignatureMode,
+}
+
+impl TrustStore {
+    /// Claves y modo explícitos (herramientas y tests); las claves inválidas se ignoran.
+    pub fn new(keys: &[TrustedKey], mode: SignatureMode) -> Self {
+        let mut st = Self { keys: Vec::new(), mode };
+        for k in keys { st.add(&k.key_id, &k.public_key, "explicit"); }
+        st
+    }
+
+    pub fn load(paths: &Paths) -> Self {
+        for var in IGNORED_ENV.iter().filter(|v| std::env::var_os(v).is_some()) {
+            tracing::warn!(var, "variable ignorada: el modo de firma y las claves de policy se fijan al compilar o al enrolar");
+        }
+        let mut st = Self { keys: Vec::new(), mode: SignatureMode::compiled() };
+        for item in EMBEDDED_KEYS.unwrap_or_default().split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (kid, b64) = item.split_once('=').filter(|(k, _)| !k.is_empty() && !k.contains('/')).unwrap_or(("default", item));
+            st.add(kid, b64, "embedded");
+        }
+        for k in load_provisioned(paths) { st.add(&k.key_id, &k.public_key, "enrollment"); }
+        if st.mode == SignatureMode::Enforce && st.keys.is_empty() {
+            tracing::warn!("sin claves de firma de policy: se rechaza toda policy hasta enrolar");
+        }
+        st
+    }
+
+    fn add(&mut self, kid: &str, b64: &str, origin: &str) {
+        match parse_key(b64) {
+            Some(k) => self.keys.push((kid.to_string(), k)),
+            None => tracing::warn!(key_id=%kid, origin, "clave pública de policy inválida; ignorada"),
+        }
+    }
+
+    pub fn key_ids(&self) -> Vec<String> { self.keys.iter().map(|(k, _)| k.clone()).collect() }
+
+    pub fn verify(&self, policy: &serde_json::Value, sig: Option<&PolicySignature>) -> SigStatus {
+        if self.mode == SignatureMode::Off { return SigStatus::NotChecked; }
+        let Some(sig) = sig else { return SigStatus::Unsigned };
+        self.verify_bytes(&canonical_bytes(policy), sig)
+    }
+
+    /// Firma sobre bytes arbitrarios (también comandos remotos). No depende del modo.
+    pub fn verify_bytes(&self, msg: &[u8], sig: &PolicySignature) -> SigStatus {
+        let raw = match base64::engine::general_purpose::STANDARD.decode(sig.sig.trim()) {
+            Ok(r) => r,
+            Err(_) => return SigStatus::Invalid { reason: "firma no es base64".into() },
+        };
+        let Ok(signature) = Signature::from_slice(&raw) else {
+            return SigStatus::Invalid { reason: "firma de longitud inválida".into() };
+        };
+        let candidates: Vec<&(String, VerifyingKey)> = match &sig.key_id {
+            Some(kid) => self.keys.iter().filter(|(k, _)| k == kid).collect(),
+            None => self.keys.iter().collect(),
+        };
+        if candidates.is_empty() {
+            return SigStatus::UnknownKey { key_id: sig.key_id.clone().unwrap_or_default() };
+        }
+        match candidates.iter().find(|(_, k)| k.verify(msg, &signature).is_ok()) {
+            Some((kid, _)) => SigStatus::Valid { key_id: kid.clone() },
+            None => SigStatus::Invalid { reason: "firma no coincide".into() },
+        }
+    }
+
+    /// Verifica y decide: `Err(motivo)` si la policy debe rechazarse según el modo.
+    pub fn check(&self, policy: &serde_json::Value, sig: Option<&PolicySignature>) -> (SigStatus, Result<(), String>) {
+        let status = self.verify(policy, sig);
+        let verdict = match (&status, self.mode) {
+            (SigStatus::Valid { .. } | SigStatus::NotChecked, _) | (_, SignatureMode::Off | SignatureMode::Warn) => Ok(()),
+            (SigStatus::Unsigned, _) => Err("policy sin firma".to_string()),
+            (SigStatus::UnknownKey { key_id }, _) => Err(format!("firma con clave desconocida '{}'", key_id)),
+            (SigStatus::Invalid { reason }, _) => Err(format!("firma inválida: {}", reason)),
+        };
+        (status, verdict)
+    }
+
+    /// Verifica la firma de `raw`, lo valida y lo deserializa. Se rechaza si la firma no cumple
+    /// el modo o si la validación da errores; los avisos acompañan a la policy aceptada.
+    pub fn admit(&self, raw: &serde_json::Value, sig: Option<&PolicySignature>) -> (SigStatus, Result<Admitted, Rejected>) {
+        let (status, verdict) = self.check(raw, sig);
+        let res = verdict.map_err(|reason| Rejected { reason, errors: Vec::new() }).and_then(|_| {
+            let report = Policy::validate(raw);
+            if !report.valid {
+                return Err(Rejected { reason: format!("policy inválida: {}", report.summary()), errors: report.errors });
+            }
+            serde_json::from_value::<Policy>(raw.clone())
+                .map(|policy| Admitted { policy, warnings: report.warnings })
+                .map_err(|e| Rejected { reason: format!("parse failed: {}", e), errors: Vec::new() })
+        });
+        (status, res)
+    }
+}
+
+/// Policy aceptada por [`TrustStore::admit`], con los avisos de validación.
+#[derive(Debug)]
+pub struct Admitted {
+    pub policy: Policy,
+    pub warnings: Vec<PolicyIssue>,
+}
+
+/// Policy rechazada: motivo (firma o validación) y errores por campo si los hay.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejected {
+    pub reason: String,
+    pub errors: Vec<PolicyIssue>,
+}
+
+/// `policy.json` con la firma guardada en `policy_meta.json`, solo si pasa la verificación y la
+/// validación. Para que la CLI use la misma configuración derivada de la policy que el daemon.
+pub fn load_verified_policy(paths: &Paths) -> Option<Policy> {
+    let read = |p: std::path::PathBuf| std::fs::read_to_string(p).ok().and_then(|txt| serde_json::from_str::<serde_json::Value>(&txt).ok());
+    let raw = read(paths.policy_file())?;
+    let sig = read(paths.policy_meta_file())
+        .and_then(|m| m.get("signature").cloned())
+        .and_then(|s| serde_json::from_value::<PolicySignature>(s).ok());
+    TrustStore::load(paths).admit(&raw, sig.as_ref()).1.ok().map(|a| a.policy)
+}
+
+/// Bytes firmados: JSON compacto con claves ordenadas.
+pub fn canonical_bytes(policy: &serde_json::Value) -> Vec<u8> {
+    // `serde_json::Map` ordena las claves (sin la feature `preserve_order`)
+    serde_json::to_vec(policy).unwrap_or_default()
+}
+
+fn parse_key(b64: &str) -> Option<VerifyingKey> {
+    let raw = base64::engine::general_purpose::STANDARD.decode(b64.trim()).ok()?;
+    VerifyingKey::from_bytes(&raw.try_into().ok()?).ok()
+}
+
+/// Claves de `policy_keys.json`. Solo las escribe el enrolamiento (0600, como `secrets.json`); si el
+/// archivo lo puede modificar otro usuario, no se confía en él.
+fn load_provisioned(paths: &Paths) -> Vec<TrustedKey> {
+    let f = paths.policy_keys_file();
+    if !f.exists() { return Vec::new(); }
+    if let Err(why) = check_private(&f) {
+        tracing::warn!(file=%f.display(), why, "claves de policy provisionadas ignoradas");
+        return Vec::new();
+    }
+    std::fs::read_to_string(&f)
+        .ok()
+        .and_then(|txt| serde_json::from_str(&txt).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn check_private(f: &std::path::Path) -> std::result::Result<(), &'static str> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = std::fs::symlink_metadata(f).map_err(|_| "no se pudo leer")?;
+    let dir = f.parent().and_then(|d| std::fs::metadata(d).ok()).ok_or("no se pudo leer el data dir")?;
+    if !meta.file_type().is_file() { return Err("no es un archivo regular"); }
+    if meta.uid() != dir.uid() { return Err("su dueño no es el del data dir"); }
+    if meta.mode() & 0o022 != 0 { return Err("escribible por grupo u otros"); }
+    Ok(())
+}
+
+// En Windows el data dir vive en el perfil del usuario, con su ACL heredada.
+#[cfg(not(unix))]
+fn check_private(_f: &std::path::Path) -> std::result::Result<(), &'static str> { Ok(()) }
+
+/// Guarda las claves entregadas por el backend al enrolar.
+pub fn save_provisioned(paths: &Paths, keys: &[TrustedKey]) -> Result<()> {
+    let f = paths.policy_keys_file();
+    crate::paths::ensure_parent(&f)?;
+    std::fs::write(&f, serde_json::to_vec_pretty(keys)?)?;
+    #[cfg(unix)] {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&f, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+// Configuración de proxy hacia el backend: explícita (env/.env), PAC o del sistema.
+// Un PAC que no se puede usar (falta el archivo, no carga, binario sin la feature `pac`) no
+// impide arrancar: se vuelve a `system` con un aviso, visible en `/state` → `proxy.fallback`.
+use crate::pac::PacScript;
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// Lo que detecte reqwest: HTTP(S)_PROXY/ALL_PROXY y, si no hay, el registro en Windows o
+    /// SystemConfiguration en macOS (reqwest 0.11 los lee sin features; 0.12 pide `macos-system-configuration`)
+    System,
+    /// Sin proxy, incluso si el entorno define uno
+    None,
+    /// `PROXY_URL` explícito
+    Manual,
+    /// `FindProxyForURL` de `PROXY_PAC_FILE`
+    Pac,
+}
+
+/// Variables soportadas:
+/// - `PROXY_MODE`: `system` | `none` | `manual` | `pac` (por defecto `manual` si hay `PROXY_URL`,
+///   `pac` si hay `PROXY_PAC_FILE`, si no `system`)
+/// - `PROXY_URL`: `http://`, `https://`, `socks5://` o `socks5h://` host:puerto
+/// - `PROXY_USERNAME` / `PROXY_PASSWORD`: credenciales (alternativa a incluirlas en la URL)
+/// - `NO_PROXY`: hosts/dominios/IPs/CIDR separados por coma que van directos
+/// - `PROXY_PAC_FILE`: ruta a un archivo PAC local (feature `pac`)
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProxySettings {
+    pub mode: ProxyMode,
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub no_proxy: Option<String>,
+    pub pac: Option<Arc<PacScript>>,
+    /// Por qué no se aplica el modo configurado (se usa `system`)
+    pub fallback: Option<String>,
+}
+
+impl Default for ProxySettings {
+    fn default() -> Self {
+        Self { mode: ProxyMode::System, url: None, username: None, password: None, no_proxy: None, pac: None, fallback: None }
+    }
+}
+
+// Sin credenciales: la configuración termina en logs y en el bundle de diagnóstico.
+impl std::fmt::Debug for ProxySettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxySettings")
+            .field("mode", &self.mode)
+            .field("url", &self.url.as_deref().map(mask_credentials))
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("no_proxy", &self.no_proxy)
+            .field("pac", &self.pac.as_ref().map(|p| p.path()))
+            .field("fallback", &self.fallback)
+            .finish()
+    }
+}
+
+impl ProxySettings {
+    pub fn from_env() -> Result<Self> {
+        let var = |k: &str| std::env::var(k).ok().filter(|v| !v.trim().is_empty());
+        let url = var("PROXY_URL");
+        let pac_file = var("PROXY_PAC_FILE").map(PathBuf::from);
+        let mode = match var("PROXY_MODE").map(|m| m.trim().to_ascii_lowercase()).as_deref() {
+            Some("none") | Some("direct") => ProxyMode::None,
+            Some("manual") => ProxyMode::Manual,
+            Some("system") => ProxyMode::System,
+            Some("pac") => ProxyMode::Pac,
+            Some(other) => bail!("PROXY_MODE '{}' inválido (system, none, manual o pac)", other),
+            None if url.is_some() => ProxyMode::Manual,
+            None if pac_file.is_some() => ProxyMode::Pac,
+            None => ProxyMode::System,
+        };
+        let mut s = Self {
+            mode,
+            url,
+            username: var("PROXY_USERNAME"),
+            password: var("PROXY_PASSWORD"),
+            no_proxy: var("NO_PROXY").or_else(|| var("no_proxy")),
+            pac: None,
+            fallback: None,
+        };
+        if s.mode == ProxyMode::Pac {
+            match pac_file.as_deref().map(PacScript::load) {
+                Some(Ok(pac)) => s.pac = Some(Arc::new(pac)),
+                Some(Err(e)) => s.fall_back(format!("PAC {} no usable: {}", pac_file.unwrap_or_default().display(), e)),
+                None => s.fall_back("PROXY_MODE=pac sin PROXY_PAC_FILE".into()),
+            }
+        }
+        Ok(s)
+    }
+
+    fn fall_back(&mut self, reason: String) {
+        tracing::warn!(%reason, "proxy: se usa la configuración del sistema");
+        self.mode = ProxyMode::System;
+        self.fallback = Some(reason);
+    }
+
+    /// Aplica la configuración al builder de reqwest.
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder> {
+        Ok(match self.mode {
+            ProxyMode::System => builder,
+            ProxyMode::None => builder.no_proxy(),
+            ProxyMode::Manual => builder.proxy(self.manual_proxy()?),
+            ProxyMode::Pac => builder.proxy(self.pac_proxy()?),
+        })
+    }
+
+    fn manual_proxy(&self) -> Result<reqwest::Proxy> {
+        let url = self.url.as_deref().ok_or_else(|| anyhow!("PROXY_MODE=manual requiere PROXY_URL"))?;
+        let mut p = reqwest::Proxy::all(url).map_err(|e| anyhow!("PROXY_URL inválido: {}", e))?;
+        if let Some(user) = &self.username {
+            p = p.basic_auth(user, self.password.as_deref().unwrap_or(""));
+        }
+        Ok(p.no_proxy(self.no_proxy.as_deref().and_then(reqwest::NoProxy::from_string)))
+    }
+
+    /// Resultado del PAC en caché para cada conexión, sin esperar al script (reqwest llama a este
+    /// closure en el runtime). Si el script falla o aún no se evaluó ese destino, directo.
+    fn pac_proxy(&self) -> Result<reqwest::Proxy> {
+        let pac = self.pac.clone().ok_or_else(|| anyhow!("PROXY_MODE=pac sin PAC cargado"))?;
+        let no_proxy = self.no_proxy.clone();
+        let mut p = reqwest::Proxy::custom(move |u| {
+            if bypass(no_proxy.as_deref(), u.host_str().unwrap_or_default()) { return None; }
+            match pac.cached_route(u) {
+                Some(Ok(via)) => via.and_then(|v| reqwest::Url::parse(&v).ok()),
+                Some(Err(e)) => {
+                    tracing::warn!(error=%e, host=u.host_str().unwrap_or_default(), "PAC falló; conexión directa");
+                    None
+                }
+                None => {
+                    tracing::debug!(host=u.host_str().unwrap_or_default(), "PAC aún sin evaluar para el destino; conexión directa");
+                    None
+                }
+            }
+        });
+        if let Some(user) = &self.username {
+            p = p.basic_auth(user, self.password.as_deref().unwrap_or(""));
+        }
+        Ok(p)
+    }
+
+    /// Evalúa el PAC para `target` y guarda el resultado, para que las conexiones no salgan
+    /// directas mientras tanto. Bloquea: llamar fuera del runtime. Sin PAC no hace nada.
+    pub fn prime(&self, target: &str) {
+        let (Some(pac), Ok(url)) = (&self.pac, reqwest::Url::parse(target)) else { return };
+        if let Err(e) = pac.route(&url) {
+            tracing::warn!(error=%e, target, "PAC falló al evaluar el backend; conexión directa");
+        }
+    }
+
+    /// Ruta efectiva (para `/state`) hacia `target`, sin credenciales.
+    pub fn route_for(&self, target: &str) -> ProxyRoute {
+        let host = reqwest::Url::parse(target).ok().and_then(|u| u.host_str().map(|h| h.to_string())).unwrap_or_default();
+        let mut r = ProxyRoute { mode: self.mode.clone(), target: target.to_string(), via: None, source: String::new(), error: None, fallback: self.fallback.clone() };
+        match self.mode {
+            ProxyMode::None => r.source = "PROXY_MODE=none".into(),
+            ProxyMode::Manual => {
+                r.source = "PROXY_URL".into();
+                if !bypass(self.no_proxy.as_deref(), &host) { r.via = self.url.as_deref().map(mask_credentials); }
+                if let Err(e) = self.manual_proxy() { r.error = Some(e.to_string()); }
+            }
+            ProxyMode::Pac => {
+                let Some(pac) = &self.pac else {
+                    r.error = Some("PROXY_MODE=pac sin PAC cargado".into());
+                    return r;
+                };
+                r.source = pac.path().display().to_string();
+                if bypass(self.no_proxy.as_deref(), &host) { return r; }
+                // `/state` tampoco espera al script: muestra lo que usan las conexiones
+                match reqwest::Url::parse(target).map_err(|e| anyhow!(e)).map(|u| pac.cached_route(&u)) {
+                    Ok(Some(Ok(via))) => r.via = via.as_deref().map(mask_credentials),
+                    Ok(Some(Err(e))) | Err(e) => r.error = Some(e.to_string()),
+                    Ok(None) => r.error = Some("PAC aún sin evaluar para este destino".into()),
+                }
+            }
+            ProxyMode::System => {
+                // Aproximación: solo variables de entorno. El proxy del registro (Windows) o de
+                // SystemConfiguration (macOS), que reqwest sí usa, no se refleja aquí.
+                let scheme_var = if target.starts_with("https") { "HTTPS_PROXY" } else { "HTTP_PROXY" };
+                let env = [scheme_var, &scheme_var.to_ascii_lowercase(), "ALL_PROXY", "all_proxy"]
+                    .iter()
+                    .find_map(|k| std::env::var(k).ok().filter(|v| !v.is_empty()).map(|v| (k.to_string(), v)));
+                match env {
+                    Some((k, v)) => {
+                        r.source = k;
+                        if !bypass(self.no_proxy.as_deref(), &host) { r.via = Some(mask_credentials(&v)); }
+                    }
+                    None => r.source = "system".into(),
+                }
+            }
+        }
+        r
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProxyRoute {
+    pub mode: ProxyMode,
+    pub target: String,
+    /// `None` = conexión directa
+    pub via: Option<String>,
+    pub source: String,
+    pub error: Option<String>,
+    /// Modo configurado que no se pudo aplicar y por qué (se usa `system`)
+    pub fallback: Option<String>,
+}
+
+fn mask_credentials(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut u) if !u.username().is_empty() => {
+            let _ = u.set_username("***");
+            let _ = u.set_password(None);
+            u.to_string()
+        }
+        _ => url.to_string(),
+    }
+}
+
+// Semántica NO_PROXY de reqwest: `*`, host exacto, sufijo de dominio (`.corp` o `corp`), IP literal o CIDR.
+fn bypass(no_proxy: Option<&str>, host: &str) -> bool {
+    let Some(list) = no_proxy else { return false };
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    let ip = host.parse::<IpAddr>().ok();
+    list.split(',').map(|e| e.trim().to_ascii_lowercase()).filter(|e| !e.is_empty()).any(|e| {
+        if e == "*" { return true; }
+        if let Some(ip) = ip {
+            return match e.split_once('/') {
+                Some((net, bits)) => in_cidr(ip, net, bits),
+                None => e.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok() == Some(ip),
+            };
+        }
+        let e = e.trim_start_matches("*.");
+        let dom = e.trim_start_matches('.');
+        host == dom || host.ends_with(&format!(".{}", dom))
+    })
+}
+
+fn in_cidr(ip: IpAddr, net: &str, bits: &str) -> bool {
+    let (Ok(net), Ok(bits)) = (net.parse::<IpAddr>(), bits.parse::<u32>()) else { return false };
+    let prefix = |a: u128, b: u128, width: u32| bits <= width && (bits == 0 || (a ^ b) >> (width - bits) == 0);
+    match (ip, net) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => prefix(u32::from(a) as u128, u32::from(b) as u128, 32),
+        (IpAddr::V6(a), IpAddr::V6(b)) => prefix(u128::from(a), u128::from(b), 128),
+        _ => false,
+    }
+}
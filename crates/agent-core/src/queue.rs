@@ -1,9 +1,11 @@
-use crate::crypto::{decrypt_decompress, encrypt_compress, load_or_create_key};
+use crate::crypto::{decrypt_decompress, encrypt_compress, load_key, load_or_create_key};
 use crate::paths::Paths;
 use crate::state::AgentState;
-use anyhow::Result;
-use rusqlite::{params, Connection};
-use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub struct Queue {
     conn: Connection,
@@ -15,8 +17,8 @@ impl Queue {
     pub fn open(paths: &Paths, state: &AgentState) -> Result<Self> {
         let key = load_or_create_key(paths)?;
         let conn = Connection::open(paths.queue_db())?;
-        conn.pragma_update(None, "journal_mode", &"WAL")?;
-        conn.pragma_update(None, "synchronous", &"NORMAL")?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
         conn.execute_batch(
             "CREATE TABLE IF NOT EXISTS events (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -50,6 +52,12 @@ impl Queue {
         Ok(cnt)
     }
 
+    /// `created_at` (epoch ms) del evento más antiguo pendiente
+    pub fn oldest_created_at(&self) -> Result<Option<u64>> {
+        let ts: Option<i64> = self.conn.query_row("SELECT MIN(created_at) FROM events", [], |row| row.get(0))?;
+        Ok(ts.map(|t| t as u64))
+    }
+
     pub fn fetch_batch(&self, limit: usize) -> Result<Vec<(i64, Vec<u8>)>> {
         let mut stmt = self
             .conn
@@ -119,6 +127,92 @@ impl Queue {
     }
 }
 
+/// Conexión de solo lectura a `queue.sqlite` para procesos que no son el daemon (CLI, diagnóstico).
+/// No cambia `journal_mode`, no crea tablas ni hace checkpoint: es un lector WAL más y no bloquea
+/// al daemon si está corriendo.
+pub fn open_read_only(paths: &Paths) -> Result<Connection> {
+    let db = paths.queue_db();
+    if !db.exists() { return Err(anyhow!("no existe {}", db.display())); }
+    let conn = Connection::open_with_flags(&db, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    conn.busy_timeout(Duration::from_secs(2))?;
+    Ok(conn)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub events: i64,
+    pub oldest_created_ms: Option<u64>,
+    pub newest_created_ms: Option<u64>,
+    pub payload_bytes: u64,
+    pub max_attempts: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedEvent {
+    pub id: i64,
+    pub created_at: u64,
+    pub attempts: i64,
+    /// Tamaño cifrado
+    pub bytes: usize,
+    /// Evento descifrado; solo con `QueueReader::with_key`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<serde_json::Value>,
+}
+
+/// Lectura de la cola sin el daemon (ver `open_read_only`). Sin `with_key` no descifra nada.
+pub struct QueueReader {
+    conn: Connection,
+    key: Option<([u8; 32], Vec<u8>)>,
+}
+
+impl QueueReader {
+    pub fn open(paths: &Paths) -> Result<Self> {
+        Ok(Self { conn: open_read_only(paths)?, key: None })
+    }
+
+    /// Habilita el descifrado con `key.bin` y el device_id de `agent_state.json` (nunca los crea).
+    pub fn with_key(mut self, paths: &Paths) -> Result<Self> {
+        let key = load_key(paths)?.ok_or_else(|| anyhow!("no existe {}", paths.key_file().display()))?;
+        let state = AgentState::load(paths)?.ok_or_else(|| anyhow!("no existe {}", paths.state_file().display()))?;
+        self.key = Some((key, state.device_id.into_bytes()));
+        Ok(self)
+    }
+
+    pub fn stats(&self) -> Result<QueueStats> {
+        let (events, oldest, newest, bytes, max_attempts): (i64, Option<i64>, Option<i64>, Option<i64>, Option<i64>) = self.conn.query_row(
+            "SELECT COUNT(1), MIN(created_at), MAX(created_at), SUM(LENGTH(payload)), MAX(attempts) FROM events",
+            [],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)),
+        )?;
+        Ok(QueueStats {
+            events,
+            oldest_created_ms: oldest.map(|t| t as u64),
+            newest_created_ms: newest.map(|t| t as u64),
+            payload_bytes: bytes.unwrap_or(0) as u64,
+            max_attempts,
+        })
+    }
+
+    /// Más recientes primero.
+    pub fn list_desc(&self, limit: usize) -> Result<Vec<QueuedEvent>> {
+        let mut stmt = self.conn.prepare("SELECT id, created_at, attempts, payload FROM events ORDER BY created_at DESC, id DESC LIMIT ?1")?;
+        let rows = stmt.query_map([limit as i64], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, i64>(2)?, r.get::<_, Vec<u8>>(3)?)))?;
+        let mut out = Vec::new();
+        for r in rows {
+            let (id, created_at, attempts, blob) = r?;
+            let event = match &self.key {
+                Some((key, aad)) => {
+                    let plain = decrypt_decompress(key, aad, &blob)?;
+                    Some(serde_json::from_slice(&plain).unwrap_or_else(|_| serde_json::json!({"raw": base64::engine::general_purpose::STANDARD.encode(&plain)})))
+                }
+                None => None,
+            };
+            out.push(QueuedEvent { id, created_at: created_at as u64, attempts, bytes: blob.len(), event });
+        }
+        Ok(out)
+    }
+}
+
 fn now_ms() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -0,0 +1,141 @@
+// Redacción de títulos antes de persistir (cola, bloques de foco, registro de descartes).
+// Se enmascaran datos personales reconocibles (emails, teléfonos, números de tarjeta o
+// cuentas, rutas bajo el home) y las regex que agregue la policy. En modo `hash` cada
+// coincidencia se reemplaza por un HMAC con la clave del dispositivo: el mismo valor da
+// siempre el mismo token, así que se puede agrupar sin ver el texto.
+use crate::paths::Paths;
+use crate::policy::ValidationReport;
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::borrow::Cow;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Detectores incorporados, en el orden en que se aplican (las rutas primero: pueden
+/// contener emails o dígitos; las tarjetas antes que las series de dígitos).
+const BUILTINS: &[(&str, &str)] = &[
+    ("home_path", r"(?i)(?:[a-z]:\\(?:users|documents and settings)\\[^\\\s]+(?:\\[^\\\s]+)*\\?|/(?:Users|home)/[^/\s]+(?:/[^/\s]+)*/?|~/[^/\s]+(?:/[^/\s]+)*/?)"),
+    ("email", r"(?i)\b[a-z0-9._%+-]+@[a-z0-9](?:[a-z0-9-]*[a-z0-9])?(?:\.[a-z0-9](?:[a-z0-9-]*[a-z0-9])?)*\.[a-z]{2,}\b"),
+    ("card", r"\b\d(?:[ -]?\d){12,18}\b"),
+    ("phone", r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{2,4}\)\s?|\b\d{2,4}[\s.-])\d{3,4}[\s.-]\d{3,4}\b|\+\d{8,15}\b"),
+    ("digits", r"\b\d{8,}\b"),
+];
+
+/// `redaction` en la policy. Por defecto activa con todos los detectores.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactionPolicy {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Subconjunto de detectores (`email`, `phone`, `card`, `digits`, `home_path`); ausente = todos
+    #[serde(default)]
+    pub builtins: Option<Vec<String>>,
+    /// Regex adicionales; cada coincidencia se reemplaza por `[redacted]`
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Reemplazar por `[tipo:hmac]` en lugar de `[tipo]`
+    #[serde(default)]
+    pub hash: bool,
+}
+
+fn default_true() -> bool { true }
+
+impl Default for RedactionPolicy {
+    fn default() -> Self { Self { enabled: true, builtins: None, patterns: Vec::new(), hash: false } }
+}
+
+const REDACTION_FIELDS: &[&str] = &["enabled", "builtins", "patterns", "hash"];
+
+impl RedactionPolicy {
+    pub(crate) fn validate(raw: &serde_json::Value, r: &mut ValidationReport) {
+        if let Some(obj) = raw.as_object() {
+            for key in obj.keys().filter(|k| !REDACTION_FIELDS.contains(&k.as_str())) {
+                r.warn(format!("redaction.{}", key), "unknown_field", "campo desconocido; se ignora");
+            }
+        }
+        let p: RedactionPolicy = match serde_json::from_value(raw.clone()) {
+            Ok(p) => p,
+            Err(e) => return r.error("redaction", "invalid_type", e.to_string()),
+        };
+        for (i, b) in p.builtins.iter().flatten().enumerate() {
+            if !BUILTINS.iter().any(|(name, _)| name == b) {
+                let names = BUILTINS.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ");
+                r.error(format!("redaction.builtins[{}]", i), "unknown_builtin", format!("'{}' no existe ({})", b, names));
+            }
+        }
+        for (i, pat) in p.patterns.iter().enumerate() {
+            if let Err(e) = Regex::new(pat) { r.error(format!("redaction.patterns[{}]", i), "invalid_regex", format!("regex inválida: {}", e)); }
+        }
+        if !p.enabled { r.warn("redaction.enabled", "redaction_disabled", "los títulos se guardan sin enmascarar datos personales"); }
+    }
+}
+
+/// Clave para los hashes de títulos, derivada de la clave local del dispositivo
+/// (no sale del equipo; distinta por dispositivo).
+#[derive(Clone)]
+pub struct TitleKey([u8; 32]);
+
+impl TitleKey {
+    pub fn load(paths: &Paths) -> Result<Self> {
+        let device = crate::crypto::load_or_create_key(paths)?;
+        let mut mac = HmacSha256::new_from_slice(&device).expect("hmac acepta cualquier largo");
+        mac.update(b"riporagent/title-hash/v1");
+        Ok(Self(mac.finalize().into_bytes().into()))
+    }
+
+    pub fn from_bytes(key: [u8; 32]) -> Self { Self(key) }
+
+    /// Token que reemplaza un título completo (acción `hash_title`): `hmac:` + 32 hex.
+    pub fn title_token(&self, title: &str) -> String { format!("hmac:{}", self.hash(title, 16)) }
+
+    /// HMAC-SHA256 en hex truncado a `bytes`.
+    pub fn hash(&self, text: &str, bytes: usize) -> String {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("hmac acepta cualquier largo");
+        mac.update(text.as_bytes());
+        hex::encode(&mac.finalize().into_bytes()[..bytes.min(32)])
+    }
+}
+
+impl std::fmt::Debug for TitleKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("TitleKey(..)") }
+}
+
+/// Pipeline compilado una vez por cambio de policy.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    /// (etiqueta, regex) en orden de aplicación
+    stages: Vec<(&'static str, Regex)>,
+    hash: bool,
+}
+
+impl Redactor {
+    pub fn compile(p: &RedactionPolicy) -> Self {
+        if !p.enabled { return Self::default(); }
+        let mut stages: Vec<(&'static str, Regex)> = BUILTINS
+            .iter()
+            .filter(|(name, _)| p.builtins.as_ref().is_none_or(|b| b.iter().any(|x| x == name)))
+            .map(|(name, re)| (*name, Regex::new(re).expect("regex incorporada")))
+            .collect();
+        // las de la policy van primero: suelen ser más específicas (códigos internos, nombres de clientes)
+        let custom: Vec<(&'static str, Regex)> = p.patterns.iter().filter_map(|pat| Regex::new(pat).ok()).map(|re| ("redacted", re)).collect();
+        stages.splice(0..0, custom);
+        Self { stages, hash: p.hash }
+    }
+
+    pub fn is_empty(&self) -> bool { self.stages.is_empty() }
+
+    pub fn redact<'a>(&self, title: &'a str, key: &TitleKey) -> Cow<'a, str> {
+        let mut out = Cow::Borrowed(title);
+        for (label, re) in &self.stages {
+            if !re.is_match(&out) { continue; }
+            let replaced = re.replace_all(&out, |c: &regex::Captures| {
+                if self.hash { format!("[{}:{}]", label, key.hash(&c[0], 4)) } else { format!("[{}]", label) }
+            });
+            out = Cow::Owned(replaced.into_owned());
+        }
+        out
+    }
+}
@@ -0,0 +1,278 @@
+// Reglas de captura ordenadas (`rules` en la policy). Cada muestra se evalúa contra la
+// lista en orden y la primera regla cuyos filtros coinciden todos decide la acción.
+use crate::policy::ValidationReport;
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Título que se envía cuando una regla `redact_title` coincide.
+pub const REDACTED_TITLE: &str = "[redacted]";
+
+const DAYS: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// La muestra no se captura
+    Drop,
+    /// Se captura con el título reemplazado por `[redacted]`
+    RedactTitle,
+    /// Se captura con el título reemplazado por su HMAC con la clave del dispositivo (`hmac:<hex>`)
+    HashTitle,
+    /// Se captura tal cual, sin aplicar las exclusiones planas (`excludeApps`, `excludePatterns`, ...)
+    Keep,
+}
+
+/// Una regla: todos los filtros presentes deben coincidir (los ausentes no restringen).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub action: RuleAction,
+    /// Nombre exacto de la app
+    #[serde(default)]
+    pub apps: Vec<String>,
+    /// Glob sobre el título
+    #[serde(default)]
+    pub title_pattern: Option<String>,
+    #[serde(default)]
+    pub title_regex: Option<String>,
+    /// Dominios que aparecen en el título (pestañas de navegador); incluyen subdominios
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Franja horaria en hora local
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    #[serde(default)]
+    pub case_insensitive: bool,
+}
+
+/// `days`: `mon`..`sun` (o `monday`..`sunday`), `weekdays`, `weekend` (vacío = todos). `from`/`to`: `HH:MM`;
+/// si `from` > `to` la franja cruza la medianoche (`19:00`–`08:00`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub days: Vec<String>,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+}
+
+const RULE_FIELDS: &[&str] = &["name", "action", "apps", "titlePattern", "titleRegex", "domains", "schedule", "caseInsensitive"];
+
+impl Rule {
+    fn label(&self, idx: usize) -> String {
+        self.name.clone().filter(|n| !n.trim().is_empty()).unwrap_or_else(|| format!("rules[{}]", idx))
+    }
+
+    fn is_unconditional(&self) -> bool {
+        self.apps.is_empty() && self.title_pattern.is_none() && self.title_regex.is_none() && self.domains.is_empty() && self.schedule.is_none()
+    }
+
+    /// Valida `rules` tal como llega en el documento crudo (errores por regla en `rules[i]`).
+    pub(crate) fn validate_list(raw: &serde_json::Value, r: &mut ValidationReport) {
+        let Some(items) = raw.as_array() else {
+            r.error("rules", "invalid_type", "se esperaba lista de reglas");
+            return;
+        };
+        let mut rules = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            let field = format!("rules[{}]", i);
+            if let Some(obj) = item.as_object() {
+                for key in obj.keys().filter(|k| !RULE_FIELDS.contains(&k.as_str())) {
+                    r.warn(format!("{}.{}", field, key), "unknown_field", "campo desconocido; se ignora");
+                }
+            }
+            match serde_json::from_value::<Rule>(item.clone()) {
+                Ok(rule) => {
+                    rule.check(&field, r);
+                    rules.push((i, rule));
+                }
+                Err(e) => r.error(field, "invalid_rule", e.to_string()),
+            }
+        }
+        let mut catch_all = None;
+        for (i, rule) in &rules {
+            if let Some(prev) = &catch_all {
+                r.warn(format!("rules[{}]", i), "unreachable", format!("nunca se evalúa: '{}' coincide con todo", prev));
+            } else if rule.is_unconditional() {
+                catch_all = Some(rule.label(*i));
+            }
+        }
+    }
+
+    fn check(&self, field: &str, r: &mut ValidationReport) {
+        if let Some(g) = &self.title_pattern {
+            if let Err(e) = globset::Glob::new(g) { r.error(format!("{}.titlePattern", field), "invalid_glob", format!("glob inválido: {}", e)); }
+        }
+        if let Some(re) = &self.title_regex {
+            if let Err(e) = Regex::new(re) { r.error(format!("{}.titleRegex", field), "invalid_regex", format!("regex inválida: {}", e)); }
+        }
+        for (i, d) in self.domains.iter().enumerate() {
+            if normalize_domain(d).is_none() { r.error(format!("{}.domains[{}]", field, i), "invalid_domain", format!("'{}' no es un dominio", d)); }
+        }
+        if let Some(s) = &self.schedule {
+            for (i, d) in s.days.iter().enumerate() {
+                if day_mask(d).is_none() { r.error(format!("{}.schedule.days[{}]", field, i), "invalid_day", format!("'{}' no es un día (mon..sun, weekdays, weekend)", d)); }
+            }
+            for (name, v) in [("from", &s.from), ("to", &s.to)] {
+                if let Some(v) = v {
+                    if parse_hhmm(v).is_none() { r.error(format!("{}.schedule.{}", field, name), "invalid_time", format!("'{}' no es una hora HH:MM", v)); }
+                }
+            }
+            if s.from.is_some() && s.from == s.to { r.warn(format!("{}.schedule", field), "empty_window", "from y to iguales: la franja no cubre ningún minuto"); }
+        }
+        if self.is_unconditional() && self.action == RuleAction::Drop {
+            r.warn(field, "matches_everything", "regla sin filtros con action drop: no se captura nada");
+        }
+    }
+}
+
+/// Día de la semana (0 = lunes) y minuto del día en hora local.
+#[derive(Debug, Clone, Copy)]
+pub struct Moment {
+    pub weekday: u8,
+    pub minute: u16,
+}
+
+impl Moment {
+    pub fn now_local() -> Self {
+        use chrono::{Datelike, Timelike};
+        let now = chrono::Local::now();
+        Self { weekday: now.weekday().num_days_from_monday() as u8, minute: (now.hour() * 60 + now.minute()) as u16 }
+    }
+}
+
+/// Regla que decidió una muestra.
+#[derive(Debug, Clone, Copy)]
+pub struct RuleHit<'a> {
+    pub action: RuleAction,
+    pub rule: &'a str,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    label: String,
+    action: RuleAction,
+    apps: HashSet<String>,
+    title_glob: Option<GlobMatcher>,
+    title_regex: Option<Regex>,
+    domains: Vec<String>,
+    /// Bit i = día i (0 = lunes)
+    days: u8,
+    window: Option<(u16, u16)>,
+    ci: bool,
+}
+
+/// Reglas compiladas una vez por cambio de policy. Una regla con algún filtro inválido
+/// se omite entera (aplicarla sin ese filtro la haría más amplia de lo escrito).
+#[derive(Debug, Clone, Default)]
+pub struct CompiledRules {
+    rules: Vec<CompiledRule>,
+    /// Extrae hosts del título; solo si alguna regla filtra por dominio
+    host_re: Option<Regex>,
+}
+
+impl CompiledRules {
+    pub fn compile(rules: &[Rule]) -> Self {
+        let compiled: Vec<CompiledRule> = rules.iter().enumerate().filter_map(|(i, r)| compile_rule(i, r)).collect();
+        let host_re = compiled
+            .iter()
+            .any(|r| !r.domains.is_empty())
+            .then(|| Regex::new(r"(?i)\b(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+[a-z]{2,}\b").expect("regex de host"));
+        Self { rules: compiled, host_re }
+    }
+
+    pub fn is_empty(&self) -> bool { self.rules.is_empty() }
+
+    pub fn evaluate(&self, app: &str, title: &str, at: Moment) -> Option<RuleHit<'_>> {
+        let mut hosts: Option<Vec<String>> = None;
+        for r in &self.rules {
+            if r.days & (1 << at.weekday) == 0 { continue; }
+            if let Some((from, to)) = r.window {
+                let inside = if from <= to { at.minute >= from && at.minute < to } else { at.minute >= from || at.minute < to };
+                if !inside { continue; }
+            }
+            if !r.apps.is_empty() {
+                let hit = if r.ci { r.apps.contains(&app.to_lowercase()) } else { r.apps.contains(app) };
+                if !hit { continue; }
+            }
+            if r.title_glob.as_ref().is_some_and(|g| !g.is_match(title)) { continue; }
+            if r.title_regex.as_ref().is_some_and(|re| !re.is_match(title)) { continue; }
+            if !r.domains.is_empty() {
+                let hosts = hosts.get_or_insert_with(|| self.hosts_in(title));
+                if !hosts.iter().any(|h| r.domains.iter().any(|d| h == d || h.strip_suffix(d.as_str()).is_some_and(|p| p.ends_with('.')))) { continue; }
+            }
+            return Some(RuleHit { action: r.action, rule: &r.label });
+        }
+        None
+    }
+
+    fn hosts_in(&self, title: &str) -> Vec<String> {
+        match &self.host_re {
+            Some(re) => re.find_iter(title).map(|m| m.as_str().to_lowercase()).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+fn compile_rule(idx: usize, r: &Rule) -> Option<CompiledRule> {
+    let ci = r.case_insensitive;
+    let title_glob = match &r.title_pattern {
+        Some(g) => Some(GlobBuilder::new(g).case_insensitive(ci).build().ok()?.compile_matcher()),
+        None => None,
+    };
+    let title_regex = match &r.title_regex {
+        Some(re) => Some(RegexBuilder::new(re).case_insensitive(ci).build().ok()?),
+        None => None,
+    };
+    let domains = r.domains.iter().map(|d| normalize_domain(d)).collect::<Option<Vec<_>>>()?;
+    let (mut days, mut window) = (0x7f, None);
+    if let Some(s) = &r.schedule {
+        if !s.days.is_empty() {
+            days = s.days.iter().map(|d| day_mask(d)).try_fold(0u8, |acc, m| m.map(|m| acc | m))?;
+        }
+        if s.from.is_some() || s.to.is_some() {
+            let from = s.from.as_deref().map(parse_hhmm).unwrap_or(Some(0))?;
+            let to = s.to.as_deref().map(parse_hhmm).unwrap_or(Some(24 * 60))?;
+            window = Some((from, to));
+        }
+    }
+    Some(CompiledRule {
+        label: r.label(idx),
+        action: r.action,
+        apps: r.apps.iter().map(|a| if ci { a.to_lowercase() } else { a.clone() }).collect(),
+        title_glob,
+        title_regex,
+        domains,
+        days,
+        window,
+        ci,
+    })
+}
+
+/// `*.example.com` y `Example.com` → `example.com`.
+fn normalize_domain(d: &str) -> Option<String> {
+    let d = d.trim().trim_start_matches("*.").trim_end_matches('.').to_lowercase();
+    let ok = d.contains('.') && d.split('.').all(|l| !l.is_empty() && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+    ok.then_some(d)
+}
+
+fn day_mask(d: &str) -> Option<u8> {
+    match d.trim().to_ascii_lowercase().as_str() {
+        "weekdays" => Some(0x1f),
+        "weekend" => Some(0x60),
+        // `mon` o `monday`
+        day => DAYS.iter().position(|x| day.len() >= 3 && x.starts_with(day) && (day.len() == 3 || day == *x)).map(|i| 1 << i),
+    }
+}
+
+fn parse_hhmm(s: &str) -> Option<u16> {
+    let (h, m) = s.trim().split_once(':')?;
+    let (h, m): (u16, u16) = (h.parse().ok()?, m.parse().ok()?);
+    // `24:00` vale como fin de día
+    ((h < 24 && m < 60) || (h == 24 && m == 0)).then_some(h * 60 + m)
+}
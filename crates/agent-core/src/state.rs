@@ -10,6 +10,13 @@ pub struct AgentState {
     pub agent_version: String,
     pub created_at: u64,
     pub updated_at: u64,
+    /// Identidad obtenida por enrolamiento (`agent enroll --code`); reemplaza ORG_ID/USER_EMAIL de env
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enrolled_at: Option<u64>,
 }
 
 impl AgentState {
@@ -29,11 +36,47 @@ impl AgentState {
             agent_version: agent_version.to_string(),
             created_at: now_ms(),
             updated_at: now_ms(),
+            org_id: None,
+            user_email: None,
+            enrolled_at: None,
         };
         ensure_parent(&f)?;
         fs::write(&f, serde_json::to_vec_pretty(&st)?)?;
         Ok(st)
     }
+
+    pub fn load(paths: &Paths) -> Result<Option<Self>> {
+        let f = paths.state_file();
+        if !f.exists() { return Ok(None); }
+        let data = fs::read_to_string(&f)?;
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+
+    /// Persiste la identidad enrolada en `agent_state.json`.
+    pub fn set_enrollment(&mut self, paths: &Paths, org_id: &str, user_email: &str) -> Result<()> {
+        self.org_id = Some(org_id.to_string());
+        self.user_email = Some(user_email.to_string());
+        self.enrolled_at = Some(now_ms());
+        self.updated_at = now_ms();
+        let f = paths.state_file();
+        ensure_parent(&f)?;
+        fs::write(&f, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Borra la identidad enrolada (re-enrolamiento); el `device_id` se conserva.
+    pub fn clear_enrollment(&mut self, paths: &Paths) -> Result<()> {
+        self.org_id = None;
+        self.user_email = None;
+        self.enrolled_at = None;
+        self.updated_at = now_ms();
+        fs::write(paths.state_file(), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_enrolled(&self) -> bool {
+        self.org_id.is_some() && self.user_email.is_some()
+    }
 }
 
 fn now_ms() -> u64 {
@@ -0,0 +1,163 @@
+// Reglas de captura: la primera que coincide decide, franjas horarias (incluso cruzando la
+// medianoche) y días, y qué pasa cuando ninguna coincide.
+use agent_core::policy::{CompiledPolicy, Policy};
+use agent_core::rules::{CompiledRules, Moment, Rule, RuleAction};
+use serde_json::json;
+
+fn rules(v: serde_json::Value) -> CompiledRules {
+    CompiledRules::compile(&serde_json::from_value::<Vec<Rule>>(v).expect("reglas"))
+}
+
+/// `weekday` 0 = lunes; `hh:mm` en hora local.
+fn at(weekday: u8, hh: u16, mm: u16) -> Moment {
+    Moment { weekday, minute: hh * 60 + mm }
+}
+
+const MON_10: Moment = Moment { weekday: 0, minute: 600 };
+
+fn action(r: &CompiledRules, app: &str, title: &str, m: Moment) -> Option<RuleAction> {
+    r.evaluate(app, title, m).map(|h| h.action)
+}
+
+#[test]
+fn first_matching_rule_wins() {
+    let r = rules(json!([
+        { "name": "slack privado", "apps": ["Slack"], "titlePattern": "*DM*", "action": "drop" },
+        { "apps": ["Slack"], "action": "redact_title" },
+        { "titleRegex": "(?i)factura", "action": "hash_title" },
+        { "action": "keep" },
+    ]));
+    let hit = r.evaluate("Slack", "DM con Ana", MON_10).unwrap();
+    assert_eq!((hit.action, hit.rule), (RuleAction::Drop, "slack privado"));
+    let hit = r.evaluate("Slack", "#general", MON_10).unwrap();
+    assert_eq!((hit.action, hit.rule), (RuleAction::RedactTitle, "rules[1]"));
+    // Slack gana a la regla de títulos aunque el título también coincida
+    assert_eq!(action(&r, "Slack", "Factura 2026", MON_10), Some(RuleAction::RedactTitle));
+    assert_eq!(action(&r, "Excel", "FACTURA 2026.xlsx", MON_10), Some(RuleAction::HashTitle));
+    assert_eq!(action(&r, "Excel", "presupuesto.xlsx", MON_10), Some(RuleAction::Keep));
+}
+
+#[test]
+fn order_decides_between_overlapping_rules() {
+    let keep_first = rules(json!([{ "apps": ["Chrome"], "action": "keep" }, { "domains": ["bank.com"], "action": "drop" }]));
+    let drop_first = rules(json!([{ "domains": ["bank.com"], "action": "drop" }, { "apps": ["Chrome"], "action": "keep" }]));
+    let title = "Cuenta - online.bank.com - Chrome";
+    assert_eq!(action(&keep_first, "Chrome", title, MON_10), Some(RuleAction::Keep));
+    assert_eq!(action(&drop_first, "Chrome", title, MON_10), Some(RuleAction::Drop));
+}
+
+#[test]
+fn all_filters_of_a_rule_must_match() {
+    let r = rules(json!([{ "apps": ["Chrome"], "domains": ["*.bank.com"], "titlePattern": "*Cuenta*", "action": "drop" }]));
+    assert_eq!(action(&r, "Chrome", "Cuenta - www.bank.com", MON_10), Some(RuleAction::Drop));
+    assert_eq!(action(&r, "Firefox", "Cuenta - www.bank.com", MON_10), None);
+    assert_eq!(action(&r, "Chrome", "Inicio - www.bank.com", MON_10), None);
+    assert_eq!(action(&r, "Chrome", "Cuenta - www.notbank.com", MON_10), None);
+}
+
+#[test]
+fn domains_include_subdomains_only() {
+    let r = rules(json!([{ "domains": ["Bank.com"], "action": "drop" }]));
+    for title in ["bank.com", "login.bank.com — Chrome", "Saldo | APP.BANK.COM"] {
+        assert_eq!(action(&r, "Chrome", title, MON_10), Some(RuleAction::Drop), "{}", title);
+    }
+    for title in ["mybank.com", "bank.com.evil.io", "bank"] {
+        assert_eq!(action(&r, "Chrome", title, MON_10), None, "{}", title);
+    }
+}
+
+#[test]
+fn case_sensitivity_is_per_rule() {
+    let strict = rules(json!([{ "apps": ["Slack"], "titlePattern": "*secreto*", "action": "drop" }]));
+    assert_eq!(action(&strict, "slack", "secreto", MON_10), None);
+    assert_eq!(action(&strict, "Slack", "SECRETO", MON_10), None);
+    let ci = rules(json!([{ "apps": ["Slack"], "titlePattern": "*secreto*", "caseInsensitive": true, "action": "drop" }]));
+    assert_eq!(action(&ci, "SLACK", "Algo SECRETO", MON_10), Some(RuleAction::Drop));
+}
+
+#[test]
+fn daytime_window_is_half_open() {
+    let r = rules(json!([{ "schedule": { "from": "09:00", "to": "18:00" }, "action": "drop" }]));
+    assert_eq!(action(&r, "A", "t", at(0, 8, 59)), None);
+    assert_eq!(action(&r, "A", "t", at(0, 9, 0)), Some(RuleAction::Drop));
+    assert_eq!(action(&r, "A", "t", at(0, 17, 59)), Some(RuleAction::Drop));
+    assert_eq!(action(&r, "A", "t", at(0, 18, 0)), None);
+}
+
+#[test]
+fn window_across_midnight() {
+    let r = rules(json!([{ "schedule": { "from": "19:00", "to": "08:00" }, "action": "drop" }]));
+    for (h, m) in [(19, 0), (23, 59), (0, 0), (3, 30), (7, 59)] {
+        assert_eq!(action(&r, "A", "t", at(2, h, m)), Some(RuleAction::Drop), "{:02}:{:02}", h, m);
+    }
+    for (h, m) in [(8, 0), (12, 0), (18, 59)] {
+        assert_eq!(action(&r, "A", "t", at(2, h, m)), None, "{:02}:{:02}", h, m);
+    }
+}
+
+#[test]
+fn open_ended_windows() {
+    let from = rules(json!([{ "schedule": { "from": "20:00" }, "action": "drop" }]));
+    assert_eq!(action(&from, "A", "t", at(0, 19, 59)), None);
+    assert_eq!(action(&from, "A", "t", at(0, 23, 59)), Some(RuleAction::Drop));
+    let to = rules(json!([{ "schedule": { "to": "07:00" }, "action": "drop" }]));
+    assert_eq!(action(&to, "A", "t", at(0, 0, 0)), Some(RuleAction::Drop));
+    assert_eq!(action(&to, "A", "t", at(0, 7, 0)), None);
+    let until_end = rules(json!([{ "schedule": { "from": "22:00", "to": "24:00" }, "action": "drop" }]));
+    assert_eq!(action(&until_end, "A", "t", at(0, 23, 59)), Some(RuleAction::Drop));
+    assert_eq!(action(&until_end, "A", "t", at(1, 0, 0)), None);
+}
+
+#[test]
+fn weekdays_and_weekend() {
+    let weekend = rules(json!([{ "schedule": { "days": ["weekend"] }, "action": "drop" }]));
+    let weekdays = rules(json!([{ "schedule": { "days": ["weekdays"] }, "action": "drop" }]));
+    let some = rules(json!([{ "schedule": { "days": ["mon", "Wednesday", "fri"] }, "action": "drop" }]));
+    for day in 0..7u8 {
+        let m = at(day, 12, 0);
+        assert_eq!(action(&weekend, "A", "t", m).is_some(), day >= 5, "weekend, día {}", day);
+        assert_eq!(action(&weekdays, "A", "t", m).is_some(), day < 5, "weekdays, día {}", day);
+        assert_eq!(action(&some, "A", "t", m).is_some(), [0, 2, 4].contains(&day), "mon/wed/fri, día {}", day);
+    }
+}
+
+#[test]
+fn days_and_window_combine() {
+    // la franja que cruza la medianoche se evalúa con el día de cada muestra: el viernes 23:00
+    // coincide, la madrugada del sábado no (el sábado no está en `days`)
+    let r = rules(json!([{ "schedule": { "days": ["weekdays"], "from": "22:00", "to": "06:00" }, "action": "drop" }]));
+    assert_eq!(action(&r, "A", "t", at(4, 23, 0)), Some(RuleAction::Drop));
+    assert_eq!(action(&r, "A", "t", at(5, 1, 0)), None);
+    assert_eq!(action(&r, "A", "t", at(0, 1, 0)), Some(RuleAction::Drop));
+    assert_eq!(action(&r, "A", "t", at(0, 12, 0)), None);
+}
+
+#[test]
+fn invalid_rules_are_skipped_not_widened() {
+    // con el filtro inválido ignorado la regla coincidiría con todo Chrome
+    let r = rules(json!([
+        { "apps": ["Chrome"], "titleRegex": "(", "action": "drop" },
+        { "apps": ["Chrome"], "schedule": { "days": ["someday"] }, "action": "drop" },
+        { "apps": ["Chrome"], "schedule": { "from": "25:00" }, "action": "drop" },
+        { "apps": ["Chrome"], "domains": ["no_es_dominio"], "action": "drop" },
+        { "apps": ["Chrome"], "action": "redact_title" },
+    ]));
+    assert_eq!(action(&r, "Chrome", "(algo)", MON_10), Some(RuleAction::RedactTitle));
+}
+
+#[test]
+fn no_match_means_no_rule_decision() {
+    let r = rules(json!([{ "apps": ["Slack"], "action": "drop" }]));
+    assert!(r.evaluate("Excel", "libro.xlsx", MON_10).is_none());
+    let empty = rules(json!([]));
+    assert!(empty.is_empty());
+    assert!(empty.evaluate("Slack", "x", MON_10).is_none());
+    // sin regla que decida, quedan las exclusiones planas de la policy
+    let p: Policy = serde_json::from_value(json!({ "excludeApps": ["KeePassXC"], "rules": [{ "apps": ["Slack"], "action": "keep" }] })).unwrap();
+    let c = CompiledPolicy::compile(&p);
+    assert!(c.has_rules());
+    assert!(c.match_rule("KeePassXC", "db", MON_10).is_none());
+    assert!(c.excludes_app("KeePassXC"));
+    assert_eq!(c.match_rule("Slack", "x", MON_10).map(|h| h.action), Some(RuleAction::Keep));
+    assert!(!CompiledPolicy::default().has_rules());
+}
@@ -0,0 +1,88 @@
+// Agenda de heartbeats: intervalo acotado de la policy, jitter de ±10%, un solo reclamo por
+// vencimiento entre el loop de heartbeat y el sender, y ventana de resultados de `/state`.
+use agent_core::heartbeat::{interval_secs, HeartbeatTracker, DEFAULT_INTERVAL_SECS, WINDOW_MS};
+use std::sync::Arc;
+
+const NOW: u64 = 1_790_000_000_000;
+
+#[test]
+fn policy_interval_is_clamped() {
+    assert_eq!(interval_secs(None), DEFAULT_INTERVAL_SECS);
+    assert_eq!(interval_secs(Some(0)), 15);
+    assert_eq!(interval_secs(Some(300)), 300);
+    assert_eq!(interval_secs(Some(86_400)), 3600);
+}
+
+#[test]
+fn jitter_stays_within_ten_percent() {
+    let hb = HeartbeatTracker::default();
+    let (mut min, mut max) = (u64::MAX, 0);
+    for _ in 0..2_000 {
+        let next = hb.schedule_next(60, NOW) - NOW;
+        assert!((54_000..=66_000).contains(&next), "{}", next);
+        (min, max) = (min.min(next), max.max(next));
+        assert_eq!(hb.next_due_ms(), NOW + next);
+    }
+    // reparte los agentes en la ventana en lugar de sincronizarlos
+    assert!(max - min > 6_000, "jitter {}..{}", min, max);
+}
+
+#[test]
+fn claim_only_when_due_and_only_once() {
+    let hb = HeartbeatTracker::default();
+    let due = hb.schedule_next(60, NOW);
+    assert!(!hb.try_claim(60, due - 1));
+    assert!(hb.try_claim(60, due));
+    // reprogramado desde el reclamo: el mismo vencimiento no se envía dos veces
+    assert!(!hb.try_claim(60, due));
+    let next = hb.next_due_ms();
+    assert!((due + 54_000..=due + 66_000).contains(&next));
+    // el intervalo nuevo de la policy aplica desde el siguiente reclamo
+    assert!(hb.try_claim(300, next));
+    assert!(hb.next_due_ms() >= next + 270_000);
+    assert_eq!(hb.report(next).interval_secs, 300);
+}
+
+#[test]
+fn released_claim_is_due_again() {
+    let hb = HeartbeatTracker::default();
+    let due = hb.schedule_next(60, NOW);
+    assert!(hb.try_claim(60, due));
+    // el ingest que lo llevaba falló: vuelve a estar vencido para el loop de heartbeat
+    hb.release(due + 500);
+    assert_eq!(hb.next_due_ms(), due + 500);
+    assert!(hb.try_claim(60, due + 500));
+}
+
+#[test]
+fn concurrent_claims_send_one_heartbeat() {
+    let hb = Arc::new(HeartbeatTracker::default());
+    let due = hb.schedule_next(60, NOW);
+    let claims: usize = (0..8)
+        .map(|_| {
+            let hb = hb.clone();
+            std::thread::spawn(move || (0..1_000).filter(|_| hb.try_claim(60, due)).count())
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|t| t.join().unwrap())
+        .sum();
+    assert_eq!(claims, 1);
+}
+
+#[test]
+fn success_rate_covers_the_last_hour() {
+    let hb = HeartbeatTracker::default();
+    assert_eq!(hb.report(NOW).success_rate, None);
+    hb.record(false, NOW);
+    hb.record(true, NOW + 1_000);
+    hb.record(true, NOW + 2_000);
+    let r = hb.report(NOW + 2_000);
+    assert_eq!((r.attempts, r.successes, r.window_secs), (3, 2, WINDOW_MS / 1000));
+    assert!((r.success_rate.unwrap() - 2.0 / 3.0).abs() < 1e-9);
+    // el fallo sale de la ventana una hora después
+    let r = hb.report(NOW + WINDOW_MS + 1);
+    assert_eq!((r.attempts, r.successes, r.success_rate), (2, 2, Some(1.0)));
+    hb.record(true, NOW + 2 * WINDOW_MS);
+    assert_eq!(hb.report(NOW + 2 * WINDOW_MS).attempts, 1);
+}
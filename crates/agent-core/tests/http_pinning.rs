@@ -0,0 +1,172 @@
+// Pinning SPKI: extracción del SubjectPublicKeyInfo desde DER y coincidencia con los pins.
+// Los pins esperados se calcularon con openssl (ver README, "Generar un pin").
+use agent_core::http::{chain_matches_pins, parse_pin, spki_sha256, validate_pins};
+use rustls::{Certificate, ServerName};
+use std::time::SystemTime;
+
+// EC P-256, longitudes DER de forma corta
+const EC_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBezCCASGgAwIBAgIUYBnnv4k5dS4JpMbUaEHaeWprCX4wCgYIKoZIzj0EAwIw
+EzERMA8GA1UEAwwIcGluLnRlc3QwHhcNMjYxMDE4MjMzNDMyWhcNMzYxMDE1MjMz
+NDMyWjATMREwDwYDVQQDDAhwaW4udGVzdDBZMBMGByqGSM49AgEGCCqGSM49AwEH
+A0IABEtz5Owy+PS+zX7jS8igDWkdFPPmcpaJ6Hp8/D1NvL+k3dLHOKP4aLFra4ZR
++tI2qBVD9vj5Ls/JbpBv4MmD78WjUzBRMB0GA1UdDgQWBBQxGNACybfCwYmnWXnB
+IfDRWvqRizAfBgNVHSMEGDAWgBQxGNACybfCwYmnWXnBIfDRWvqRizAPBgNVHRMB
+Af8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIQCCfaG9ZsOH528RgKD+r49ARqfU
+QR/x4rm0OXfAvYFFZgIgWGnPVpszMMXI2x7xzoKe5cjsUrvKP3+noysbWNmFxSs=
+-----END CERTIFICATE-----";
+const EC_PIN: &str = "sha256/Exh2f3Mx0q7KymBoQ/HoPxC/A7OpisZpGvpDs4OSPPU=";
+
+// RSA 2048, longitudes DER de forma larga (0x82)
+const RSA_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIDDzCCAfegAwIBAgIUSnXzDCkg7KMrm6CurP/t9yOnTNUwDQYJKoZIhvcNAQEL
+BQAwFzEVMBMGA1UEAwwMcnNhLnBpbi50ZXN0MB4XDTI2MTAxODIzMzQzNFoXDTM2
+MTAxNTIzMzQzNFowFzEVMBMGA1UEAwwMcnNhLnBpbi50ZXN0MIIBIjANBgkqhkiG
+9w0BAQEFAAOCAQ8AMIIBCgKCAQEAl9R2pCS7/gSib7D4gTcppRBh+XdL4hwo7H6l
+sp+Ipfn1m/CKFplGG83T2oyUCqf+M6pR6YcBCRKQc4musHVxF3+BNDrMyLtR7lvH
+FuXg3yUszy2obrvK3hcY7YhWmsLcqn7OlqgUBj3piDiVVBG6TmYKAJ6ymge93Ax+
+u1/KmROR1SdoZtYRrzkfBmb0ogSXKeXs4jBXU3z/wP7mCkXvOXeVGZsamUHOHU4r
+6pOgYWtADzrSA05gOu7r1KBY7/27zaCaVxBqc2XORWw1f+3tas8eR2X8UdgwoOX8
++DkGziDOWr+sYm5TgPBVKRyer/el3h77j2/uJaHUUYkdDrMH2QIDAQABo1MwUTAd
+BgNVHQ4EFgQUy3JxuSMEYODPwaNsxE/pJyPvHKowHwYDVR0jBBgwFoAUy3JxuSME
+YODPwaNsxE/pJyPvHKowDwYDVR0TAQH/BAUwAwEB/zANBgkqhkiG9w0BAQsFAAOC
+AQEAG9zTzKMkHo4U5d7I4UkPtFo1x7LtnF9hTnzuBxTTQ1wRAT02n3F3FdzmkAi3
+wrKyy5XxHrZXI839w6X8FV+ebKbcfLyogW5Q8ClimuK736wBZ7Qe3Ubq8mstr2gc
+ZU6A0mMAFqZ6pOti8CCS5iXglHzQwycORcm27K7tijfJiibndbUa9Pn4oQLOM7LD
+By1kXmbdnRjWzmGcW4ms+pNTyR5wFDI/31S/NHVaLLo1zqVR/ksil6DiLck413vn
+uPBMrtlRuBQ94o6nRTqetab/BRtf3PvCrQB9f8u5VXAlLc9hHameCPVdL9CBN/aq
+48zl2x+Y/4QcU5+WYCnIkwCxjA==
+-----END CERTIFICATE-----";
+const RSA_PIN: &str = "sha256/SbjYC6Cqa6D8+NHY6tqsrl/cDIbrgmBcB6ijKIMTJ2Y=";
+
+// Cadena real (EC P-256, válida hasta 2036): raíz → intermedio → hoja para backend.pin.test
+const ROOT_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBlDCCATugAwIBAgIUMnPzE9Rs/VWTzlW78cbOp9+Kdk8wCgYIKoZIzj0EAwIw
+GDEWMBQGA1UEAwwNUGluIFRlc3QgUm9vdDAeFw0yNjEwMTkwMDQ4MTJaFw0zNjEw
+MTYwMDQ4MTJaMBgxFjAUBgNVBAMMDVBpbiBUZXN0IFJvb3QwWTATBgcqhkjOPQIB
+BggqhkjOPQMBBwNCAATIEws/qSSuq6Gij3uvYGi7AJL5YzT4hQULozMZlG7ApTuA
+RqvNR3e7VOw8YM///1cQr+6a2o04Cne1c05HvZ/Jo2MwYTAdBgNVHQ4EFgQUAtZu
++MXDhLt3NLaqWVII487v4gwwHwYDVR0jBBgwFoAUAtZu+MXDhLt3NLaqWVII487v
+4gwwDwYDVR0TAQH/BAUwAwEB/zAOBgNVHQ8BAf8EBAMCAQYwCgYIKoZIzj0EAwID
+RwAwRAIgBVFCITvxwejYK6SwiIgrPHLLMke3ZD30Ae6sPMc5tIcCIGYjb6YAbgjw
+PBnoBGfxNVa+Y48yGGLnr/3Ja+bmmA+K
+-----END CERTIFICATE-----";
+const ROOT_PIN: &str = "sha256/MzkBksaf0gBeS8LnX/HIdtom/aqac65Mvmj6Y8HZlTs=";
+
+const INTER_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBnTCCAUOgAwIBAgIUVOiF5mV9Gii7HYJVh4R/cpDDtbIwCgYIKoZIzj0EAwIw
+GDEWMBQGA1UEAwwNUGluIFRlc3QgUm9vdDAeFw0yNjEwMTkwMDQ4MTJaFw0zNjEw
+MTYwMDQ4MTJaMCAxHjAcBgNVBAMMFVBpbiBUZXN0IEludGVybWVkaWF0ZTBZMBMG
+ByqGSM49AgEGCCqGSM49AwEHA0IABOk6g5jRlAERAkn+c+U3hSXUWAJJ5zaEXb46
+GB1FT5uRBs551JYQwA4n2hx49aF3MLD94Glyx0CRlhtGJf1WbXqjYzBhMA8GA1Ud
+EwEB/wQFMAMBAf8wDgYDVR0PAQH/BAQDAgEGMB0GA1UdDgQWBBSOicKU4borUbZA
+WobCQzFeyxaEOjAfBgNVHSMEGDAWgBQC1m74xcOEu3c0tqpZUgjjzu/iDDAKBggq
+hkjOPQQDAgNIADBFAiBGsofVLdacC5Nhd4Omo9uj9r61rGya8nniElig0enXjgIh
+AJ+aMqQ5Ze87u79w2dZlu3mtc4DEipwzFUqM3n1suCf1
+-----END CERTIFICATE-----";
+const INTER_PIN: &str = "sha256/cu+0/HWsu+h4kNFmzySV5/FzEPL94pYpMTry5/VdPt4=";
+
+const LEAF_CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIB0TCCAXegAwIBAgIUURM/K7HZpPWGJ8J3Abmm2FHDhZowCgYIKoZIzj0EAwIw
+IDEeMBwGA1UEAwwVUGluIFRlc3QgSW50ZXJtZWRpYXRlMB4XDTI2MTAxOTAwNDgx
+MloXDTM2MTAxNjAwNDgxMlowGzEZMBcGA1UEAwwQYmFja2VuZC5waW4udGVzdDBZ
+MBMGByqGSM49AgEGCCqGSM49AwEHA0IABBKWONOqfAahTP2VRXSmSzmC1X025lgh
+/8wVBKXLFYyl4K3BKi5cv49JzlUKU0CiA3rOOHyhE+6Glo7kyiHFz2WjgZMwgZAw
+DAYDVR0TAQH/BAIwADAOBgNVHQ8BAf8EBAMCB4AwEwYDVR0lBAwwCgYIKwYBBQUH
+AwEwGwYDVR0RBBQwEoIQYmFja2VuZC5waW4udGVzdDAdBgNVHQ4EFgQU5GPVrdq4
+6lbxfd0nfFJeLBfHOFkwHwYDVR0jBBgwFoAUjonClOG6K1G2QFqGwkMxXssWhDow
+CgYIKoZIzj0EAwIDSAAwRQIge44I2qfwut3487EuPDpFSJ0KCQxlNQWVLnQC4KAX
+ZK8CIQCIesqkYlto/PGPVGPvR1Uth9Wa2Rcc9xUioHMmAgg2aQ==
+-----END CERTIFICATE-----";
+const LEAF_PIN: &str = "sha256/KACbLC0qsNi9WoMWbGgjv9KakXJb/XyzqHKM8HjjTLg=";
+
+fn der(pem: &str) -> Vec<u8> {
+    rustls_pemfile::certs(&mut pem.as_bytes()).unwrap().remove(0)
+}
+
+#[test]
+fn spki_hash_matches_openssl_pin() {
+    assert_eq!(spki_sha256(&der(EC_CERT)), Some(parse_pin(EC_PIN).unwrap()));
+    assert_eq!(spki_sha256(&der(RSA_CERT)), Some(parse_pin(RSA_PIN).unwrap()));
+}
+
+fn cert(pem: &str) -> Certificate {
+    Certificate(der(pem))
+}
+
+// Hoja de backend.pin.test seguida de `extra` tal como los enviaría el servidor.
+fn matches(extra: &[&str], pins: &[&str]) -> bool {
+    let name = ServerName::try_from("backend.pin.test").unwrap();
+    let intermediates: Vec<Certificate> = extra.iter().map(|p| cert(p)).collect();
+    let pins: Vec<[u8; 32]> = pins.iter().map(|p| parse_pin(p).unwrap()).collect();
+    chain_matches_pins(&cert(LEAF_CERT), &intermediates, &name, &pins, SystemTime::now())
+}
+
+#[test]
+fn chain_matches_pinned_cert_on_the_verified_path() {
+    // pin de la hoja, del intermedio que la firma, de la raíz enviada, o uno de varios
+    assert!(matches(&[INTER_CERT], &[LEAF_PIN]));
+    assert!(matches(&[INTER_CERT], &[INTER_PIN]));
+    assert!(matches(&[INTER_CERT, ROOT_CERT], &[ROOT_PIN]));
+    assert!(matches(&[INTER_CERT], &[EC_PIN, INTER_PIN]));
+    // el orden en que el servidor envía los intermedios no importa
+    assert!(matches(&[ROOT_CERT, INTER_CERT], &[ROOT_PIN]));
+}
+
+#[test]
+fn appended_pinned_cert_off_the_path_is_rejected() {
+    // Un proxy con inspección TLS presenta su hoja de confianza y anexa el certificado real del
+    // backend (fijado): no firma la hoja, así que no cuenta.
+    assert!(!matches(&[INTER_CERT, EC_CERT], &[EC_PIN]));
+    assert!(!matches(&[INTER_CERT, RSA_CERT], &[RSA_PIN]));
+    // sin el intermedio, la hoja no desciende de la raíz fijada
+    assert!(!matches(&[ROOT_CERT], &[ROOT_PIN]));
+}
+
+#[test]
+fn mismatched_pin_rejects_chain() {
+    assert!(!matches(&[INTER_CERT], &[RSA_PIN]));
+    assert!(!matches(&[INTER_CERT], &[]));
+    assert!(!matches(&[], &[INTER_PIN]));
+    // la raíz no enviada por el servidor no se compara
+    assert!(!matches(&[INTER_CERT], &[ROOT_PIN]));
+}
+
+#[test]
+fn malformed_der_never_matches() {
+    let rsa = der(RSA_CERT);
+    let pin = parse_pin(RSA_PIN).unwrap();
+    let bad: Vec<Vec<u8>> = vec![
+        Vec::new(),
+        vec![0x30],
+        // truncado a la mitad
+        rsa[..rsa.len() / 2].to_vec(),
+        // longitud declarada mayor que el buffer
+        vec![0x30, 0x84, 0xff, 0xff, 0xff, 0xff, 0x00],
+        // longitud de forma larga sin bytes (0x80) y con demasiados (0x85)
+        vec![0x30, 0x80, 0x00, 0x00],
+        vec![0x30, 0x85, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00],
+        // SEQUENCE vacía y basura
+        vec![0x30, 0x00],
+        b"no es un certificado".to_vec(),
+    ];
+    let name = ServerName::try_from("backend.pin.test").unwrap();
+    for b in &bad {
+        assert_eq!(spki_sha256(b), None, "entrada: {:02x?}", b);
+        assert!(!chain_matches_pins(&Certificate(b.clone()), &[], &name, &[pin], SystemTime::now()));
+    }
+    // un certificado ilegible en la cadena no impide que coincida otro válido
+    let chain = [Certificate(bad[2].clone()), cert(INTER_CERT)];
+    assert!(chain_matches_pins(&cert(LEAF_CERT), &chain, &name, &[parse_pin(INTER_PIN).unwrap()], SystemTime::now()));
+}
+
+#[test]
+fn pin_format_is_validated() {
+    assert!(parse_pin(EC_PIN).is_ok());
+    // sin prefijo también vale
+    assert!(parse_pin(EC_PIN.trim_start_matches("sha256/")).is_ok());
+    assert!(parse_pin("sha256/no-es-base64!").is_err());
+    assert!(parse_pin("sha256/AAAA").is_err());
+    assert!(validate_pins(&[EC_PIN.to_string(), RSA_PIN.to_string()]).is_ok());
+    assert!(validate_pins(&[EC_PIN.to_string(), "sha256/AAAA".to_string()]).is_err());
+}
@@ -0,0 +1,74 @@
+// Instancia única: un segundo `try_acquire` sobre el mismo data dir ve al dueño del lock, y soltar
+// el lock borra `agent_instance.json` y deja tomarlo de nuevo. `flock` y `LockFileEx` son por
+// descriptor abierto, así que dos aperturas del mismo proceso compiten como dos daemons.
+use agent_core::instance::{holder, read_info, try_acquire, Acquire, InstanceInfo};
+use agent_core::paths::Paths;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn temp_paths(name: &str) -> Paths {
+    let dir = std::env::temp_dir().join(format!("ripor-instance-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    Paths { data_dir: dir }
+}
+
+fn info(panel_addr: &str) -> InstanceInfo {
+    InstanceInfo {
+        pid: std::process::id(),
+        started_at_ms: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
+        version: "0.1.0".into(),
+        panel_addr: panel_addr.into(),
+        shutdown_token: "token".into(),
+    }
+}
+
+#[test]
+fn second_acquire_reports_the_holder() {
+    let paths = temp_paths("busy");
+    let Acquire::Acquired(lock) = try_acquire(&paths, info("127.0.0.1:49219")).unwrap() else { panic!("el primero debe tomar el lock") };
+    match try_acquire(&paths, info("127.0.0.1:49220")).unwrap() {
+        Acquire::Busy(Some(who)) => {
+            assert_eq!(who.pid, lock.info.pid);
+            assert_eq!(who.panel_addr, "127.0.0.1:49219");
+            assert_eq!(who.shutdown_token, "token");
+        }
+        Acquire::Busy(None) => panic!("el dueño ya escribió agent_instance.json"),
+        Acquire::Acquired(_) => panic!("dos instancias con el lock"),
+    }
+    // el proceso dueño sigue vivo: la CLI lo ve sin tocar el lock
+    assert_eq!(holder(&paths).map(|h| h.panel_addr), Some("127.0.0.1:49219".into()));
+    drop(lock);
+    let _ = std::fs::remove_dir_all(&paths.data_dir);
+}
+
+#[test]
+fn dropping_the_lock_removes_the_info_file_and_frees_it() {
+    let paths = temp_paths("drop");
+    let Acquire::Acquired(lock) = try_acquire(&paths, info("127.0.0.1:49219")).unwrap() else { panic!("el primero debe tomar el lock") };
+    assert!(paths.instance_file().exists());
+    #[cfg(unix)] {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(paths.instance_file()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "el token de apagado solo lo lee el dueño");
+    }
+    drop(lock);
+    assert!(!paths.instance_file().exists());
+    assert!(read_info(&paths).is_none() && holder(&paths).is_none());
+    let Acquire::Acquired(next) = try_acquire(&paths, info("127.0.0.1:49220")).unwrap() else { panic!("el lock quedó libre") };
+    assert_eq!(read_info(&paths).map(|i| i.panel_addr), Some("127.0.0.1:49220".into()));
+    drop(next);
+    let _ = std::fs::remove_dir_all(&paths.data_dir);
+}
+
+#[test]
+fn stale_info_file_without_a_live_process_is_not_a_holder() {
+    let paths = temp_paths("stale");
+    // tras un crash queda el archivo, pero ese pid ya no es el que lo escribió
+    let mut stale = info("127.0.0.1:49219");
+    stale.started_at_ms = 1_000;
+    std::fs::write(paths.instance_file(), serde_json::to_vec(&stale).unwrap()).unwrap();
+    assert!(read_info(&paths).is_some());
+    assert!(holder(&paths).is_none());
+    assert!(matches!(try_acquire(&paths, info("127.0.0.1:49220")).unwrap(), Acquire::Acquired(_)));
+    let _ = std::fs::remove_dir_all(&paths.data_dir);
+}
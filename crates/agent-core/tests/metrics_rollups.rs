@@ -0,0 +1,163 @@
+// Rollups por minuto y resúmenes de `/metrics/summary`: cuantiles de histogramas sobre los
+// buckets combinados, p95 de gauges como p95 de los p95 por minuto y límites de minuto.
+// `scripts/slo_idle_check.py --from-agent` compara estos p95 con sus umbrales.
+use agent_core::metrics::{summarize, MetricKind, MetricsStore, Registry, Rollup, SeriesSummary};
+use agent_core::paths::Paths;
+
+const M0: u64 = 1_790_000_040_000;
+const MINUTE: u64 = 60_000;
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+fn series<'a>(s: &'a [SeriesSummary], name: &str) -> &'a SeriesSummary {
+    s.iter().find(|x| x.name == name).unwrap_or_else(|| panic!("falta la serie {}", name))
+}
+
+#[test]
+fn histogram_quantiles_interpolate_within_buckets() {
+    let r = Registry::new();
+    for v in 1..=100 {
+        r.observe("ingest_ms", &[], v as f64);
+    }
+    let rows = r.take_minute(M0);
+    assert_eq!(rows.len(), 1);
+    let h = &rows[0];
+    assert_eq!((h.kind, h.count, h.min, h.max), (MetricKind::Histogram, 100, 1.0, 100.0));
+    // ≤1, ≤2, ≤5, ≤10, ≤25, ≤50, ≤100 y el resto vacíos
+    assert_eq!(&h.buckets[..8], &[1, 1, 3, 5, 15, 25, 50, 0]);
+    assert!(close(h.p50, 50.0), "p50 = {}", h.p50);
+    assert!(close(h.p95, 95.0), "p95 = {}", h.p95);
+}
+
+#[test]
+fn histogram_quantiles_are_bounded_by_min_and_max() {
+    let r = Registry::new();
+    for _ in 0..10 {
+        r.observe("flush_ms", &[], 70.0);
+    }
+    // todo en el bucket (50, 100]: acotado a [70, 70]
+    let h = &r.take_minute(M0)[0];
+    assert_eq!((h.p50, h.p95), (70.0, 70.0));
+    r.observe("flush_ms", &[], 10.0);
+    for _ in 0..9 {
+        r.observe("flush_ms", &[], 90_000.0);
+    }
+    // el bucket +Inf va de 60 s al máximo observado
+    let h = &r.take_minute(M0 + MINUTE)[0];
+    assert!(h.p95 > 60_000.0 && h.p95 <= 90_000.0, "p95 = {}", h.p95);
+}
+
+#[test]
+fn histogram_summary_merges_buckets_across_minutes() {
+    let r = Registry::new();
+    for v in 1..=50 {
+        r.observe("ingest_ms", &[], v as f64);
+    }
+    let mut rows = r.take_minute(M0);
+    for v in 51..=100 {
+        r.observe("ingest_ms", &[], v as f64);
+    }
+    rows.extend(r.take_minute(M0 + MINUTE));
+    // por minuto: 47.5 y 97.55; sobre los buckets combinados, lo mismo que con un solo minuto
+    assert!(close(rows[0].p95, 47.5), "{}", rows[0].p95);
+    assert!(close(rows[1].p95, 97.55), "{}", rows[1].p95);
+    let s = summarize(&rows, 2 * MINUTE);
+    let h = series(&s, "ingest_ms");
+    assert_eq!((h.minutes, h.count), (2, 100));
+    assert_eq!((h.min, h.max), (Some(1.0), Some(100.0)));
+    assert!(close(h.avg.unwrap(), 50.5));
+    assert!(close(h.p50.unwrap(), 50.0), "p50 = {:?}", h.p50);
+    assert!(close(h.p95.unwrap(), 95.0), "p95 = {:?}", h.p95);
+}
+
+#[test]
+fn gauge_summary_is_p95_of_minute_p95s() {
+    let r = Registry::new();
+    let mut rows = Vec::new();
+    for i in 1..=20u64 {
+        r.set("cpu_pct", &[], i as f64);
+        r.set("cpu_pct", &[], 10.0 * i as f64);
+        let minute = r.take_minute(M0 + (i - 1) * MINUTE);
+        // nearest-rank sobre dos muestras: p50 la menor, p95 la mayor
+        assert_eq!((minute[0].p50, minute[0].p95), (i as f64, 10.0 * i as f64));
+        rows.extend(minute);
+    }
+    let s = summarize(&rows, 20 * MINUTE);
+    let g = series(&s, "cpu_pct");
+    assert_eq!((g.kind, g.minutes, g.count), (MetricKind::Gauge, 20, 40));
+    assert_eq!(g.p50, Some(10.0), "mediana de las medianas por minuto");
+    assert_eq!(g.p95, Some(190.0), "p95 de los p95 por minuto");
+    // el p95 real de las 40 muestras es 180: el resumen no lo subestima
+    assert!(g.p95.unwrap() >= 180.0);
+    assert_eq!((g.min, g.max), (Some(1.0), Some(200.0)));
+    assert!(close(g.avg.unwrap(), 57.75));
+    assert_eq!(g.rate_per_s, None);
+}
+
+#[test]
+fn counters_report_increment_and_rate() {
+    let r = Registry::new();
+    r.inc("events", &[("source", "focus")], 90);
+    let mut rows = r.take_minute(M0);
+    r.inc("events", &[("source", "focus")], 30);
+    r.inc("events", &[("source", "idle")], 6);
+    rows.extend(r.take_minute(M0 + MINUTE));
+    let c = rows.iter().find(|x| x.minute_ms == M0).unwrap();
+    assert_eq!((c.count, c.min, c.max, c.p50, c.p95), (90, 0.0, 0.0, 0.0, 0.0));
+    let s = summarize(&rows, 2 * MINUTE);
+    let focus = s.iter().find(|x| x.labels == r#"source="focus""#).unwrap();
+    assert_eq!((focus.minutes, focus.count), (2, 120));
+    assert_eq!(focus.rate_per_s, Some(1.0));
+    assert_eq!(focus.p95, None);
+    let idle = s.iter().find(|x| x.labels == r#"source="idle""#).unwrap();
+    assert_eq!((idle.minutes, idle.rate_per_s), (1, Some(0.05)));
+}
+
+#[test]
+fn closing_a_minute_resets_it_and_peeking_does_not() {
+    let r = Registry::new();
+    r.observe("ingest_ms", &[], 10.0);
+    r.set("queue_size", &[], 3.0);
+    assert_eq!(r.peek_minute(M0).len(), 2);
+    assert_eq!(r.peek_minute(M0).len(), 2, "peek no reinicia");
+    let closed = r.take_minute(M0);
+    assert_eq!(closed.len(), 2);
+    assert!(closed.iter().all(|x| x.minute_ms == M0 && x.count == 1));
+    // el minuto siguiente empieza vacío: sin datos no hay rollup
+    assert!(r.take_minute(M0 + MINUTE).is_empty());
+    r.observe("ingest_ms", &[], 20.0);
+    let next = r.take_minute(M0 + MINUTE);
+    assert_eq!(next.len(), 1);
+    assert_eq!((next[0].minute_ms, next[0].count, next[0].min), (M0 + MINUTE, 1, 20.0));
+}
+
+#[test]
+fn store_window_includes_its_first_minute() {
+    let dir = std::env::temp_dir().join(format!("ripor-metrics-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = Paths { data_dir: dir.clone() };
+    let mut store = MetricsStore::open(&paths).unwrap();
+    let r = Registry::new();
+    for (i, v) in [5.0, 40.0, 400.0].into_iter().enumerate() {
+        r.observe("ingest_ms", &[], v);
+        r.set("mem_mb", &[], v);
+        store.insert(&r.take_minute(M0 + i as u64 * MINUTE)).unwrap();
+    }
+    let all = store.since(M0).unwrap();
+    assert_eq!(all.len(), 6);
+    assert!(all.windows(2).all(|w| w[0].minute_ms <= w[1].minute_ms));
+    let last_two: Vec<Rollup> = store.since(M0 + MINUTE).unwrap();
+    assert_eq!(last_two.len(), 4, "el minuto de inicio entra en la ventana");
+    assert!(last_two.iter().all(|x| x.minute_ms >= M0 + MINUTE));
+    // tipo y buckets sobreviven al guardado
+    let h = last_two.iter().find(|x| x.kind == MetricKind::Histogram && x.minute_ms == M0 + MINUTE).unwrap();
+    assert_eq!(h.buckets.iter().sum::<u64>(), 1);
+    assert!(last_two.iter().any(|x| x.kind == MetricKind::Gauge && x.buckets.is_empty()));
+    assert_eq!(summarize(&last_two, 2 * MINUTE).len(), 2);
+    assert_eq!(store.prune_before(M0 + MINUTE).unwrap(), 2);
+    assert_eq!(store.since(0).unwrap().len(), 4);
+    drop(store);
+    let _ = std::fs::remove_dir_all(&dir);
+}
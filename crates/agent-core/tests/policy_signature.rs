@@ -0,0 +1,159 @@
+// Firma de policies: verificación Ed25519 y admisión (firma + validación) según el modo.
+// Las claves se derivan de semillas fijas; la firma es sobre la forma canónica, como en el backend.
+use agent_core::paths::Paths;
+use agent_core::policy_sig::{canonical_bytes, save_provisioned, PolicySignature, SigStatus, SignatureMode, TrustStore, TrustedKey};
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::json;
+
+const B64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+
+fn key(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32])
+}
+
+fn trusted(kid: &str, k: &SigningKey) -> TrustedKey {
+    TrustedKey { key_id: kid.to_string(), public_key: B64.encode(k.verifying_key().as_bytes()) }
+}
+
+fn sign(k: &SigningKey, kid: Option<&str>, policy: &serde_json::Value) -> PolicySignature {
+    PolicySignature { key_id: kid.map(str::to_string), sig: B64.encode(k.sign(&canonical_bytes(policy)).to_bytes()) }
+}
+
+fn policy() -> serde_json::Value {
+    json!({ "excludeApps": ["KeePassXC"], "titleCapture": true, "heartbeatIntervalSecs": 60 })
+}
+
+fn store(mode: SignatureMode) -> TrustStore {
+    TrustStore::new(&[trusted("k1", &key(1)), trusted("k2", &key(2))], mode)
+}
+
+#[test]
+fn valid_signature_is_admitted() {
+    let p = policy();
+    for (kid, seed) in [(Some("k1"), 1), (Some("k2"), 2), (None, 2)] {
+        let (status, res) = store(SignatureMode::Enforce).admit(&p, Some(&sign(&key(seed), kid, &p)));
+        assert_eq!(status, SigStatus::Valid { key_id: format!("k{}", seed) });
+        let admitted = res.expect("policy firmada aceptada");
+        assert_eq!(admitted.policy.excludeApps, vec!["KeePassXC".to_string()]);
+    }
+}
+
+#[test]
+fn canonical_form_ignores_key_order() {
+    let p = policy();
+    let sig = sign(&key(1), Some("k1"), &p);
+    let reordered: serde_json::Value = serde_json::from_str(r#"{"titleCapture":true,"heartbeatIntervalSecs":60,"excludeApps":["KeePassXC"]}"#).unwrap();
+    assert!(store(SignatureMode::Enforce).verify(&reordered, Some(&sig)).is_valid());
+}
+
+#[test]
+fn tampered_policy_is_rejected() {
+    let p = policy();
+    let sig = sign(&key(1), Some("k1"), &p);
+    let mut tampered = p.clone();
+    tampered["excludeApps"] = json!([]);
+    let (status, res) = store(SignatureMode::Enforce).admit(&tampered, Some(&sig));
+    assert!(matches!(status, SigStatus::Invalid { .. }), "{:?}", status);
+    assert!(res.unwrap_err().reason.contains("firma inválida"));
+    // firma de otra clave de confianza con el keyId de k1
+    let (status, _) = store(SignatureMode::Enforce).admit(&p, Some(&sign(&key(2), Some("k1"), &p)));
+    assert!(matches!(status, SigStatus::Invalid { .. }));
+    // basura en lugar de firma
+    for bad in ["no-es-base64!", "AAAA"] {
+        let sig = PolicySignature { key_id: Some("k1".into()), sig: bad.into() };
+        let (status, res) = store(SignatureMode::Enforce).admit(&p, Some(&sig));
+        assert!(matches!(status, SigStatus::Invalid { .. }), "{}: {:?}", bad, status);
+        assert!(res.is_err());
+    }
+}
+
+#[test]
+fn unsigned_is_rejected_when_enforced() {
+    let (status, res) = store(SignatureMode::Enforce).admit(&policy(), None);
+    assert_eq!(status, SigStatus::Unsigned);
+    assert_eq!(res.unwrap_err().reason, "policy sin firma");
+}
+
+#[test]
+fn unknown_key_is_rejected() {
+    let p = policy();
+    let (status, res) = store(SignatureMode::Enforce).admit(&p, Some(&sign(&key(9), Some("k9"), &p)));
+    assert_eq!(status, SigStatus::UnknownKey { key_id: "k9".into() });
+    assert!(res.unwrap_err().reason.contains("k9"));
+    // sin keyId: se prueba con todas las de confianza y ninguna coincide
+    let (status, res) = store(SignatureMode::Enforce).admit(&p, Some(&sign(&key(9), None, &p)));
+    assert!(matches!(status, SigStatus::Invalid { .. }));
+    assert!(res.is_err());
+}
+
+#[test]
+fn enforce_without_keys_rejects_everything() {
+    let p = policy();
+    let empty = TrustStore::new(&[], SignatureMode::Enforce);
+    assert!(empty.admit(&p, None).1.is_err());
+    assert!(empty.admit(&p, Some(&sign(&key(1), Some("k1"), &p))).1.is_err());
+    assert!(empty.admit(&p, Some(&sign(&key(1), None, &p))).1.is_err());
+}
+
+#[test]
+fn warn_mode_applies_but_reports() {
+    let (status, res) = store(SignatureMode::Warn).admit(&policy(), None);
+    assert_eq!(status, SigStatus::Unsigned);
+    assert!(res.is_ok());
+    let (status, res) = store(SignatureMode::Off).admit(&policy(), None);
+    assert_eq!(status, SigStatus::NotChecked);
+    assert!(res.is_ok());
+}
+
+#[test]
+fn build_without_keys_applies_unsigned_policy() {
+    let mode = SignatureMode::for_build(None, None);
+    assert_eq!(mode, SignatureMode::Warn);
+    assert_eq!(SignatureMode::for_build(None, Some(" ")), SignatureMode::Warn);
+    let (status, res) = TrustStore::new(&[], mode).admit(&policy(), None);
+    assert_eq!(status, SigStatus::Unsigned);
+    assert_eq!(res.expect("policy sin firma aplicada").policy.excludeApps, vec!["KeePassXC".to_string()]);
+    // con claves embebidas, o pedido explícitamente, se exige firma
+    assert_eq!(SignatureMode::for_build(None, Some("k1=AAAA")), SignatureMode::Enforce);
+    assert_eq!(SignatureMode::for_build(Some("enforce"), None), SignatureMode::Enforce);
+    assert_eq!(SignatureMode::for_build(Some("enfroce"), None), SignatureMode::Enforce);
+    assert_eq!(SignatureMode::for_build(Some("off"), Some("k1=AAAA")), SignatureMode::Off);
+}
+
+#[test]
+fn signed_but_invalid_policy_is_rejected() {
+    let p = json!({ "heartbeatIntervalSecs": "60" });
+    let (status, res) = store(SignatureMode::Enforce).admit(&p, Some(&sign(&key(1), Some("k1"), &p)));
+    assert!(status.is_valid(), "la firma es válida; falla la validación");
+    let rej = res.unwrap_err();
+    assert!(rej.reason.starts_with("policy inválida"), "{}", rej.reason);
+    assert_eq!(rej.errors[0].field, "heartbeatIntervalSecs");
+}
+
+#[test]
+fn signature_header_is_parsed() {
+    assert_eq!(PolicySignature::from_header(" k1:QUJD "), Some(PolicySignature { key_id: Some("k1".into()), sig: "QUJD".into() }));
+    assert_eq!(PolicySignature::from_header("QUJD"), Some(PolicySignature { key_id: None, sig: "QUJD".into() }));
+    assert_eq!(PolicySignature::from_header("  "), None);
+}
+
+#[test]
+fn provisioned_keys_are_trusted_only_when_private() {
+    let dir = std::env::temp_dir().join(format!("ripor-policy-keys-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = Paths { data_dir: dir.clone() };
+    let p = policy();
+    let sig = sign(&key(3), Some("enr"), &p);
+    save_provisioned(&paths, &[trusted("enr", &key(3))]).unwrap();
+    let st = TrustStore::load(&paths);
+    assert!(st.key_ids().contains(&"enr".to_string()));
+    assert!(st.verify(&p, Some(&sig)).is_valid());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(paths.policy_keys_file(), std::fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(!TrustStore::load(&paths).key_ids().contains(&"enr".to_string()), "archivo escribible por otros");
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
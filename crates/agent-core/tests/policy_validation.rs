@@ -0,0 +1,170 @@
+// `Policy::validate`: errores por campo (tipo, rango, formato) y avisos (desconocidos, acotados).
+use agent_core::policy::{check_log_filter, Policy, PolicyIssue, ValidationReport};
+use serde_json::json;
+
+fn codes(issues: &[PolicyIssue]) -> Vec<(&str, &str)> {
+    issues.iter().map(|i| (i.field.as_str(), i.code)).collect()
+}
+
+fn has_error(r: &ValidationReport, field: &str, code: &str) -> bool {
+    r.errors.iter().any(|e| e.field == field && e.code == code)
+}
+
+fn has_warning(r: &ValidationReport, field: &str, code: &str) -> bool {
+    r.warnings.iter().any(|w| w.field == field && w.code == code)
+}
+
+#[test]
+fn complete_policy_is_valid() {
+    let raw = json!({
+        "killSwitch": false,
+        "pauseCapture": false,
+        "titleCapture": true,
+        "excludeApps": ["KeePassXC", "1Password"],
+        "excludePatterns": ["*banco*"],
+        "excludeExePaths": ["/opt/secret/**"],
+        "excludeTitleRegex": ["(?i)nómina"],
+        "excludeCaseInsensitive": true,
+        "updateChannel": "stable",
+        "titleSampleHz": 2,
+        "titleBurstPerMinute": 20,
+        "focusMinMinutes": 10,
+        "heartbeatIntervalSecs": 60,
+        "tlsPins": ["sha256/Exh2f3Mx0q7KymBoQ/HoPxC/A7OpisZpGvpDs4OSPPU="],
+        "reportDrops": true,
+        "rules": [{ "apps": ["Slack"], "action": "redact_title" }],
+        "redaction": { "builtins": ["email"] },
+        "logLevel": "info,agent_daemon=debug",
+    });
+    let r = Policy::validate(&raw);
+    assert!(r.valid, "errores: {:?}", r.errors);
+    assert!(r.errors.is_empty());
+    assert!(r.warnings.is_empty(), "avisos: {:?}", r.warnings);
+    let p: Policy = serde_json::from_value(raw).unwrap();
+    assert_eq!(p.titleSampleHz, Some(2));
+    // la policy vacía también es válida
+    assert!(Policy::validate(&json!({})).valid);
+}
+
+#[test]
+fn document_must_be_an_object() {
+    for raw in [json!([]), json!("policy"), json!(null), json!(1)] {
+        let r = Policy::validate(&raw);
+        assert!(!r.valid);
+        assert_eq!(codes(&r.errors), vec![("$", "not_an_object")]);
+    }
+}
+
+#[test]
+fn mistyped_fields_are_rejected_per_field() {
+    let cases = [
+        ("killSwitch", json!("true")),
+        ("titleCapture", json!(1)),
+        ("updateChannel", json!(5)),
+        ("titleSampleHz", json!(-1)),
+        ("titleSampleHz", json!(1.5)),
+        ("heartbeatIntervalSecs", json!("60")),
+        ("focusMinMinutes", json!(5_000_000_000u64)),
+        ("excludeApps", json!("Slack")),
+        ("excludePatterns", json!(["ok", 3])),
+        ("tlsPins", json!({})),
+        ("rules", json!({})),
+        ("redaction", json!([])),
+        ("logLevel", json!(true)),
+    ];
+    for (field, value) in cases {
+        let r = Policy::validate(&json!({ field: value.clone() }));
+        assert!(!r.valid, "{} = {} debería rechazarse", field, value);
+        assert_eq!(codes(&r.errors), vec![(field, "invalid_type")], "{} = {}", field, value);
+    }
+    // null equivale a ausente en los campos opcionales; una lista null no se puede aplicar
+    assert!(Policy::validate(&json!({ "titleSampleHz": null, "logLevel": null })).valid);
+    assert!(!Policy::validate(&json!({ "excludeApps": null })).valid);
+    // varios errores a la vez, cada uno en su campo
+    let r = Policy::validate(&json!({ "killSwitch": 1, "excludeApps": "x", "titleSampleHz": 2 }));
+    assert_eq!(r.errors.len(), 2);
+    assert!(r.summary().contains("killSwitch") && r.summary().contains("excludeApps"));
+}
+
+#[test]
+fn out_of_range_values() {
+    let r = Policy::validate(&json!({ "titleSampleHz": 0 }));
+    assert!(has_error(&r, "titleSampleHz", "out_of_range"));
+    let r = Policy::validate(&json!({ "titleBurstPerMinute": 0 }));
+    assert!(has_error(&r, "titleBurstPerMinute", "out_of_range"));
+    // se aplican, pero acotados: aviso, no error
+    let r = Policy::validate(&json!({ "titleSampleHz": 50, "heartbeatIntervalSecs": 5 }));
+    assert!(r.valid);
+    assert!(has_warning(&r, "titleSampleHz", "clamped"));
+    assert!(has_warning(&r, "heartbeatIntervalSecs", "clamped"));
+    let r = Policy::validate(&json!({ "heartbeatIntervalSecs": 7200 }));
+    assert!(r.valid && has_warning(&r, "heartbeatIntervalSecs", "clamped"));
+    for s in [15, 3600] {
+        assert!(Policy::validate(&json!({ "heartbeatIntervalSecs": s })).warnings.is_empty(), "{} s está en rango", s);
+    }
+    let r = Policy::validate(&json!({ "focusMinMinutes": 0 }));
+    assert!(r.valid && has_warning(&r, "focusMinMinutes", "suspicious"));
+}
+
+#[test]
+fn unknown_fields_warn_with_suggestion() {
+    let r = Policy::validate(&json!({ "excludeApp": ["Slack"], "killswitch": true, "foo": 1 }));
+    assert!(r.valid, "los campos desconocidos no impiden aplicar");
+    assert!(has_warning(&r, "excludeApp", "unknown_field"));
+    let typo = r.warnings.iter().find(|w| w.field == "excludeApp").unwrap();
+    assert!(typo.message.contains("excludeApps"), "{}", typo.message);
+    let case = r.warnings.iter().find(|w| w.field == "killswitch").unwrap();
+    assert!(case.message.contains("killSwitch"), "{}", case.message);
+    let other = r.warnings.iter().find(|w| w.field == "foo").unwrap();
+    assert!(!other.message.contains("quisiste"));
+}
+
+#[test]
+fn malformed_entries_are_rejected_by_index() {
+    let r = Policy::validate(&json!({
+        "excludePatterns": ["ok*", "[abc"],
+        "excludeTitleRegex": ["(abierto"],
+        "tlsPins": ["sha256/AAAA"],
+        "logLevel": "info,=debug",
+    }));
+    assert!(!r.valid);
+    assert!(has_error(&r, "excludePatterns[1]", "invalid_glob"));
+    assert!(has_error(&r, "excludeTitleRegex[0]", "invalid_regex"));
+    assert!(has_error(&r, "tlsPins[0]", "invalid_pin"));
+    assert!(has_error(&r, "logLevel", "invalid_log_filter"));
+    // vacías y repetidas: aviso
+    let r = Policy::validate(&json!({ "excludeApps": ["Slack", " ", "Slack"] }));
+    assert!(r.valid);
+    assert!(has_warning(&r, "excludeApps[1]", "empty_entry"));
+    assert!(has_warning(&r, "excludeApps[2]", "duplicate"));
+}
+
+#[test]
+fn nested_rules_and_redaction_are_validated() {
+    let r = Policy::validate(&json!({
+        "rules": [{ "apps": ["Slack"], "action": "explode" }, { "titleRegex": "(", "action": "drop" }],
+        "redaction": { "builtins": ["email", "dni_marciano"] },
+    }));
+    assert!(!r.valid);
+    assert!(has_error(&r, "rules[0]", "invalid_rule"), "{:?}", r.errors);
+    assert!(has_error(&r, "rules[1].titleRegex", "invalid_regex"), "{:?}", r.errors);
+    assert!(has_error(&r, "redaction.builtins[1]", "unknown_builtin"), "{:?}", r.errors);
+}
+
+#[test]
+fn kill_switch_is_flagged() {
+    let r = Policy::validate(&json!({ "killSwitch": true }));
+    assert!(r.valid && has_warning(&r, "killSwitch", "capture_disabled"));
+}
+
+#[test]
+fn log_filters() {
+    for ok in ["info", "WARN", "agent_daemon=debug", "info,agent_core::http=trace", "agent_daemon"] {
+        assert!(check_log_filter(ok).is_ok(), "{}", ok);
+    }
+    for bad in ["", "  ", "=debug", "agent_daemon=loud", "info debug"] {
+        assert!(check_log_filter(bad).is_err(), "{}", bad);
+    }
+    let r = Policy::validate(&json!({ "logLevel": "trace" }));
+    assert!(r.valid && has_warning(&r, "logLevel", "verbose"));
+}
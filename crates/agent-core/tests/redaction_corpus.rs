@@ -0,0 +1,113 @@
+// Corpus de títulos reales (o casi) para el pipeline de redacción: lo que debe
+// enmascararse y, tan importante, lo que debe quedar intacto.
+use agent_core::policy::{CompiledPolicy, Policy};
+use agent_core::redact::TitleKey;
+
+fn key() -> TitleKey { TitleKey::from_bytes([7u8; 32]) }
+
+fn policy(redaction: serde_json::Value) -> CompiledPolicy {
+    let p: Policy = serde_json::from_value(serde_json::json!({ "redaction": redaction })).unwrap();
+    CompiledPolicy::compile(&p)
+}
+
+const MASKED: &[(&str, &str)] = &[
+    // emails
+    ("Bandeja de entrada - ana.perez@empresa.com - Gmail", "Bandeja de entrada - [email] - Gmail"),
+    ("Re: factura (JUAN+facturas@Mail.Example.co.uk) — Outlook", "Re: factura ([email]) — Outlook"),
+    ("Chat con soporte@ripor.io y ventas@ripor.io", "Chat con [email] y [email]"),
+    // teléfonos
+    ("Llamada con +58 412 555 1234 - WhatsApp", "Llamada con [phone] - WhatsApp"),
+    ("Contacto: (212) 555-0198 | CRM", "Contacto: [phone] | CRM"),
+    ("Cliente 0414-555-7788 pendiente", "Cliente [phone] pendiente"),
+    ("Marcando +14155550123…", "Marcando [phone]…"),
+    // tarjetas y series largas de dígitos
+    ("Pago con 4111 1111 1111 1111 - Stripe", "Pago con [card] - Stripe"),
+    ("Tarjeta 5500-0000-0000-0004 rechazada", "Tarjeta [card] rechazada"),
+    ("Cuenta 01020123450000123456 - Banco", "Cuenta [digits] - Banco"),
+    ("Pedido 987654321 confirmado", "Pedido [digits] confirmado"),
+    ("Cédula 12345678 — Registro", "Cédula [digits] — Registro"),
+    // rutas bajo el home
+    ("/Users/ana/Documents/contrato_cliente.pdf - Vista Previa", "[home_path] - Vista Previa"),
+    ("nvim /home/jperez/proyectos/nomina/src/main.rs", "nvim [home_path]"),
+    // la ruta termina en el primer espacio (el resto no identifica al usuario)
+    ("C:\\Users\\MariaG\\Desktop\\sueldos 2024.xlsx - Excel", "[home_path] 2024.xlsx - Excel"),
+    ("~/proyectos/acme-fusion/notas.md — zsh", "[home_path] — zsh"),
+    // varios en un mismo título
+    ("ana@x.io llamó desde +34 600 123 456 por /home/ana/f.txt", "[email] llamó desde [phone] por [home_path]"),
+];
+
+const UNTOUCHED: &[&str] = &[
+    "main.rs — timeTracker",
+    "Pull request #12345 · gerswin/timeTracker",
+    "Reunión 2024-06-15 10:30 - Zoom",
+    "Versión 1.2.30 publicada",
+    "Excel - Q3 2024 presupuesto",
+    "localhost:49219 - Panel",
+    "127.0.0.1 - Chrome",
+    "Sala 101-202",
+    "@ana mencionó en #general - Slack",
+    "/usr/local/bin/agent --help",
+    "/var/log/system.log",
+    "Ticket ABC-1234 en curso",
+    "iPhone 15 Pro Max - Apple Store",
+    "",
+];
+
+#[test]
+fn masks_pii() {
+    let p = policy(serde_json::json!({}));
+    for (input, expected) in MASKED {
+        assert_eq!(p.redact(input, &key()), *expected, "entrada: {:?}", input);
+    }
+}
+
+#[test]
+fn leaves_ordinary_titles_alone() {
+    let p = policy(serde_json::json!({}));
+    for input in UNTOUCHED {
+        assert_eq!(p.redact(input, &key()), *input);
+    }
+}
+
+#[test]
+fn keyed_hash_groups_without_revealing() {
+    let p = policy(serde_json::json!({ "hash": true }));
+    let a = p.redact("Chat con ana@x.io", &key()).into_owned();
+    let b = p.redact("Llamada: ana@x.io", &key()).into_owned();
+    let c = p.redact("Chat con luis@x.io", &key()).into_owned();
+    assert!(!a.contains("ana"), "{}", a);
+    assert!(a.starts_with("Chat con [email:") && a.ends_with(']'), "{}", a);
+    // mismo valor → mismo token; otro valor → otro token
+    assert_eq!(a.trim_start_matches("Chat con "), b.trim_start_matches("Llamada: "));
+    assert_ne!(a, c);
+    // otra clave de dispositivo → otro token
+    let other = p.redact("Chat con ana@x.io", &TitleKey::from_bytes([8u8; 32])).into_owned();
+    assert_ne!(a, other);
+}
+
+#[test]
+fn policy_patterns_and_builtin_subset() {
+    let p = policy(serde_json::json!({ "builtins": ["email"], "patterns": ["(?i)proyecto\\s+\\w+"] }));
+    assert_eq!(p.redact("Proyecto Halcón - ana@x.io - 4111 1111 1111 1111", &key()), "[redacted] - [email] - 4111 1111 1111 1111");
+    let off = policy(serde_json::json!({ "enabled": false }));
+    assert_eq!(off.redact("ana@x.io", &key()), "ana@x.io");
+}
+
+#[test]
+fn title_token_is_stable_per_key() {
+    let k = key();
+    assert_eq!(k.title_token("Cuenta banco"), k.title_token("Cuenta banco"));
+    assert_ne!(k.title_token("Cuenta banco"), k.title_token("Cuenta banco 2"));
+    assert!(k.title_token("x").starts_with("hmac:"));
+}
+
+#[test]
+fn validation_reports_redaction_errors() {
+    let raw = serde_json::json!({ "redaction": { "builtins": ["email", "ssn"], "patterns": ["(unclosed"], "enabled": false, "mask": 1 } });
+    let r = Policy::validate(&raw);
+    let codes: Vec<_> = r.errors.iter().map(|e| (e.field.as_str(), e.code)).collect();
+    assert!(codes.contains(&("redaction.builtins[1]", "unknown_builtin")), "{:?}", codes);
+    assert!(codes.contains(&("redaction.patterns[0]", "invalid_regex")), "{:?}", codes);
+    let warns: Vec<_> = r.warnings.iter().map(|w| w.code).collect();
+    assert!(warns.contains(&"redaction_disabled") && warns.contains(&"unknown_field"), "{:?}", warns);
+}
@@ -0,0 +1,193 @@
+// Comandos remotos: firma Ed25519 con las claves de policy, destinatario, vigencia, idempotencia
+// por `id` y allowlist.
+use agent_core::backend::{CommandAck, CommandStatus, RemoteCommand};
+use agent_core::commands::{allowed_from, CommandGate, Recipient, Verdict, DEFAULT_ALLOWED, KNOWN_ACTIONS, LEDGER_CAP, MAX_AGE_MS};
+use agent_core::paths::Paths;
+use agent_core::policy_sig::{SignatureMode, TrustStore, TrustedKey};
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::json;
+
+const B64: base64::engine::GeneralPurpose = base64::engine::general_purpose::STANDARD;
+const NOW: u64 = 1_790_000_000_000;
+const ME: Recipient = Recipient { device_id: "dev-a", org_id: Some("org-1") };
+
+fn key(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32])
+}
+
+fn trust() -> TrustStore {
+    let k = key(1);
+    TrustStore::new(&[TrustedKey { key_id: "k1".into(), public_key: B64.encode(k.verifying_key().as_bytes()) }], SignatureMode::Enforce)
+}
+
+fn command(id: &str, action: &str, args: serde_json::Value) -> RemoteCommand {
+    RemoteCommand { id: id.into(), device_id: "dev-a".into(), org_id: None, action: action.into(), args, issued_at: NOW - 1_000, expires_at: Some(NOW + 60_000), key_id: None, sig: String::new() }
+}
+
+fn signed(mut cmd: RemoteCommand, seed: u8, kid: Option<&str>) -> RemoteCommand {
+    cmd.key_id = kid.map(str::to_string);
+    cmd.sig = B64.encode(key(seed).sign(cmd.signing_input().as_bytes()).to_bytes());
+    cmd
+}
+
+fn all_allowed() -> CommandGate {
+    CommandGate::in_memory(KNOWN_ACTIONS.to_vec())
+}
+
+fn ack(cmd: &RemoteCommand) -> CommandAck {
+    CommandAck { id: cmd.id.clone(), action: cmd.action.clone(), status: CommandStatus::Done, detail: None, finished_ms: NOW }
+}
+
+const BAD_SIG: Verdict = Verdict::Reject { detail: "bad_signature", remember: false };
+
+#[test]
+fn signed_command_runs() {
+    let cmd = signed(command("c1", "refresh_policy", serde_json::Value::Null), 1, Some("k1"));
+    assert_eq!(all_allowed().check(&cmd, ME, &trust(), NOW), Verdict::Run);
+    // sin keyId se prueba con todas las claves de confianza
+    let cmd = signed(command("c2", "pause", json!({"minutes": 5})), 1, None);
+    assert_eq!(all_allowed().check(&cmd, ME, &trust(), NOW), Verdict::Run);
+}
+
+#[test]
+fn bad_signatures_are_rejected_and_not_remembered() {
+    let gate = all_allowed();
+    let t = trust();
+    // sin firma
+    assert_eq!(gate.check(&command("c1", "flush_queue", serde_json::Value::Null), ME, &t, NOW), BAD_SIG);
+    // clave que no es de confianza, con y sin keyId
+    assert_eq!(gate.check(&signed(command("c1", "flush_queue", serde_json::Value::Null), 9, Some("k9")), ME, &t, NOW), BAD_SIG);
+    assert_eq!(gate.check(&signed(command("c1", "flush_queue", serde_json::Value::Null), 9, None), ME, &t, NOW), BAD_SIG);
+    // alterado tras firmar: acción, args o vencimiento
+    let mut c = signed(command("c1", "refresh_policy", serde_json::Value::Null), 1, Some("k1"));
+    c.action = "reenroll".into();
+    assert_eq!(gate.check(&c, ME, &t, NOW), BAD_SIG);
+    let mut c = signed(command("c1", "pause", json!({"minutes": 5})), 1, Some("k1"));
+    c.args = json!({"minutes": 1440});
+    assert_eq!(gate.check(&c, ME, &t, NOW), BAD_SIG);
+    let mut c = signed(command("c1", "pause", json!({"minutes": 5})), 1, Some("k1"));
+    c.expires_at = Some(NOW + 365 * 86_400_000);
+    assert_eq!(gate.check(&c, ME, &t, NOW), BAD_SIG);
+    // sin claves de confianza nada se ejecuta
+    let c = signed(command("c1", "flush_queue", serde_json::Value::Null), 1, Some("k1"));
+    assert_eq!(gate.check(&c, ME, &TrustStore::new(&[], SignatureMode::Enforce), NOW), BAD_SIG);
+    // el legítimo con el mismo id sigue pudiendo ejecutarse
+    assert_eq!(gate.check(&c, ME, &t, NOW), Verdict::Run);
+}
+
+#[test]
+fn signature_is_required_even_when_policy_checks_are_off() {
+    let off = TrustStore::new(&[], SignatureMode::Off);
+    let c = signed(command("c1", "flush_queue", serde_json::Value::Null), 1, Some("k1"));
+    assert_eq!(all_allowed().check(&c, ME, &off, NOW), BAD_SIG);
+}
+
+#[test]
+fn commands_are_bound_to_their_device() {
+    let gate = all_allowed();
+    let wrong_device = Verdict::Reject { detail: "wrong_device", remember: false };
+    let for_a = signed(command("c1", "pause", json!({"minutes": 60})), 1, Some("k1"));
+    let on_b = Recipient { device_id: "dev-b", org_id: Some("org-1") };
+    assert_eq!(gate.check(&for_a, on_b, &trust(), NOW), wrong_device);
+    // cambiar el destinatario tras firmar invalida la firma
+    let mut moved = for_a.clone();
+    moved.device_id = "dev-b".into();
+    assert_eq!(gate.check(&moved, on_b, &trust(), NOW), BAD_SIG);
+    // sin deviceId no va dirigido a nadie
+    let mut c = command("c2", "flush_queue", serde_json::Value::Null);
+    c.device_id = String::new();
+    assert_eq!(gate.check(&signed(c, 1, Some("k1")), ME, &trust(), NOW), wrong_device);
+    // el rechazo en B no se recuerda ni bloquea la ejecución en A
+    assert_eq!(gate.check(&for_a, ME, &trust(), NOW), Verdict::Run);
+}
+
+#[test]
+fn org_is_checked_when_signed_in() {
+    let gate = all_allowed();
+    let mut c = command("c1", "flush_queue", serde_json::Value::Null);
+    c.org_id = Some("org-2".into());
+    let other_org = signed(c.clone(), 1, Some("k1"));
+    assert_eq!(gate.check(&other_org, ME, &trust(), NOW), Verdict::Reject { detail: "wrong_org", remember: false });
+    // un agente sin identidad no acepta comandos dirigidos a una org
+    let unenrolled = Recipient { device_id: "dev-a", org_id: None };
+    assert_eq!(gate.check(&other_org, unenrolled, &trust(), NOW), Verdict::Reject { detail: "wrong_org", remember: false });
+    c.org_id = Some("org-1".into());
+    assert_eq!(gate.check(&signed(c, 1, Some("k1")), ME, &trust(), NOW), Verdict::Run);
+}
+
+#[test]
+fn expired_commands_are_rejected() {
+    let expired = Verdict::Reject { detail: "expired", remember: true };
+    let mut c = command("c1", "flush_queue", serde_json::Value::Null);
+    c.expires_at = Some(NOW - 1);
+    assert_eq!(all_allowed().check(&signed(c, 1, Some("k1")), ME, &trust(), NOW), expired);
+    // sin expiresAt vale MAX_AGE_MS desde issuedAt
+    let mut c = command("c2", "flush_queue", serde_json::Value::Null);
+    c.expires_at = None;
+    c.issued_at = NOW - MAX_AGE_MS - 1;
+    assert_eq!(all_allowed().check(&signed(c.clone(), 1, Some("k1")), ME, &trust(), NOW), expired);
+    c.issued_at = NOW - MAX_AGE_MS;
+    assert_eq!(all_allowed().check(&signed(c, 1, Some("k1")), ME, &trust(), NOW), Verdict::Run);
+}
+
+#[test]
+fn executed_ids_are_replayed_not_rerun() {
+    let gate = all_allowed();
+    let c = signed(command("c1", "rotate_keys", serde_json::Value::Null), 1, Some("k1"));
+    assert_eq!(gate.check(&c, ME, &trust(), NOW), Verdict::Run);
+    gate.record(&ack(&c));
+    assert_eq!(gate.check(&c, ME, &trust(), NOW), Verdict::Replay(ack(&c)));
+    // el id manda, aunque cambie el resto
+    let other = signed(command("c1", "flush_queue", serde_json::Value::Null), 1, Some("k1"));
+    assert!(matches!(gate.check(&other, ME, &trust(), NOW), Verdict::Replay(_)));
+    assert_eq!(gate.recent(5), vec![ack(&c)]);
+}
+
+#[test]
+fn ledger_survives_restart_and_is_capped() {
+    let dir = std::env::temp_dir().join(format!("ripor-commands-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = Paths { data_dir: dir.clone() };
+    let gate = CommandGate::load(&paths, KNOWN_ACTIONS.to_vec());
+    for i in 0..LEDGER_CAP + 10 {
+        gate.record(&ack(&command(&format!("c{}", i), "flush_queue", serde_json::Value::Null)));
+    }
+    let reloaded = CommandGate::load(&paths, KNOWN_ACTIONS.to_vec());
+    let recent = reloaded.recent(LEDGER_CAP + 10);
+    assert_eq!(recent.len(), LEDGER_CAP);
+    assert_eq!(recent[0].id, format!("c{}", LEDGER_CAP + 9));
+    let last = signed(command(&format!("c{}", LEDGER_CAP + 9), "flush_queue", serde_json::Value::Null), 1, Some("k1"));
+    assert!(matches!(reloaded.check(&last, ME, &trust(), NOW), Verdict::Replay(_)));
+    // los más viejos se olvidaron
+    let first = signed(command("c0", "flush_queue", serde_json::Value::Null), 1, Some("k1"));
+    assert_eq!(reloaded.check(&first, ME, &trust(), NOW), Verdict::Run);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn default_allowlist_is_the_safe_subset() {
+    let gate = CommandGate::in_memory(allowed_from(None));
+    assert_eq!(gate.allowed, DEFAULT_ALLOWED);
+    for action in ["refresh_policy", "flush_queue"] {
+        let c = signed(command("c", action, serde_json::Value::Null), 1, Some("k1"));
+        assert_eq!(gate.check(&c, ME, &trust(), NOW), Verdict::Run, "{}", action);
+    }
+    for action in ["upload_diagnostics", "pause", "rotate_keys", "reenroll"] {
+        let c = signed(command("c", action, serde_json::Value::Null), 1, Some("k1"));
+        assert_eq!(gate.check(&c, ME, &trust(), NOW), Verdict::Reject { detail: "not_allowed", remember: true }, "{}", action);
+    }
+    let c = signed(command("c", "rm_rf", serde_json::Value::Null), 1, Some("k1"));
+    assert_eq!(gate.check(&c, ME, &trust(), NOW), Verdict::Reject { detail: "unknown_action", remember: true });
+}
+
+#[test]
+fn allowlist_parsing() {
+    assert_eq!(allowed_from(Some("pause, reenroll,pause")), vec!["pause", "reenroll"]);
+    assert_eq!(allowed_from(Some("none")), Vec::<&str>::new());
+    assert_eq!(allowed_from(Some("")), Vec::<&str>::new());
+    assert_eq!(allowed_from(Some("flush_queue,rm_rf")), vec!["flush_queue"]);
+    let gate = CommandGate::in_memory(allowed_from(Some("none")));
+    let c = signed(command("c", "refresh_policy", serde_json::Value::Null), 1, Some("k1"));
+    assert_eq!(gate.check(&c, ME, &trust(), NOW), Verdict::Reject { detail: "not_allowed", remember: true });
+}
@@ -0,0 +1,98 @@
+// Renovación del `agent_token`: cuándo se considera vencido (arranque y renovación proactiva),
+// cuándo un refresh rechazado cae a bootstrap, cuándo otro loop ya lo renovó y compatibilidad de
+// `agent_secrets.json` con backends que no informan expiración ni refresh token.
+use agent_core::auth::AgentSecrets;
+use agent_core::backend::BackendError;
+use agent_core::paths::Paths;
+
+const NOW: u64 = 1_790_000_000_000;
+const MINUTE: u64 = 60_000;
+
+fn secrets(expires_at: Option<u64>) -> AgentSecrets {
+    AgentSecrets {
+        agent_token: "tok".into(),
+        server_salt: "salt".into(),
+        device_id: Some("dev-1".into()),
+        expires_at,
+        refresh_token: Some("ref".into()),
+    }
+}
+
+#[test]
+fn expired_token_is_due_at_startup() {
+    // `ensure_bootstrapped` usa margen 0: vencido justo en `expires_at` o antes
+    assert!(secrets(Some(NOW - 1)).expires_within(NOW, 0));
+    assert!(secrets(Some(NOW)).expires_within(NOW, 0));
+    assert!(!secrets(Some(NOW + 1)).expires_within(NOW, 0));
+}
+
+#[test]
+fn margin_triggers_proactive_refresh() {
+    let s = secrets(Some(NOW + 5 * MINUTE));
+    assert!(s.expires_within(NOW, 5 * MINUTE));
+    assert!(s.expires_within(NOW, 10 * MINUTE));
+    assert!(!s.expires_within(NOW, 5 * MINUTE - 1));
+    // márgenes enormes no desbordan
+    assert!(s.expires_within(NOW, u64::MAX));
+    assert!(secrets(Some(u64::MAX)).expires_within(u64::MAX, 0));
+}
+
+#[test]
+fn token_without_expiry_never_expires() {
+    let s = secrets(None);
+    assert!(!s.expires_within(NOW, 0));
+    assert!(!s.expires_within(u64::MAX, u64::MAX));
+}
+
+#[test]
+fn rejected_refresh_falls_back_to_bootstrap() {
+    for e in [BackendError::Unauthorized, BackendError::Forbidden, BackendError::Decode("sin agent_token".into())] {
+        assert!(e.rejects_refresh(), "{}", e);
+    }
+    // transitorios o locales: se reintenta el refresh más tarde, sin perder el refresh token
+    for e in [
+        BackendError::RateLimited { retry_after: None },
+        BackendError::Server { status: 503 },
+        BackendError::Config("API_BASE_URL".into()),
+        BackendError::Secrets("disco lleno".into()),
+        BackendError::EnrollmentRequired,
+    ] {
+        assert!(!e.rejects_refresh(), "{}", e);
+    }
+}
+
+#[test]
+fn renewal_by_another_loop_is_reused() {
+    let cur = secrets(Some(NOW));
+    // quien vio vencer el token vigente debe renovarlo
+    assert!(!cur.supersedes(Some("tok")));
+    // otro loop ya lo cambió mientras se esperaba el lock
+    assert!(cur.supersedes(Some("tok-anterior")));
+    // bootstrap pedido sin credenciales, pero otro loop ya las obtuvo
+    assert!(cur.supersedes(None));
+}
+
+#[test]
+fn secrets_file_without_expiry_fields_still_loads() {
+    let dir = std::env::temp_dir().join(format!("ripor-auth-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = Paths { data_dir: dir.clone() };
+    // formato anterior a la expiración
+    std::fs::write(paths.secrets_file(), r#"{"agent_token":"tok","server_salt":"salt","device_id":null}"#).unwrap();
+    let old = AgentSecrets::load(&paths).unwrap().unwrap();
+    assert_eq!((old.expires_at, old.refresh_token.as_deref()), (None, None));
+    assert!(!old.expires_within(NOW, 10 * MINUTE));
+    // sin expiración ni refresh token no se escriben los campos
+    old.save(&paths).unwrap();
+    let txt = std::fs::read_to_string(paths.secrets_file()).unwrap();
+    assert!(!txt.contains("expires_at") && !txt.contains("refresh_token"), "{}", txt);
+    // con ellos, sobreviven al guardado
+    secrets(Some(NOW)).save(&paths).unwrap();
+    let s = AgentSecrets::load(&paths).unwrap().unwrap();
+    assert_eq!((s.expires_at, s.refresh_token.as_deref()), (Some(NOW), Some("ref")));
+    #[cfg(unix)] {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(paths.secrets_file()).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -2,13 +2,14 @@ use agent_core::queue::Queue;
 use agent_core::state::AgentState;
 use anyhow::Result;
 use serde::Serialize;
-use crate::policy::{PolicyRuntime, PolicyState};
-use globset::{Glob, GlobSetBuilder};
+use crate::policy::PolicyState;
 #[cfg(target_os = "macos")]
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::time::{sleep, Duration};
+use crate::otel::pipeline_span;
+use tracing::field::Empty;
 use tracing::{debug, info, warn};
 
 #[derive(Debug, Clone, Serialize)]
@@ -17,6 +18,55 @@ struct CaptureEvent {
     app_name: String,
     window_title: String,
     input_idle_ms: u64,
+    /// Span de captura (W3C), para enlazarlo desde el envío; solo con trazas OTLP activas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    traceparent: Option<String>,
+}
+
+#[cfg(target_os = "macos")]
+const CAPTURE_BACKEND: &str = "macos-ax";
+#[cfg(target_os = "windows")]
+const CAPTURE_BACKEND: &str = "win32";
+#[cfg(target_os = "linux")]
+const CAPTURE_BACKEND: &str = "linux-stub";
+
+/// Errores seguidos de `sample_once` a partir de los cuales la captura se reporta como no sana
+const UNHEALTHY_AFTER_ERRORS: u64 = 5;
+/// Un tramo excluido se corta si pasa esto sin muestras descartadas (loop detenido, suspensión)
+const DROP_SPAN_GAP_MS: u64 = 5_000;
+/// ... y se encola al llegar a este largo, para que el backend no espere al final del tramo
+const DROP_SPAN_MAX_MS: u64 = 5 * 60_000;
+const DROPS_SAVE_EVERY_MS: u64 = 10_000;
+
+/// Salud del loop de captura, publicada en `/state` y en el heartbeat.
+#[derive(Default)]
+pub struct CaptureHealth {
+    last_ok_ms: AtomicU64,
+    consecutive_errors: AtomicU64,
+    last_error: std::sync::Mutex<Option<String>>,
+}
+
+impl CaptureHealth {
+    pub fn new() -> Arc<Self> { Arc::new(Self::default()) }
+    fn on_ok(&self) {
+        self.last_ok_ms.store(now_ms(), Ordering::Relaxed);
+        self.consecutive_errors.store(0, Ordering::Relaxed);
+    }
+    fn on_error(&self, e: &anyhow::Error) {
+        self.consecutive_errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(e.to_string());
+    }
+    pub fn report(&self) -> agent_core::backend::CaptureHealthReport {
+        let last_ok = self.last_ok_ms.load(Ordering::Relaxed);
+        let errors = self.consecutive_errors.load(Ordering::Relaxed);
+        agent_core::backend::CaptureHealthReport {
+            backend: CAPTURE_BACKEND.to_string(),
+            ok: last_ok != 0 && errors < UNHEALTHY_AFTER_ERRORS,
+            last_sample_ms: (last_ok != 0).then_some(last_ok),
+            consecutive_errors: errors,
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -78,70 +128,104 @@ impl FocusAgg {
     }
 }
 
-pub async fn run_capture_loop(
-    state: Arc<AgentState>,
-    paths: &agent_core::paths::Paths,
-    last_event_ts: Arc<AtomicU64>,
-    last_idle_ms: Arc<AtomicU64>,
-    paused_until_ms: Arc<AtomicU64>,
-    policy_rt: std::sync::Arc<PolicyRuntime>,
-    dropped_counter: Arc<AtomicU64>,
-    drop_counters: Arc<crate::policy::DropCounters>,
-    drop_log: Arc<crate::policy::DropLog>,
-    focus_agg: std::sync::Arc<FocusAgg>,
-) {
+pub async fn run_capture_loop(ctx: crate::AppCtx) {
+    let crate::AppCtx {
+        state, paths, last_event_ts, last_idle_ms, paused_until_ms, policy_rt,
+        dropped_events: dropped_counter, drop_counters, drop_log, focus_agg,
+        capture_health: health, registry, ..
+    } = ctx;
+    let paths = &paths;
     info!("iniciando loop de captura (Fase 1)");
     println!("[debug] capture loop started");
     let mut prev_app = String::new();
     let mut prev_title = String::new();
     // Throttle state
     let mut thr = Throttle::new();
+    let mut span: Option<DropSpan> = None;
+    let mut drops_saved_ms = now_ms();
+    let mut drops_dirty = false;
+    // clave de los hashes de títulos (HMAC); sin ella no se puede redactar en modo hash
+    let title_key = match agent_core::redact::TitleKey::load(paths) {
+        Ok(k) => k,
+        Err(e) => {
+            warn!(error=%e, "no se pudo cargar la clave del dispositivo; captura detenida");
+            return;
+        }
+    };
     loop {
         debug!("capture tick");
-        // Respetar pausa
         let now = now_ms();
+        if drops_dirty && now.saturating_sub(drops_saved_ms) >= DROPS_SAVE_EVERY_MS {
+            drop_counters.save();
+            (drops_saved_ms, drops_dirty) = (now, false);
+        }
+        // Respetar pausa
         if paused_until_ms.load(Ordering::Relaxed) > now {
+            flush_span(&mut span, paths, &state);
             sleep(Duration::from_millis(500)).await;
             continue;
         }
-        match sample_once() {
+        let tick = pipeline_span!("capture.tick", app = Empty, outcome = Empty);
+        let sample_started = std::time::Instant::now();
+        let sample = tick.in_scope(sample_once);
+        registry.observe("capture_sample_ms", &[], sample_started.elapsed().as_secs_f64() * 1000.0);
+        match sample {
             Ok((app, title, idle_ms)) => {
+                health.on_ok();
+                tick.record("app", app.as_str());
                 last_idle_ms.store(idle_ms, Ordering::Relaxed);
                 debug!(app = ?app, title = ?title, idle_ms, "sample actual");
                 // Apply policy filters
                 let pol = policy_rt.get();
                 thr.update_from_policy(&pol.policy);
-                if let Some(reason) = drop_reason(&pol, &app, &title) {
-                    dropped_counter.fetch_add(1, Ordering::Relaxed);
-                    match reason {
-                        DropReason::KillSwitch => drop_counters.kill_switch.fetch_add(1, Ordering::Relaxed),
-                        DropReason::PauseCapture => drop_counters.pause.fetch_add(1, Ordering::Relaxed),
-                        DropReason::ExcludedApp => drop_counters.excluded_app.fetch_add(1, Ordering::Relaxed),
-                        DropReason::ExcludedPattern => drop_counters.excluded_pattern.fetch_add(1, Ordering::Relaxed),
-                        DropReason::Throttled => drop_counters.throttled.fetch_add(1, Ordering::Relaxed),
-                    };
-                    drop_log.push(crate::policy::DropEvent { ts_ms: now_ms(), reason: match reason { DropReason::KillSwitch=>"killSwitch", DropReason::PauseCapture=>"pauseCapture", DropReason::ExcludedApp=>"excludedApp", DropReason::ExcludedPattern=>"excludedPattern", DropReason::Throttled=>"throttled" }.to_string(), app: app.clone(), title: title.clone() });
-                    sleep(Duration::from_millis(1000)).await;
-                    continue;
-                }
-                let effective_title = if pol.policy.titleCapture { title.clone() } else { String::new() };
+                let effective_title = match apply_policy(&pol, &app, &title, &title_key) {
+                    Ok(t) => t,
+                    Err(reason) => {
+                        dropped_counter.fetch_add(1, Ordering::Relaxed);
+                        drops_dirty = true;
+                        registry.inc("events_dropped", &reason.metric_labels(), 1);
+                        match reason.span_label().filter(|_| pol.policy.reportDrops) {
+                            Some(label) => extend_span(&mut span, label, now, paths, &state),
+                            None => flush_span(&mut span, paths, &state),
+                        }
+                        match reason {
+                            DropReason::KillSwitch => drop_counters.kill_switch.fetch_add(1, Ordering::Relaxed),
+                            DropReason::PauseCapture => drop_counters.pause.fetch_add(1, Ordering::Relaxed),
+                            DropReason::ExcludedApp => drop_counters.excluded_app.fetch_add(1, Ordering::Relaxed),
+                            DropReason::ExcludedPattern => drop_counters.excluded_pattern.fetch_add(1, Ordering::Relaxed),
+                            DropReason::Rule(_) => drop_counters.rule.fetch_add(1, Ordering::Relaxed),
+                            DropReason::Throttled => drop_counters.throttled.fetch_add(1, Ordering::Relaxed),
+                        };
+                        drop_log.push(crate::policy::DropEvent { ts_ms: now_ms(), reason: reason.label(), app: app.clone(), title: pol.compiled.redact(&title, &title_key).into_owned() });
+                        tick.record("outcome", "dropped");
+                        drop(tick);
+                        sleep(Duration::from_millis(1000)).await;
+                        continue;
+                    }
+                };
+                flush_span(&mut span, paths, &state);
                 // Emitir solo en cambio o cada 30s
                 let changed = app != prev_app || effective_title != prev_title;
                 let force_emit = should_force_emit(last_event_ts.load(Ordering::Relaxed));
                 if changed || force_emit {
                     if !thr.permit(now, force_emit) {
                         dropped_counter.fetch_add(1, Ordering::Relaxed);
+                        drops_dirty = true;
                         drop_counters.throttled.fetch_add(1, Ordering::Relaxed);
+                        registry.inc("events_dropped", &DropReason::Throttled.metric_labels(), 1);
                         drop_log.push(crate::policy::DropEvent { ts_ms: now_ms(), reason: "throttled".into(), app: app.clone(), title: effective_title.clone() });
                         // Throttled: no emit this tick
+                        tick.record("outcome", "throttled");
+                        drop(tick);
                         sleep(Duration::from_millis(1000)).await;
                         continue;
                     }
-                    let evt = CaptureEvent {
+                    let mut evt = CaptureEvent {
                         ts_ms: now_ms(),
                         app_name: app.clone(),
                         window_title: effective_title.clone(),
                         input_idle_ms: idle_ms,
+                        traceparent: None,
                     };
                     if let Some(block) = focus_agg.on_event(evt.ts_ms, &evt.app_name, &evt.window_title) {
                         // Si cumple el umbral de focus, encolar evento de bloque para el sender
@@ -173,14 +257,22 @@ pub async fn run_capture_loop(
                         }
                     }
                     debug!("abriendo queue para enqueue");
+                    let enqueue = pipeline_span!(parent: &tick, "queue.enqueue", event.id = Empty);
+                    let _entered = enqueue.enter();
+                    evt.traceparent = crate::otel::traceparent();
                     if let Ok(q) = Queue::open(paths, &state) {
-                        if let Ok(_) = q.enqueue_json(&serde_json::to_vec(&evt).unwrap()) {
+                        if let Ok(id) = q.enqueue_json(&serde_json::to_vec(&evt).unwrap()) {
+                            enqueue.record("event.id", id);
+                            tick.record("outcome", "enqueued");
                             last_event_ts.store(evt.ts_ms, Ordering::Relaxed);
+                            registry.inc("events_enqueued", &[], 1);
                             info!(app = ?evt.app_name, title = ?evt.window_title, "captura encolada");
                         } else {
+                            tick.record("outcome", "enqueue_failed");
                             warn!("falló enqueue captura");
                         }
                     } else {
+                        tick.record("outcome", "enqueue_failed");
                         warn!("falló abrir cola");
                     }
                     prev_app = app;
@@ -188,9 +280,13 @@ pub async fn run_capture_loop(
                 }
             }
             Err(e) => {
+                tick.record("outcome", "error");
+                flush_span(&mut span, paths, &state);
+                health.on_error(&e);
                 debug!(?e, "sample_once error");
             }
         }
+        drop(tick);
         sleep(Duration::from_millis(1000)).await;
     }
 }
@@ -203,29 +299,103 @@ fn should_force_emit(last_ts: u64) -> bool {
     now.saturating_sub(last_ts) > 30_000
 }
 
-#[derive(Copy, Clone)]
-enum DropReason { KillSwitch, PauseCapture, ExcludedApp, ExcludedPattern, Throttled }
-
-fn drop_reason(pol: &PolicyState, app: &str, title: &str) -> Option<DropReason> {
-    let p = &pol.policy;
-    if p.killSwitch { return Some(DropReason::KillSwitch); }
-    if p.pauseCapture { return Some(DropReason::PauseCapture); }
-    if !p.excludeApps.is_empty() && p.excludeApps.iter().any(|a| a == app) { return Some(DropReason::ExcludedApp); }
-    if !p.excludePatterns.is_empty() {
-        let mut b = GlobSetBuilder::new();
-        for pat in &p.excludePatterns {
-            if let Ok(g) = Glob::new(pat) { b.add(g); }
+#[derive(Clone)]
+enum DropReason { KillSwitch, PauseCapture, ExcludedApp, ExcludedPattern, Rule(String), Throttled }
+
+impl DropReason {
+    fn name(&self) -> &'static str {
+        match self {
+            DropReason::KillSwitch => "killSwitch",
+            DropReason::PauseCapture => "pauseCapture",
+            DropReason::ExcludedApp => "excludedApp",
+            DropReason::ExcludedPattern => "excludedPattern",
+            DropReason::Rule(_) => "rule",
+            DropReason::Throttled => "throttled",
         }
-        if let Ok(gs) = b.build() { if gs.is_match(title) { return Some(DropReason::ExcludedPattern); } }
     }
-    if !p.excludeExePaths.is_empty() {
-        if let Some(exe) = front_exe_identity() {
-            let mut b = GlobSetBuilder::new();
-            for pat in &p.excludeExePaths { if let Ok(g) = Glob::new(pat) { b.add(g); } }
-            if let Ok(gs) = b.build() { if gs.is_match(&exe) { return Some(DropReason::ExcludedPattern); } }
+
+    fn label(&self) -> String {
+        match self {
+            DropReason::Rule(name) => format!("rule:{}", name),
+            _ => self.name().to_string(),
         }
     }
-    None
+
+    /// Etiquetas de `events_dropped` (las reglas con su nombre: la métrica es local).
+    fn metric_labels(&self) -> Vec<(&'static str, &str)> {
+        match self {
+            DropReason::Rule(name) => vec![("reason", self.name()), ("rule", name.as_str())],
+            _ => vec![("reason", self.name())],
+        }
+    }
+
+    /// Motivo con el que el tiempo descartado se reporta (`reportDrops`). Sin nombre de regla:
+    /// el backend cuenta el tiempo excluido sin saber qué lo excluyó. `killSwitch` no reporta
+    /// nada y `throttled` no es tiempo excluido (la actividad sigue en el evento anterior).
+    fn span_label(&self) -> Option<&'static str> {
+        match self {
+            DropReason::KillSwitch | DropReason::Throttled => None,
+            _ => Some(self.name()),
+        }
+    }
+}
+
+/// Tramo continuo de muestras descartadas por el mismo motivo.
+struct DropSpan { reason: &'static str, start_ms: u64, last_ms: u64 }
+
+fn extend_span(span: &mut Option<DropSpan>, reason: &'static str, now: u64, paths: &agent_core::paths::Paths, state: &AgentState) {
+    if let Some(s) = span.as_mut() {
+        if s.reason == reason && now.saturating_sub(s.last_ms) <= DROP_SPAN_GAP_MS && now.saturating_sub(s.start_ms) < DROP_SPAN_MAX_MS {
+            s.last_ms = now;
+            return;
+        }
+    }
+    flush_span(span, paths, state);
+    *span = Some(DropSpan { reason, start_ms: now, last_ms: now });
+}
+
+/// Encola el tramo como evento anónimo: solo motivo y tiempos.
+fn flush_span(span: &mut Option<DropSpan>, paths: &agent_core::paths::Paths, state: &AgentState) {
+    let Some(s) = span.take() else { return };
+    // cada muestra descartada cubre un tick del loop (~1 s)
+    let end_ms = s.last_ms + 1000;
+    let evt = serde_json::json!({
+        "type": "excluded",
+        "dropped_reason": s.reason,
+        "start_ms": s.start_ms,
+        "end_ms": end_ms,
+        "dur_ms": end_ms - s.start_ms,
+        "ts_ms": end_ms,
+    });
+    match Queue::open(paths, state) {
+        Ok(q) => { let _ = q.enqueue_json(&serde_json::to_vec(&evt).unwrap()); }
+        Err(e) => warn!(error=%e, "no se pudo encolar tramo excluido"),
+    }
+}
+
+/// Aplica la policy a una muestra: título a capturar (ya redactado) o motivo de descarte.
+/// Orden: killSwitch/pauseCapture, `rules` (la primera que coincide), exclusiones planas,
+/// `titleCapture` y por último la redacción de datos personales (también con `keep`).
+fn apply_policy(pol: &PolicyState, app: &str, title: &str, key: &agent_core::redact::TitleKey) -> Result<String, DropReason> {
+    use agent_core::rules::{Moment, RuleAction, REDACTED_TITLE};
+    let (p, c) = (&pol.policy, &pol.compiled);
+    if p.killSwitch { return Err(DropReason::KillSwitch); }
+    if p.pauseCapture { return Err(DropReason::PauseCapture); }
+    let hit = if c.has_rules() { c.match_rule(app, title, Moment::now_local()) } else { None };
+    let action = hit.map(|h| h.action);
+    if let Some(h) = hit.filter(|h| h.action == RuleAction::Drop) { return Err(DropReason::Rule(h.rule.to_string())); }
+    // `keep` exime de las exclusiones planas
+    if action != Some(RuleAction::Keep) {
+        if c.excludes_app(app) { return Err(DropReason::ExcludedApp); }
+        if c.excludes_title(title) { return Err(DropReason::ExcludedPattern); }
+        if c.has_exe_rules() && front_exe_identity().is_some_and(|exe| c.excludes_exe(&exe)) { return Err(DropReason::ExcludedPattern); }
+    }
+    if !p.titleCapture { return Ok(String::new()); }
+    Ok(match action {
+        Some(RuleAction::RedactTitle) => REDACTED_TITLE.to_string(),
+        Some(RuleAction::HashTitle) => key.title_token(title),
+        _ => c.redact(title, key).into_owned(),
+    })
 }
 
 #[cfg(target_os = "macos")]
@@ -287,9 +457,7 @@ impl Throttle {
     }
     fn permit(&mut self, now: u64, force: bool) -> bool {
         self.refill(now);
-        if !force {
-            if self.last_emit_ms != 0 && now.saturating_sub(self.last_emit_ms) < self.min_interval_ms { return false; }
-        }
+        if !force && self.last_emit_ms != 0 && now.saturating_sub(self.last_emit_ms) < self.min_interval_ms { return false; }
         if self.tokens >= 1.0 {
             self.tokens -= 1.0;
             self.last_emit_ms = now;
@@ -358,6 +526,7 @@ fn sample_once() -> Result<(String, String, u64)> {
     Ok((String::new(), String::new(), 0))
 }
 
+#[cfg(any(target_os = "macos", target_os = "windows"))]
 #[derive(Debug, Clone, Serialize)]
 pub struct SampleDebugDto {
     pub app_name: String,
@@ -486,24 +655,6 @@ pub fn sample_debug() -> Result<SampleDebugDto> {
     })
 }
 
-#[cfg(target_os = "linux")]
-pub fn sample_debug() -> Result<SampleDebugDto> {
-    Ok(SampleDebugDto {
-        app_name: String::new(),
-        window_title: String::new(),
-        input_idle_ms: 0,
-        title_source: "unsupported".into(),
-        ax_pid: None,
-        ax_name: None,
-        ns_pid: None,
-        ns_name: None,
-        cg_pid: None,
-        cg_owner: None,
-        cg_title: None,
-        ax_title: None,
-    })
-}
-
 #[cfg(target_os = "windows")]
 struct WinForegroundSnapshot {
     active_hwnd: windows::Win32::Foundation::HWND,
@@ -698,6 +849,7 @@ fn windows_idle_ms() -> u64 {
     0
 }
 
+#[cfg(target_os = "macos")]
 #[derive(Debug, Clone, Serialize)]
 pub struct WindowInfoDto {
     pub owner_name: String,
@@ -706,6 +858,7 @@ pub struct WindowInfoDto {
     pub window_title: String,
 }
 
+#[cfg(target_os = "macos")]
 #[derive(Debug, Clone, Serialize)]
 pub struct FrontmostDebugDto {
     pub ax_pid: Option<i32>,
@@ -753,19 +906,6 @@ pub fn frontmost_debug() -> FrontmostDebugDto {
     }
 }
 
-#[cfg(any(target_os = "windows", target_os = "linux"))]
-pub fn frontmost_debug() -> FrontmostDebugDto {
-    FrontmostDebugDto {
-        ax_pid: None,
-        ax_name: None,
-        ns_pid: None,
-        ns_name: None,
-        cg_pid: None,
-        cg_owner: None,
-        cg_title: None,
-    }
-}
-
 #[cfg(target_os = "macos")]
 pub fn list_windows_debug(limit: usize) -> Vec<WindowInfoDto> {
     use core_foundation::base::TCFType;
@@ -847,11 +987,6 @@ pub fn list_windows_debug(limit: usize) -> Vec<WindowInfoDto> {
     out
 }
 
-#[cfg(any(target_os = "windows", target_os = "linux"))]
-pub fn list_windows_debug(_limit: usize) -> Vec<WindowInfoDto> {
-    Vec::new()
-}
-
 #[cfg(target_os = "macos")]
 unsafe fn nsstring_to_string(s: *mut objc::runtime::Object) -> String {
     use objc::{msg_send, sel, sel_impl};
@@ -0,0 +1,178 @@
+// Canal de comandos servidor → agente. Los comandos llegan en la respuesta del
+// heartbeat (o del ingest que lo lleva adjunto) y se admiten con `agent_core::commands`
+// (firma Ed25519, vigencia, allowlist e idempotencia por `id`). El acuse viaja en el
+// siguiente heartbeat.
+use agent_core::backend::{CommandAck, CommandStatus, RemoteCommand};
+use agent_core::commands::{allowed_from_env, CommandGate, Recipient, Verdict};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+const MAX_PAUSE_MINUTES: u64 = 24 * 60;
+
+pub struct CommandChannel {
+    gate: CommandGate,
+    pending: Mutex<Vec<CommandAck>>,
+    exec: tokio::sync::Mutex<()>,
+    /// Despierta al sender (comando `flush_queue`)
+    pub flush: tokio::sync::Notify,
+}
+
+#[derive(serde::Serialize)]
+pub struct CommandsReport {
+    pub allowed: Vec<&'static str>,
+    pub pending_acks: usize,
+    pub recent: Vec<CommandAck>,
+}
+
+impl CommandChannel {
+    pub fn new(paths: &agent_core::paths::Paths) -> Arc<Self> {
+        Arc::new(Self {
+            gate: CommandGate::load(paths, allowed_from_env()),
+            pending: Mutex::new(Vec::new()),
+            exec: tokio::sync::Mutex::new(()),
+            flush: tokio::sync::Notify::new(),
+        })
+    }
+
+    /// Acuses pendientes para el próximo heartbeat (se devuelven con `requeue` si el envío falla).
+    pub fn take_acks(&self) -> Vec<CommandAck> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    pub fn requeue(&self, acks: Vec<CommandAck>) {
+        if acks.is_empty() { return; }
+        let mut p = self.pending.lock().unwrap();
+        let newer = std::mem::replace(&mut *p, acks);
+        p.extend(newer);
+    }
+
+    pub fn report(&self) -> CommandsReport {
+        CommandsReport {
+            allowed: self.gate.allowed.clone(),
+            pending_acks: self.pending.lock().unwrap().len(),
+            recent: self.gate.recent(20),
+        }
+    }
+
+    fn finish(&self, ack: CommandAck, remember: bool) {
+        if remember { self.gate.record(&ack); }
+        self.pending.lock().unwrap().push(ack);
+    }
+}
+
+/// Procesa los comandos recibidos en segundo plano (no bloquea al loop que los recibió).
+pub fn dispatch(ctx: &crate::AppCtx, cmds: Vec<RemoteCommand>) {
+    if cmds.is_empty() { return; }
+    let ctx = ctx.clone();
+    tokio::spawn(async move { handle(&ctx, cmds).await });
+}
+
+async fn handle(ctx: &crate::AppCtx, cmds: Vec<RemoteCommand>) {
+    let ch = &ctx.commands;
+    let _guard = ch.exec.lock().await;
+    let now = now_ms();
+    let org = ctx.backend.identity().map(|i| i.org_id);
+    // el mismo id que el heartbeat que trajo los comandos
+    let device_id = crate::net::backend_device_id(ctx);
+    let to = Recipient { device_id: &device_id, org_id: org.as_deref() };
+    let mut reenroll = None;
+    for cmd in cmds {
+        let verdict = ch.gate.check(&cmd, to, &ctx.policy_gate.trust(), now);
+        match verdict {
+            Verdict::Replay(prev) => {
+                ch.finish(prev, false);
+                continue;
+            }
+            Verdict::Reject { detail, remember } => {
+                warn!(id=%cmd.id, action=%cmd.action, detail, "comando remoto rechazado");
+                let ack = CommandAck { id: cmd.id, action: cmd.action, status: CommandStatus::Rejected, detail: Some(detail.into()), finished_ms: now };
+                ch.finish(ack, remember);
+                continue;
+            }
+            Verdict::Run => {}
+        }
+        // re-enrolar borra las credenciales: se deja para el final, tras entregar los acuses
+        if cmd.action == "reenroll" {
+            reenroll = Some(cmd);
+            continue;
+        }
+        info!(id=%cmd.id, action=%cmd.action, "ejecutando comando remoto");
+        let res = execute(ctx, &cmd).await;
+        let (status, detail) = match res {
+            Ok(detail) => (CommandStatus::Done, detail),
+            Err(e) => {
+                warn!(id=%cmd.id, action=%cmd.action, error=%e, "comando remoto falló");
+                (CommandStatus::Failed, Some(e))
+            }
+        };
+        ch.finish(CommandAck { id: cmd.id, action: cmd.action, status, detail, finished_ms: now_ms() }, true);
+    }
+    if let Some(cmd) = reenroll {
+        ch.finish(CommandAck { id: cmd.id, action: cmd.action, status: CommandStatus::Done, detail: None, finished_ms: now_ms() }, true);
+        let mut req = crate::net::heartbeat_payload(ctx);
+        req.acks = ch.take_acks();
+        if let Err(e) = ctx.backend.heartbeat(&req).await {
+            warn!(error=%e, "no se pudo entregar el acuse antes de re-enrolar");
+            ch.requeue(std::mem::take(&mut req.acks));
+        }
+        match ctx.backend.reset_enrollment().await {
+            Ok(()) => info!("re-enrolamiento solicitado por el servidor"),
+            Err(e) => warn!(error=%e, "re-enrolamiento falló"),
+        }
+    }
+}
+
+async fn execute(ctx: &crate::AppCtx, cmd: &RemoteCommand) -> Result<Option<String>, String> {
+    match cmd.action.as_str() {
+        "refresh_policy" => {
+            crate::net::fetch_policy(ctx, None).await.map_err(|e| e.to_string())?;
+            Ok(ctx.policy_rt.get().etag)
+        }
+        "flush_queue" => {
+            ctx.commands.flush.notify_one();
+            Ok(None)
+        }
+        "pause" => {
+            let minutes = cmd.args.get("minutes").and_then(|v| v.as_u64()).ok_or("args.minutes requerido")?;
+            let until = now_ms().saturating_add(minutes.clamp(1, MAX_PAUSE_MINUTES) * 60_000);
+            ctx.paused_until_ms.store(until, Ordering::Relaxed);
+            Ok(Some(format!("paused_until_ms={}", until)))
+        }
+        "rotate_keys" => {
+            let s = ctx.backend.rotate_credentials().await.map_err(|e| e.to_string())?;
+            Ok(s.expires_at.map(|e| format!("expires_at={}", e)))
+        }
+        "upload_diagnostics" => {
+            ctx.backend.upload_diagnostics(&diagnostics(ctx)).await.map_err(|e| e.to_string())?;
+            Ok(None)
+        }
+        other => Err(format!("acción sin implementar: {}", other)),
+    }
+}
+
+/// Instantánea de diagnóstico (sin títulos de ventana ni secretos).
+pub(crate) fn diagnostics(ctx: &crate::AppCtx) -> serde_json::Value {
+    serde_json::json!({
+        "device_id": ctx.state.device_id,
+        "agent_version": ctx.version,
+        "os": std::env::consts::OS,
+        "generated_ms": now_ms(),
+        "heartbeat": crate::net::heartbeat_payload(ctx),
+        "heartbeat_stats": ctx.heartbeat.report(now_ms()),
+        "auth_state": ctx.backend.auth_report(),
+        "tls": ctx.http.report(),
+        "proxy": ctx.backend.base_url().map(|base| ctx.http.proxy_route(base)),
+        "policy_etag": ctx.policy_rt.get().etag,
+        "policy_sync": ctx.policy_sync.report(),
+        "commands": ctx.commands.report(),
+    })
+}
+
+fn now_ms() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,63 @@
+// `GET /diag/export`: paquete de diagnóstico (ver `agent_core::diag`) con las secciones que
+// solo tiene el proceso en marcha: `/state`, descartes recientes, permisos y nivel de logs.
+use agent_core::diag::{DiagBundle, DiagOptions};
+use axum::extract::{Query, State as AxumState};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    /// Incluir los eventos pendientes descifrados y los títulos de los descartes
+    #[serde(default)]
+    payloads: bool,
+}
+
+pub async fn build(ctx: &crate::AppCtx, opts: DiagOptions) -> anyhow::Result<Vec<u8>> {
+    let mut bundle = DiagBundle::new();
+    let mut state = serde_json::to_value(crate::state_handler(AxumState(ctx.clone())).await.0)?;
+    // la vista previa de la cola son payloads descifrados
+    if !opts.include_payloads {
+        if let Some(s) = state.as_object_mut() { s.remove("queue_preview"); }
+    }
+    bundle.add_json("state.json", &state);
+    bundle.add_json("diagnostics.json", &crate::commands::diagnostics(ctx));
+    // títulos de ventanas descartadas justamente por la policy: solo con payloads
+    let mut drops = ctx.drop_log.list_desc(500);
+    if !opts.include_payloads {
+        for d in &mut drops {
+            if !d.title.is_empty() { d.title = "[redacted]".into(); }
+        }
+    }
+    bundle.add_json("drops/recent.json", &drops);
+    bundle.add_json("permissions.json", &crate::perms_value());
+    bundle.add_json("logs/level.json", &ctx.logs.report());
+    let meta = serde_json::json!({
+        "source": "agent",
+        "agent_version": ctx.version,
+        "device_id": ctx.state.device_id,
+        "os": std::env::consts::OS,
+        "include_payloads": opts.include_payloads,
+    });
+    let paths = ctx.paths.clone();
+    tokio::task::spawn_blocking(move || {
+        bundle.collect_disk(&paths, &opts);
+        bundle.finish(meta)
+    })
+    .await?
+}
+
+pub async fn export_handler(AxumState(ctx): AxumState<crate::AppCtx>, Query(p): Query<ExportParams>) -> axum::response::Response {
+    match build(&ctx, DiagOptions { include_payloads: p.payloads }).await {
+        Ok(zip) => {
+            info!(bytes = zip.len(), payloads = p.payloads, "paquete de diagnóstico generado");
+            let disposition = format!("attachment; filename=\"agent_diag_{}.zip\"", ctx.state.device_id);
+            ([(header::CONTENT_TYPE, "application/zip".to_string()), (header::CONTENT_DISPOSITION, disposition)], zip).into_response()
+        }
+        Err(e) => {
+            warn!(error = %e, "no se pudo generar el paquete de diagnóstico");
+            (StatusCode::INTERNAL_SERVER_ERROR, axum::Json(serde_json::json!({ "ok": false, "error": e.to_string() }))).into_response()
+        }
+    }
+}
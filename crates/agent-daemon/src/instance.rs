@@ -0,0 +1,94 @@
+// Instancia única: el lock se toma al arrancar, antes de logs, captura y bootstrap. Con `--replace`
+// se pide a la instancia vigente que se apague por su panel (`POST /shutdown` con el token de
+// `agent_instance.json`) y se espera a que suelte el lock.
+use agent_core::instance::{try_acquire, Acquire, InstanceInfo, InstanceLock};
+use agent_core::paths::Paths;
+use anyhow::{anyhow, bail, Result};
+use axum::extract::State as AxumState;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const TOKEN_HEADER: &str = "x-agent-shutdown-token";
+/// La instancia anterior vacía contadores y cierra conexiones del panel antes de salir
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub struct Shutdown {
+    token: String,
+    pub notify: tokio::sync::Notify,
+}
+
+impl Shutdown {
+    pub fn new(token: String) -> std::sync::Arc<Self> {
+        std::sync::Arc::new(Self { token, notify: tokio::sync::Notify::new() })
+    }
+}
+
+/// Otra instancia tiene el lock y no se pidió `--replace`.
+#[derive(Debug)]
+pub struct AlreadyRunning(pub String);
+
+impl std::fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str(&self.0) }
+}
+
+impl std::error::Error for AlreadyRunning {}
+
+pub fn new_token() -> String {
+    rand::random::<[u8; 16]>().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub async fn acquire(paths: &Paths, info: InstanceInfo, replace: bool) -> Result<InstanceLock> {
+    let holder = match try_acquire(paths, info.clone())? {
+        Acquire::Acquired(lock) => return Ok(lock),
+        Acquire::Busy(holder) => holder,
+    };
+    let who = holder.as_ref().map(|h| h.to_string()).unwrap_or_else(|| "pid desconocido".to_string());
+    if !replace {
+        return Err(AlreadyRunning(format!("ya hay un agent-daemon corriendo ({}; lock {}). Usa --replace para reemplazarlo o `agent daemon stop`", who, paths.lock_file().display())).into());
+    }
+    let holder = holder
+        .filter(|h| !h.shutdown_token.is_empty())
+        .ok_or_else(|| anyhow!("no se puede reemplazar la instancia ({}): {} no indica su panel ni token", who, paths.instance_file().display()))?;
+    request_shutdown(&holder).await.map_err(|e| anyhow!("no se pudo pedir el apagado a la instancia ({}): {}", who, e))?;
+    println!("[info] apagado solicitado a la instancia ({}); esperando a que libere el lock", who);
+    let deadline = Instant::now() + REPLACE_TIMEOUT;
+    loop {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        if let Acquire::Acquired(lock) = try_acquire(paths, info.clone())? {
+            return Ok(lock);
+        }
+        if Instant::now() >= deadline {
+            bail!("la instancia ({}) no terminó en {}s; detenla con `agent daemon stop`", who, REPLACE_TIMEOUT.as_secs());
+        }
+    }
+}
+
+async fn request_shutdown(holder: &InstanceInfo) -> Result<()> {
+    // directo al loopback: sin proxies del entorno
+    let client = reqwest::Client::builder().no_proxy().timeout(Duration::from_secs(5)).build()?;
+    let resp = client
+        .post(format!("http://{}/shutdown", holder.panel_addr))
+        .header(TOKEN_HEADER, &holder.shutdown_token)
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        bail!("el panel respondió {}", resp.status());
+    }
+    Ok(())
+}
+
+/// `POST /shutdown`: apagado ordenado (igual que SIGTERM) si el token coincide.
+pub async fn shutdown_handler(AxumState(ctx): AxumState<crate::AppCtx>, headers: HeaderMap) -> impl IntoResponse {
+    let token = headers.get(TOKEN_HEADER).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    if token.is_empty() || token != ctx.shutdown.token {
+        warn!("POST /shutdown rechazado: token ausente o inválido");
+        return (StatusCode::FORBIDDEN, Json(serde_json::json!({ "ok": false, "error": "token de apagado inválido" })));
+    }
+    info!("apagado solicitado por el panel (--replace)");
+    // el servidor termina de responder antes de cerrar (graceful shutdown)
+    ctx.shutdown.notify.notify_one();
+    (StatusCode::OK, Json(serde_json::json!({ "ok": true, "pid": std::process::id() })))
+}
@@ -0,0 +1,257 @@
+// Logs del daemon: stdout + archivo diario en `logs_dir`, con filtro recargable en caliente
+// (`POST /debug/log-level` y `logLevel` de la policy) y mantenimiento de los archivos rotados
+// (compresión zstd opcional, retención por días y por tamaño total).
+use crate::otel;
+use agent_core::paths::Paths;
+use axum::extract::State as AxumState;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::EnvFilter;
+
+const LOG_PREFIX: &str = "agent.log";
+const MAINTENANCE_EVERY: Duration = Duration::from_secs(3600);
+const POLICY_CHECK_EVERY: Duration = Duration::from_secs(10);
+
+/// Configuración de logs desde env (`LOG_FORMAT`, `LOG_RETENTION_DAYS`, `LOG_MAX_TOTAL_MB`, `LOG_COMPRESS`).
+#[derive(Debug, Clone, Serialize)]
+pub struct LogConfig {
+    /// Archivo en JSON (una línea por evento) en lugar de texto
+    pub json: bool,
+    /// 0 = sin límite
+    pub retention_days: u64,
+    /// 0 = sin límite
+    pub max_total_mb: u64,
+    /// Comprimir con zstd los archivos ya rotados
+    pub compress: bool,
+}
+
+impl LogConfig {
+    pub fn from_env() -> Self {
+        let num = |key: &str, default: u64| std::env::var(key).ok().and_then(|s| s.trim().parse().ok()).unwrap_or(default);
+        let flag = |key: &str, on: &str| std::env::var(key).is_ok_and(|v| v.trim().eq_ignore_ascii_case(on));
+        Self { json: flag("LOG_FORMAT", "json"), retention_days: num("LOG_RETENTION_DAYS", 14), max_total_mb: num("LOG_MAX_TOTAL_MB", 200), compress: flag("LOG_COMPRESS", "zstd") }
+    }
+}
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>;
+
+/// Filtro vigente: override del panel > `logLevel` de la policy > `RUST_LOG`.
+pub struct LogControl {
+    base: String,
+    policy: Mutex<Option<String>>,
+    manual: Mutex<Option<String>>,
+    current: Mutex<String>,
+    reload: Reload,
+    pub config: LogConfig,
+    dir: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogLevelReport {
+    pub effective: String,
+    /// `override` | `policy` | `env`
+    pub source: &'static str,
+    pub env: String,
+    pub policy: Option<String>,
+    #[serde(rename = "override")]
+    pub manual: Option<String>,
+}
+
+impl LogControl {
+    fn effective(&self) -> (String, &'static str) {
+        if let Some(m) = self.manual.lock().unwrap().clone() { return (m, "override"); }
+        // un logLevel inválido en la policy se ignora (ya se avisó en `set_policy`)
+        if let Some(p) = self.policy.lock().unwrap().clone().filter(|p| EnvFilter::try_new(p).is_ok()) { return (p, "policy"); }
+        (self.base.clone(), "env")
+    }
+
+    /// Aplica el filtro efectivo si cambió.
+    fn apply(&self) -> Result<(), String> {
+        let (filter, source) = self.effective();
+        let mut current = self.current.lock().unwrap();
+        if *current == filter { return Ok(()); }
+        (self.reload)(EnvFilter::try_new(&filter).map_err(|e| e.to_string())?)?;
+        info!(filter = %filter, source, "nivel de logs actualizado");
+        *current = filter;
+        Ok(())
+    }
+
+    /// Override manual (`None` vuelve a policy/env). Un filtro inválido no cambia nada.
+    pub fn set_override(&self, filter: Option<String>) -> Result<(), String> {
+        if let Some(f) = &filter { EnvFilter::try_new(f).map_err(|e| e.to_string())?; }
+        *self.manual.lock().unwrap() = filter;
+        self.apply()
+    }
+
+    pub fn set_policy(&self, filter: Option<String>) {
+        let mut p = self.policy.lock().unwrap();
+        if *p == filter { return; }
+        if let Some(Err(e)) = filter.as_deref().map(EnvFilter::try_new) { warn!(error = %e, "logLevel de la policy inválido; se ignora"); }
+        *p = filter;
+        drop(p);
+        if let Err(e) = self.apply() { warn!(error = %e, "no se pudo aplicar el nivel de logs"); }
+    }
+
+    pub fn report(&self) -> LogLevelReport {
+        let (effective, source) = self.effective();
+        LogLevelReport { effective, source, env: self.base.clone(), policy: self.policy.lock().unwrap().clone(), manual: self.manual.lock().unwrap().clone() }
+    }
+
+    pub fn dir(&self) -> &Path { &self.dir }
+}
+
+/// Instala el subscriber global. Los guards deben vivir hasta el final del proceso.
+pub fn init(paths: &Paths, version: &str) -> (Arc<LogControl>, WorkerGuard, otel::Guard) {
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::{fmt, reload, Layer, Registry};
+    type Base = tracing_subscriber::layer::Layered<Option<otel::BoxedLayer<Registry>>, Registry>;
+
+    let config = LogConfig::from_env();
+    let base = std::env::var("RUST_LOG").ok().filter(|s| EnvFilter::try_new(s).is_ok()).unwrap_or_else(|| "info".to_string());
+    let logs_dir = paths.logs_dir();
+    std::fs::create_dir_all(&logs_dir).ok();
+    let file_appender = tracing_appender::rolling::daily(&logs_dir, LOG_PREFIX);
+    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+
+    let file_layer: Box<dyn Layer<Base> + Send + Sync> = if config.json {
+        fmt::layer().json().with_writer(non_blocking).boxed()
+    } else {
+        fmt::layer().with_ansi(false).with_target(false).with_writer(non_blocking).boxed()
+    };
+    let stdout_layer: Box<dyn Layer<Base> + Send + Sync> = fmt::layer().with_target(false).compact().boxed();
+
+    // filtro por layer y recargable: gobierna los logs; las trazas OTLP tienen el suyo (ver otel.rs)
+    let (filter, handle) = reload::Layer::<EnvFilter, Base>::new(EnvFilter::new(&base));
+    let (otel_layer, otel_guard) = otel::init(version);
+    tracing_subscriber::registry()
+        .with(otel_layer)
+        .with(stdout_layer.and_then(file_layer).with_filter(filter))
+        .init();
+    let ctl = LogControl {
+        current: Mutex::new(base.clone()),
+        base,
+        policy: Mutex::new(None),
+        manual: Mutex::new(None),
+        reload: Box::new(move |f| handle.reload(f).map_err(|e| e.to_string())),
+        config,
+        dir: logs_dir,
+    };
+    (Arc::new(ctl), guard, otel_guard)
+}
+
+/// Sigue el `logLevel` de la policy y mantiene los archivos rotados (al arrancar y cada hora).
+pub async fn run_maintenance_loop(ctx: crate::AppCtx) {
+    let mut last_maintenance: Option<std::time::Instant> = None;
+    loop {
+        ctx.logs.set_policy(ctx.policy_rt.get().policy.logLevel.filter(|s| !s.trim().is_empty()));
+        if last_maintenance.is_none_or(|t| t.elapsed() >= MAINTENANCE_EVERY) {
+            let logs = ctx.logs.clone();
+            match tokio::task::spawn_blocking(move || maintain(logs.dir(), &logs.config)).await {
+                Ok(Ok(s)) if s.compressed + s.removed > 0 => info!(compressed = s.compressed, removed = s.removed, total_bytes = s.total_bytes, "mantenimiento de logs"),
+                Ok(Err(e)) => warn!(error = %e, "mantenimiento de logs falló"),
+                _ => {}
+            }
+            last_maintenance = Some(std::time::Instant::now());
+        }
+        tokio::time::sleep(POLICY_CHECK_EVERY).await;
+    }
+}
+
+#[derive(Debug, Default)]
+struct MaintenanceStats {
+    compressed: usize,
+    removed: usize,
+    total_bytes: u64,
+}
+
+/// Comprime los archivos rotados, borra los vencidos y luego los más viejos hasta
+/// quedar bajo el tope. El archivo activo (el más reciente sin comprimir) no se toca.
+fn maintain(dir: &Path, cfg: &LogConfig) -> anyhow::Result<MaintenanceStats> {
+    let mut stats = MaintenanceStats::default();
+    let mut files = log_files(dir)?;
+    let active = files.iter().filter(|(p, ..)| !is_zst(p)).max_by_key(|(_, m, _)| *m).map(|(p, ..)| p.clone());
+    if cfg.compress {
+        for (path, modified, _) in files.iter().filter(|(p, ..)| !is_zst(p) && Some(p) != active.as_ref()) {
+            let mut name = path.clone().into_os_string();
+            name.push(".zst");
+            let out = PathBuf::from(name);
+            let written = (|| -> anyhow::Result<()> {
+                let mut input = std::fs::File::open(path)?;
+                let mut enc = zstd::Encoder::new(std::fs::File::create(&out)?, 3)?;
+                std::io::copy(&mut input, &mut enc)?;
+                // conserva la fecha del original: la retención se calcula sobre ella
+                enc.finish()?.set_modified(*modified)?;
+                Ok(())
+            })();
+            match written {
+                Ok(()) => {
+                    std::fs::remove_file(path)?;
+                    stats.compressed += 1;
+                }
+                Err(e) => {
+                    let _ = std::fs::remove_file(&out);
+                    warn!(file = %path.display(), error = %e, "no se pudo comprimir log");
+                }
+            }
+        }
+        files = log_files(dir)?;
+    }
+    // más viejo primero
+    files.sort_by_key(|(_, m, _)| *m);
+    let now = SystemTime::now();
+    let max_age = Duration::from_secs(cfg.retention_days * 86_400);
+    let mut total: u64 = files.iter().map(|(_, _, len)| len).sum();
+    for (path, modified, len) in &files {
+        if Some(path) == active.as_ref() { continue; }
+        let expired = cfg.retention_days > 0 && now.duration_since(*modified).unwrap_or_default() > max_age;
+        let over = cfg.max_total_mb > 0 && total > cfg.max_total_mb * 1024 * 1024;
+        if !(expired || over) { continue; }
+        if std::fs::remove_file(path).is_ok() {
+            total -= len;
+            stats.removed += 1;
+        }
+    }
+    stats.total_bytes = total;
+    Ok(stats)
+}
+
+/// `agent.log.*` (y `.zst`) con fecha de modificación y tamaño.
+fn log_files(dir: &Path) -> std::io::Result<Vec<(PathBuf, SystemTime, u64)>> {
+    let mut out = Vec::new();
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name();
+        if !name.to_string_lossy().starts_with(LOG_PREFIX) { continue; }
+        let Ok(meta) = entry.metadata() else { continue };
+        if !meta.is_file() { continue; }
+        out.push((entry.path(), meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), meta.len()));
+    }
+    Ok(out)
+}
+
+fn is_zst(p: &Path) -> bool { p.extension().is_some_and(|e| e == "zst") }
+
+#[derive(Deserialize)]
+pub struct LogLevelBody {
+    /// Filtro estilo `RUST_LOG`; `null` o ausente quita el override
+    #[serde(default)]
+    level: Option<String>,
+}
+
+/// `GET /debug/log-level`: filtro vigente y de dónde sale.
+pub async fn log_level_get_handler(AxumState(ctx): AxumState<crate::AppCtx>) -> Json<LogLevelReport> {
+    Json(ctx.logs.report())
+}
+
+/// `POST /debug/log-level` `{"level":"debug"}` (o `{"level":null}` para volver a policy/env).
+pub async fn log_level_post_handler(AxumState(ctx): AxumState<crate::AppCtx>, Json(body): Json<LogLevelBody>) -> Json<serde_json::Value> {
+    let level = body.level.filter(|s| !s.trim().is_empty());
+    match ctx.logs.set_override(level) {
+        Ok(()) => Json(serde_json::json!({ "ok": true, "log_level": ctx.logs.report() })),
+        Err(e) => Json(serde_json::json!({ "ok": false, "error": format!("filtro inválido: {}", e) })),
+    }
+}
@@ -1,3 +1,4 @@
+use agent_core::heartbeat::{HeartbeatReport, HeartbeatTracker};
 use agent_core::metrics::{AgentMetrics, MetricsHandle};
 use agent_core::paths::Paths;
 use agent_core::state::AgentState;
@@ -18,13 +19,18 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::signal;
 use tracing::{error, info};
-use tracing_appender::non_blocking::WorkerGuard;
 
 mod capture;
+mod commands;
+mod diag;
+mod instance;
+mod metrics;
 mod policy;
 #[cfg(target_os = "macos")]
 mod macos_perms;
+mod logs;
 mod net;
+mod otel;
 
 #[cfg(target_os = "macos")]
 #[link(name = "AppKit", kind = "framework")]
@@ -43,6 +49,11 @@ struct AppCtx {
     state: Arc<AgentState>,
     paths: agent_core::paths::Paths,
     metrics: MetricsHandle,
+    logs: Arc<logs::LogControl>,
+    /// Series con rollups por minuto (`/metrics/summary`) y acumulados (`/metrics`)
+    registry: Arc<agent_core::metrics::Registry>,
+    /// Rollups en SQLite, compartido entre el cierre por minuto y `/metrics/summary`
+    metrics_store: Option<Arc<std::sync::Mutex<agent_core::metrics::MetricsStore>>>,
     version: String,
     last_event_ts: Arc<AtomicU64>,
     last_heartbeat_ts: Arc<AtomicU64>,
@@ -53,6 +64,16 @@ struct AppCtx {
     drop_counters: std::sync::Arc<policy::DropCounters>,
     drop_log: std::sync::Arc<policy::DropLog>,
     focus_agg: std::sync::Arc<capture::FocusAgg>,
+    http: agent_core::http::ClientFactory,
+    backend: agent_core::backend::BackendClient,
+    started_at_ms: u64,
+    last_ingest_ts: Arc<AtomicU64>,
+    capture_health: Arc<capture::CaptureHealth>,
+    heartbeat: Arc<HeartbeatTracker>,
+    commands: Arc<commands::CommandChannel>,
+    policy_sync: Arc<net::PolicySync>,
+    policy_gate: Arc<policy::PolicyGate>,
+    shutdown: Arc<instance::Shutdown>,
 }
 
 #[derive(Serialize)]
@@ -78,9 +99,22 @@ struct StateDto {
     agent_path: String,
     policy: serde_json::Value,
     policy_etag: Option<String>,
+    /// Motivo si se arrancó sin policy verificada (captura detenida hasta recibir una)
+    policy_locked: Option<String>,
     dropped_events: u64,
     dropped_by_reason: serde_json::Value,
     focus_blocks: Vec<capture::FocusBlockDto>,
+    tls: agent_core::http::TlsReport,
+    proxy: Option<agent_core::proxy::ProxyRoute>,
+    auth_state: agent_core::backend::AuthReport,
+    uptime_seconds: u64,
+    last_ingest_ts: u64,
+    capture: agent_core::backend::CaptureHealthReport,
+    metrics_p95: agent_core::metrics::MetricsP95,
+    heartbeat: HeartbeatReport,
+    commands: commands::CommandsReport,
+    policy_sync: net::PolicySyncReport,
+    policy_signature: policy::SignatureReport,
 }
 
 // Usamos runtime de un solo hilo para garantizar que las llamadas a AppKit/AX
@@ -90,32 +124,97 @@ async fn main() -> Result<()> {
     // Carga variables desde .env si existe
     let _ = dotenvy::dotenv();
     let paths = Paths::new()?;
-    let _guard = init_tracing(&paths);
+    let version = env!("CARGO_PKG_VERSION").to_string();
+    let addr_str = std::env::var("PANEL_ADDR").unwrap_or_else(|_| DEFAULT_PANEL_ADDR.to_string());
+    // instancia única: antes de tocar logs, cola o captura
+    let replace = std::env::args().skip(1).any(|a| a == "--replace");
+    let exit_ok_if_running = std::env::args().skip(1).any(|a| a == agent_core::instance::EXIT_OK_IF_RUNNING_ARG);
+    let info = agent_core::instance::InstanceInfo {
+        pid: std::process::id(),
+        started_at_ms: now_ms(),
+        version: version.clone(),
+        panel_addr: addr_str.clone(),
+        shutdown_token: instance::new_token(),
+    };
+    let shutdown = instance::Shutdown::new(info.shutdown_token.clone());
+    let instance_lock = match instance::acquire(&paths, info, replace).await {
+        Ok(lock) => lock,
+        Err(e) if e.is::<instance::AlreadyRunning>() => {
+            // código propio: el servicio no lo relanza (volvería a encontrar el lock tomado)
+            eprintln!("[error] {}", e);
+            std::process::exit(if exit_ok_if_running { 0 } else { agent_core::instance::EXIT_ALREADY_RUNNING });
+        }
+        Err(e) => {
+            eprintln!("[error] {}", e);
+            return Err(e);
+        }
+    };
+    let (logs, _guard, _otel) = logs::init(&paths, &version);
+    info!(pid = instance_lock.info.pid, replace, "lock de instancia tomado");
     #[cfg(target_os = "macos")]
     unsafe {
         macos_load_appkit();
     }
-    let version = env!("CARGO_PKG_VERSION").to_string();
     let state = AgentState::load_or_init(&paths, &version)?;
 
     let metrics = MetricsHandle::new();
     let metrics_bg = metrics.clone();
     tokio::spawn(async move { metrics_bg.run_sampler().await });
 
+    let http = match agent_core::http::ClientFactory::from_env() {
+        Ok(h) => h,
+        Err(e) => {
+            tracing::error!(error = %e, "configuración TLS/proxy inválida");
+            eprintln!("[error] configuración TLS/proxy inválida: {}. Revisa TLS_SPKI_PINS, TLS_CA_BUNDLE y PROXY_* en .env", e);
+            return Err(e);
+        }
+    };
+    // policy en disco disponible desde el arranque (captura y heartbeat la leen antes del primer fetch)
+    let policy_rt = policy::PolicyRuntime::new();
+    let policy_gate = policy::PolicyGate::new(&paths);
+    let initial_policy = policy::load_policy(&paths, &policy_gate);
+    let backend = agent_core::backend::BackendClient::from_env(http.clone(), paths.clone(), &version);
+    if let Some(base) = backend.base_url().map(str::to_string) {
+        // el PAC se evalúa fuera del runtime antes de que arranquen los loops
+        let h = http.clone();
+        let _ = tokio::task::spawn_blocking(move || h.prime_proxy(&base)).await;
+    }
+    {
+        // la comprobación contra el backend no retrasa el arranque; hasta entonces, pins locales
+        let (b, pins) = (backend.clone(), initial_policy.policy.tlsPins.clone());
+        tokio::spawn(async move { b.apply_tls_pins(&pins).await; });
+    }
+    policy_rt.set(initial_policy);
+    let commands = commands::CommandChannel::new(&paths);
+    let drop_counters = policy::DropCounters::load(&paths);
+    let metrics_store = metrics::open_store(&paths);
     let ctx = AppCtx {
         state: Arc::new(state),
         paths,
         metrics: metrics.clone(),
+        logs,
+        registry: agent_core::metrics::Registry::new(),
+        metrics_store,
         version: version.clone(),
         last_event_ts: Arc::new(AtomicU64::new(0)),
         last_heartbeat_ts: Arc::new(AtomicU64::new(0)),
         last_idle_ms: Arc::new(AtomicU64::new(0)),
         paused_until_ms: Arc::new(AtomicU64::new(0)),
-        policy_rt: policy::PolicyRuntime::new(),
-        dropped_events: Arc::new(AtomicU64::new(0)),
-        drop_counters: std::sync::Arc::new(policy::DropCounters::default()),
+        policy_rt,
+        dropped_events: Arc::new(AtomicU64::new(drop_counters.total())),
+        drop_counters,
         drop_log: policy::DropLog::new(200),
         focus_agg: capture::FocusAgg::new(),
+        http,
+        backend,
+        started_at_ms: now_ms(),
+        last_ingest_ts: Arc::new(AtomicU64::new(0)),
+        capture_health: capture::CaptureHealth::new(),
+        heartbeat: Arc::default(),
+        commands,
+        policy_sync: net::PolicySync::new(),
+        policy_gate,
+        shutdown,
     };
 
     let app_ctx = ctx.clone();
@@ -126,6 +225,8 @@ async fn main() -> Result<()> {
         .route("/state", get(state_handler))
         .route("/queue", get(queue_handler))
         .route("/debug/drops", get(debug_drops_handler))
+        .route("/metrics", get(metrics::openmetrics_handler))
+        .route("/metrics/summary", get(metrics::summary_handler))
         .route("/pause", get(pause_handler))
         .route("/pause/clear", get(pause_clear_handler))
         .route("/permissions", get(perms_handler))
@@ -136,11 +237,16 @@ async fn main() -> Result<()> {
         )
         .route("/permissions/open/screen", get(perms_open_screen))
         .route("/debug/sample", get(debug_sample_handler))
+        .route("/diag/export", get(diag::export_handler))
+        .route("/debug/log-level", get(logs::log_level_get_handler).post(logs::log_level_post_handler))
         .route("/debug/windows", get(debug_windows_handler))
         .route("/debug/window", get(debug_windows_handler))
         .route("/debug/frontmost", get(debug_frontmost_handler))
         .route("/policy/apply", post(policy_apply_handler))
         .route("/policy/refresh", post(policy_refresh_handler))
+        .route("/policy/validate", post(policy_validate_handler))
+        .route("/enroll", post(enroll_handler))
+        .route("/shutdown", post(instance::shutdown_handler))
         .route("/focus/blocks", get(focus_blocks_handler))
         .route("/focus/aggregate", get(focus_aggregate_handler))
         .route("/focus/aggregate.csv", get(focus_aggregate_csv_handler));
@@ -214,56 +320,35 @@ async fn main() -> Result<()> {
 
     // Bootstrap (login) si es necesario
     {
-        let s_paths = ctx.paths.clone();
-        let s_state = ctx.state.clone();
-        tokio::spawn(async move { net::bootstrap_if_needed(&s_paths, &s_state).await; });
+        let s_backend = ctx.backend.clone();
+        tokio::spawn(async move { net::bootstrap_if_needed(&s_backend).await; });
     }
 
     // lanzar tareas de captura y heartbeat antes de iniciar servidor
     info!("spawning capture and heartbeat tasks");
     println!("[debug] spawning capture/heartbeat tasks");
     // debug: se puede verificar la captura con logs del loop
-    let bg_state1 = ctx.state.clone();
-    let bg_paths1 = ctx.paths.clone();
-    let last_event1 = ctx.last_event_ts.clone();
-    let last_idle1 = ctx.last_idle_ms.clone();
-    let paused1 = ctx.paused_until_ms.clone();
-    let pol1 = ctx.policy_rt.clone();
-    let dropped1 = ctx.dropped_events.clone();
-    let dropc1 = ctx.drop_counters.clone();
-    let droplog1 = ctx.drop_log.clone();
-    let focus1 = ctx.focus_agg.clone();
-    tokio::spawn(async move { capture::run_capture_loop(bg_state1.clone(), &bg_paths1, last_event1, last_idle1, paused1, pol1, dropped1, dropc1, droplog1, focus1).await; });
-    let bg_state2 = ctx.state.clone();
-    let bg_paths2 = ctx.paths.clone();
-    let bg_metrics2 = ctx.metrics.clone();
-    let last_event2 = ctx.last_event_ts.clone();
-    let last_hb2 = ctx.last_heartbeat_ts.clone();
-    tokio::spawn(async move {
-        net::run_heartbeat_loop(
-            bg_state2.clone(),
-            &bg_paths2,
-            bg_metrics2.clone(),
-            last_event2,
-            last_hb2,
-        )
-        .await;
-    });
+    let c_ctx = ctx.clone();
+    tokio::spawn(async move { capture::run_capture_loop(c_ctx).await; });
+    let m_ctx = ctx.clone();
+    tokio::spawn(async move { metrics::run_rollup_loop(m_ctx).await; });
+    let l_ctx = ctx.clone();
+    tokio::spawn(async move { logs::run_maintenance_loop(l_ctx).await; });
+    let hb_ctx = ctx.clone();
+    tokio::spawn(async move { net::run_heartbeat_loop(hb_ctx).await; });
 
     // opcional: sender de eventos si API_BASE_URL está configurado
-    if std::env::var("API_BASE_URL").is_ok() {
-        let s_state = ctx.state.clone();
-        let s_paths = ctx.paths.clone();
-        tokio::spawn(async move {
-            net::run_sender_loop(s_state.clone(), &s_paths).await;
-        });
-        // policy fetch loop
-        let p_paths = ctx.paths.clone();
-        let prt = ctx.policy_rt.clone();
-        tokio::spawn(async move { net::run_policy_loop(&p_paths, prt).await; });
+    if ctx.backend.is_configured() {
+        let s_ctx = ctx.clone();
+        tokio::spawn(async move { net::run_sender_loop(s_ctx).await; });
+        // policy: long-poll con polling de respaldo
+        let p_ctx = ctx.clone();
+        tokio::spawn(async move { net::run_policy_loop(p_ctx).await; });
+        // renovación proactiva del token
+        let r_backend = ctx.backend.clone();
+        tokio::spawn(async move { net::run_token_refresh_loop(r_backend).await; });
     }
 
-    let addr_str = std::env::var("PANEL_ADDR").unwrap_or_else(|_| DEFAULT_PANEL_ADDR.to_string());
     let addr: SocketAddr = match addr_str.parse() {
         Ok(a) => a,
         Err(e) => {
@@ -284,10 +369,11 @@ async fn main() -> Result<()> {
     };
 
     let server =
-        axum::serve(listener, app.into_make_service()).with_graceful_shutdown(shutdown_signal());
+        axum::serve(listener, app.into_make_service()).with_graceful_shutdown(shutdown_signal(ctx.shutdown.clone()));
     if let Err(e) = server.await {
         error!(?e, "falló servidor panel");
     }
+    ctx.drop_counters.save();
     Ok(())
 }
 
@@ -327,6 +413,13 @@ async fn ui_index() -> Html<&'static str> {
       <div class="card"><div class="muted">Cola</div><div id="qlen"></div></div>
       <div class="card"><div class="muted">Descartes</div><div id="dropped"></div></div>
       <div class="card"><div class="muted">Policy ETag</div><div id="petag"></div></div>
+      <div class="card"><div class="muted">Heartbeat OK (1h)</div><div id="hbrate">—</div></div>
+    </div>
+    <div class="card" id="enroll-card" style="margin:0 16px 12px 16px"><div class="muted">Enrolamiento</div><div id="enroll_status">—</div>
+      <div id="enroll_form" style="margin-top:8px;display:none;gap:8px">
+        <input id="enroll_code" placeholder="Código de enrolamiento" />
+        <button id="btn-enroll">Enrolar</button>
+      </div>
     </div>
     <div class="card" id="perms-card" style="margin:0 16px"><div class="muted">Permisos</div><div id="perms">—</div>
       <div class="muted" style="margin-top:6px">Binario a autorizar:</div>
@@ -357,6 +450,12 @@ async fn ui_index() -> Html<&'static str> {
           document.getElementById('qlen').textContent=s.queue_len;
           document.getElementById('dropped').textContent=String(s.dropped_events||0);
           document.getElementById('petag').textContent=s.policy_etag||'';
+          const hb=s.heartbeat||{}; document.getElementById('hbrate').textContent = hb.success_rate==null ? '—' : Math.round(hb.success_rate*100)+'% ('+hb.successes+'/'+hb.attempts+')';
+          const au=s.auth_state||{}; const es=document.getElementById('enroll_status');
+          if(au.phase==='pending_enrollment'){ es.className='warn'; es.textContent='Pendiente de enrolamiento'; }
+          else if(au.identity){ es.className='ok'; es.textContent=au.identity.user_email+' ('+au.identity.org_id+', '+au.identity.source+') - '+au.phase; }
+          else { es.className='muted'; es.textContent=au.phase||'—'; }
+          document.getElementById('enroll_form').style.display = au.phase==='pending_enrollment' ? 'flex' : 'none';
                     const permsCard = document.getElementById('perms-card');
           if(permsCard){
             if(s.perms && s.perms.unsupported){
@@ -439,6 +538,7 @@ async fn ui_index() -> Html<&'static str> {
         const bax=document.getElementById('openAx'); if(bax){ bax.onclick=()=>j('/permissions/open/accessibility').then(()=>setTimeout(ref,1000)); }
         const bsc=document.getElementById('openSc'); if(bsc){ bsc.onclick=()=>j('/permissions/open/screen').then(()=>setTimeout(ref,1000)); }
         const brp=document.getElementById('btn-refresh-policy'); if(brp){ brp.onclick=()=>fetch('/policy/refresh',{method:'POST'}).then(()=>setTimeout(ref,1000)); }
+        const ben=document.getElementById('btn-enroll'); if(ben){ ben.onclick=()=>fetch('/enroll',{method:'POST',headers:{'Content-Type':'application/json'},body:JSON.stringify({code:document.getElementById('enroll_code').value})}).then(r=>r.json()).then(r=>{ if(!r.ok) alert(r.error); ref(); }); }
         ref(); setInterval(ref,2000);
       });
               try{document.getElementById('policy').textContent = JSON.stringify(s.policy||{},null,2);}catch(e){}
@@ -534,13 +634,7 @@ async fn state_handler(AxumState(ctx): AxumState<AppCtx>) -> Json<StateDto> {
         }
         Err(_) => (0, Vec::new()),
     };
-    // permisos
-    #[cfg(target_os = "macos")]
-    let perms_v = serde_json::to_value(crate::macos_perms::check_permissions()).unwrap();
-    #[cfg(not(target_os = "macos"))]
-    let perms_v = serde_json::json!({"unsupported": true});
-
-    let dc = &ctx.drop_counters;
+    let perms_v = perms_value();
     Json(StateDto {
         device_id: ctx.state.device_id.clone(),
         agent_version: ctx.state.agent_version.clone(),
@@ -557,18 +651,32 @@ async fn state_handler(AxumState(ctx): AxumState<AppCtx>) -> Json<StateDto> {
         agent_path: std::env::current_exe().map(|p| p.display().to_string()).unwrap_or_default(),
         policy: serde_json::to_value(ctx.policy_rt.get().policy).unwrap_or(serde_json::json!({})),
         policy_etag: ctx.policy_rt.get().etag,
+        policy_locked: ctx.policy_rt.get().locked,
         dropped_events: ctx.dropped_events.load(Ordering::Relaxed),
-        dropped_by_reason: serde_json::json!({
-            "killSwitch": dc.kill_switch.load(Ordering::Relaxed),
-            "pauseCapture": dc.pause.load(Ordering::Relaxed),
-            "excludedApp": dc.excluded_app.load(Ordering::Relaxed),
-            "excludedPattern": dc.excluded_pattern.load(Ordering::Relaxed),
-            "throttled": dc.throttled.load(Ordering::Relaxed),
-        }),
+        dropped_by_reason: serde_json::to_value(ctx.drop_counters.snapshot()).unwrap_or_default(),
         focus_blocks: ctx.focus_agg.recent(5, ctx.policy_rt.get().policy.focusMinMinutes.unwrap_or(5)),
+        tls: ctx.http.report(),
+        proxy: ctx.backend.base_url().map(|base| ctx.http.proxy_route(base)),
+        auth_state: ctx.backend.auth_report(),
+        uptime_seconds: now_ms().saturating_sub(ctx.started_at_ms) / 1000,
+        last_ingest_ts: ctx.last_ingest_ts.load(Ordering::Relaxed),
+        capture: ctx.capture_health.report(),
+        metrics_p95: ctx.metrics.p95(),
+        heartbeat: ctx.heartbeat.report(now_ms()),
+        commands: ctx.commands.report(),
+        policy_sync: ctx.policy_sync.report(),
+        policy_signature: ctx.policy_gate.report(),
     })
 }
 
+/// Estado de permisos de captura (solo macOS los requiere).
+fn perms_value() -> serde_json::Value {
+    #[cfg(target_os = "macos")]
+    { serde_json::to_value(crate::macos_perms::check_permissions()).unwrap_or_default() }
+    #[cfg(not(target_os = "macos"))]
+    { serde_json::json!({"unsupported": true}) }
+}
+
 #[derive(Deserialize)]
 struct DropsParams { limit: Option<usize> }
 
@@ -579,28 +687,60 @@ async fn debug_drops_handler(AxumState(ctx): AxumState<AppCtx>, Query(p): Query<
 }
 
 async fn policy_apply_handler(AxumState(ctx): AxumState<AppCtx>, axum::Json(body): axum::Json<serde_json::Value>) -> Json<serde_json::Value> {
-    // admitir envoltura {policy:{...}}
+    // admitir envoltura {policy:{...}, signature:{keyId, sig}}
+    let signature = body.get("signature").and_then(|s| serde_json::from_value::<agent_core::policy_sig::PolicySignature>(s.clone()).ok());
     let pol_v = body.get("policy").cloned().unwrap_or(body);
-    match serde_json::from_value::<crate::policy::Policy>(pol_v) {
-        Ok(policy) => {
-            let st = crate::policy::PolicyState { policy, etag: None };
-            if let Err(e) = crate::policy::save_policy(&ctx.paths, &st) {
+    match ctx.policy_gate.admit(&pol_v, signature.as_ref(), "local") {
+        Ok(crate::policy::Admitted { policy, warnings }) => {
+            if let Err(e) = crate::policy::save_policy(&ctx.paths, &pol_v, None, signature) {
                 return Json(serde_json::json!({"ok": false, "error": format!("save failed: {}", e)}));
             }
-            ctx.policy_rt.set(st);
-            Json(serde_json::json!({"ok": true}))
+            ctx.backend.apply_tls_pins(&policy.tlsPins).await;
+            ctx.policy_rt.set(crate::policy::PolicyState::new(policy, None));
+            Json(serde_json::json!({"ok": true, "warnings": warnings, "signature": ctx.policy_gate.report().current}))
         }
-        Err(e) => Json(serde_json::json!({"ok": false, "error": format!("parse failed: {}", e)})),
+        Err(rej) => Json(serde_json::json!({"ok": false, "error": rej.reason, "errors": rej.errors})),
     }
 }
 
+/// Dry-run: valida la policy (y su firma, si viene) sin aplicarla.
+async fn policy_validate_handler(AxumState(ctx): AxumState<AppCtx>, axum::Json(body): axum::Json<serde_json::Value>) -> Json<serde_json::Value> {
+    let signature = body.get("signature").and_then(|s| serde_json::from_value::<agent_core::policy_sig::PolicySignature>(s.clone()).ok());
+    let pol_v = body.get("policy").cloned().unwrap_or(body);
+    let report = crate::policy::Policy::validate(&pol_v);
+    // las mismas claves y modo con los que el gate admitiría la policy
+    let (sig_status, sig_verdict) = ctx.policy_gate.trust().check(&pol_v, signature.as_ref());
+    Json(serde_json::json!({
+        "ok": report.valid && sig_verdict.is_ok(),
+        "valid": report.valid,
+        "errors": report.errors,
+        "warnings": report.warnings,
+        "signature": sig_status,
+        "signature_error": sig_verdict.err(),
+    }))
+}
+
 async fn policy_refresh_handler(AxumState(ctx): AxumState<AppCtx>) -> Json<serde_json::Value> {
-    let p = ctx.paths.clone();
-    let rt = ctx.policy_rt.clone();
-    tokio::spawn(async move { crate::net::fetch_policy_once(&p, rt).await; });
+    tokio::spawn(crate::net::fetch_policy_once(ctx));
     Json(serde_json::json!({"ok": true}))
 }
 
+#[derive(Deserialize)]
+struct EnrollBody { code: String }
+
+async fn enroll_handler(AxumState(ctx): AxumState<AppCtx>, axum::Json(body): axum::Json<EnrollBody>) -> Json<serde_json::Value> {
+    match ctx.backend.enroll(&body.code).await {
+        Ok(identity) => {
+            // el enrolamiento puede traer claves de firma de policy
+            ctx.policy_gate.reload(&ctx.paths);
+            // con la identidad nueva ya se puede descargar la policy
+            tokio::spawn(crate::net::fetch_policy_once(ctx.clone()));
+            Json(serde_json::json!({"ok": true, "identity": identity}))
+        }
+        Err(e) => Json(serde_json::json!({"ok": false, "error": e.to_string()})),
+    }
+}
+
 #[derive(Deserialize)]
 struct FocusParams { limit: Option<usize>, min_minutes: Option<u32> }
 
@@ -780,7 +920,7 @@ async fn queue_handler(
     AxumState(ctx): AxumState<AppCtx>,
     Query(params): Query<QueueParams>,
 ) -> Json<QueueDto> {
-    let limit = params.limit.unwrap_or(10).min(100).max(1);
+    let limit = params.limit.unwrap_or(10).clamp(1, 100);
     let q = agent_core::queue::Queue::open(&ctx.paths, &ctx.state);
     let (len, items) = match q {
         Ok(q) => {
@@ -885,32 +1025,7 @@ async fn debug_frontmost_handler() -> Json<serde_json::Value> {
     Json(serde_json::json!({"unsupported": true}))
 }
 
-fn init_tracing(paths: &Paths) -> WorkerGuard {
-    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
-    let logs_dir = paths.logs_dir();
-    std::fs::create_dir_all(&logs_dir).ok();
-    let file_appender = tracing_appender::rolling::daily(&logs_dir, "agent.log");
-    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
-
-    let fmt_layer_file = tracing_subscriber::fmt::layer()
-        .with_ansi(false)
-        .with_target(false)
-        .with_writer(non_blocking);
-
-    let fmt_layer_stdout = tracing_subscriber::fmt::layer()
-        .with_target(false)
-        .compact();
-
-    use tracing_subscriber::prelude::*;
-    let subscriber = tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(filter))
-        .with(fmt_layer_stdout)
-        .with(fmt_layer_file);
-    subscriber.init();
-    guard
-}
-
-async fn shutdown_signal() {
+async fn shutdown_signal(requested: Arc<instance::Shutdown>) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -930,5 +1045,6 @@ async fn shutdown_signal() {
     tokio::select! {
         _ = ctrl_c => {},
         _ = terminate => {},
+        _ = requested.notify.notified() => {},
     }
 }
@@ -0,0 +1,113 @@
+// Series de métricas del agente: el registro en memoria se cierra cada minuto en
+// rollups (SQLite, últimas 24 h) y `/metrics/summary` resume una ventana con p50/p95;
+// `/metrics` expone los acumulados desde el arranque en formato OpenMetrics.
+use agent_core::metrics::{summarize, MetricsStore};
+use axum::extract::{Query, State as AxumState};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::Json;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio::time::{sleep, Duration};
+use tracing::warn;
+
+const MINUTE_MS: u64 = 60_000;
+const RETENTION_MS: u64 = 24 * 3_600_000;
+const GAUGE_SAMPLE_SECS: u64 = 10;
+
+/// Una sola conexión para todo el proceso; `None` si no se pudo abrir (sin rollups ni resúmenes).
+pub fn open_store(paths: &agent_core::paths::Paths) -> Option<Arc<Mutex<MetricsStore>>> {
+    match MetricsStore::open(paths) {
+        Ok(s) => Some(Arc::new(Mutex::new(s))),
+        Err(e) => {
+            warn!(error=%e, "no se pudo abrir el almacén de métricas; sin rollups");
+            None
+        }
+    }
+}
+
+pub async fn run_rollup_loop(ctx: crate::AppCtx) {
+    let Some(store) = ctx.metrics_store.clone() else { return };
+    let mut minute = minute_of(now_ms());
+    loop {
+        sample_gauges(&ctx);
+        sleep(Duration::from_secs(GAUGE_SAMPLE_SECS)).await;
+        let current = minute_of(now_ms());
+        if current == minute { continue; }
+        let rows = ctx.registry.take_minute(minute);
+        {
+            let mut store = store.lock().unwrap();
+            if let Err(e) = store.insert(&rows) {
+                warn!(error=%e, "no se pudieron guardar los rollups de métricas");
+            }
+            let _ = store.prune_before(current.saturating_sub(RETENTION_MS));
+        }
+        minute = current;
+    }
+}
+
+fn sample_gauges(ctx: &crate::AppCtx) {
+    let m = ctx.metrics.get();
+    ctx.registry.set("cpu_pct", &[], m.cpu_pct as f64);
+    ctx.registry.set("mem_mb", &[], m.mem_mb as f64);
+    if let Ok(q) = agent_core::queue::Queue::open(&ctx.paths, &ctx.state) {
+        ctx.registry.set("queue_size", &[], q.queue_len().unwrap_or(0) as f64);
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SummaryParams { minutes: Option<u64> }
+
+/// `GET /metrics/summary?minutes=60` (1..1440): rollups guardados más el minuto en curso.
+pub async fn summary_handler(AxumState(ctx): AxumState<crate::AppCtx>, Query(p): Query<SummaryParams>) -> Json<serde_json::Value> {
+    let minutes = p.minutes.unwrap_or(60).clamp(1, 1440);
+    let now = now_ms();
+    let since = minute_of(now).saturating_sub((minutes - 1) * MINUTE_MS);
+    let Some(store) = &ctx.metrics_store else {
+        return Json(serde_json::json!({ "ok": false, "error": "almacén de métricas no disponible" }));
+    };
+    let mut rows = match store.lock().unwrap().since(since) {
+        Ok(r) => r,
+        Err(e) => return Json(serde_json::json!({ "ok": false, "error": e.to_string() })),
+    };
+    rows.extend(ctx.registry.peek_minute(minute_of(now)));
+    Json(serde_json::json!({
+        "ok": true,
+        "window_minutes": minutes,
+        "since_ms": since,
+        "series": summarize(&rows, now.saturating_sub(since)),
+    }))
+}
+
+/// Gauges que `/metrics` calcula al momento del scrape (en el registro se muestrean cada 10 s).
+const SCRAPE_GAUGES: &[&str] = &["cpu_pct", "mem_mb", "queue_size"];
+
+/// `GET /metrics`: exposición OpenMetrics para scrapers locales (Prometheus, node-exporter).
+pub async fn openmetrics_handler(AxumState(ctx): AxumState<crate::AppCtx>) -> impl IntoResponse {
+    use std::fmt::Write;
+    let mut out = String::new();
+    ctx.registry.write_openmetrics(&mut out, "riporagent_", SCRAPE_GAUGES);
+    let queue_len = agent_core::queue::Queue::open(&ctx.paths, &ctx.state).and_then(|q| q.queue_len()).unwrap_or(0);
+    let m = ctx.metrics.get();
+    let last_hb = ctx.last_heartbeat_ts.load(Ordering::Relaxed);
+    let _ = writeln!(out, "# TYPE riporagent_queue_length gauge\nriporagent_queue_length {}", queue_len);
+    let _ = writeln!(out, "# TYPE riporagent_process_cpu_percent gauge\nriporagent_process_cpu_percent {}", m.cpu_pct);
+    let _ = writeln!(out, "# TYPE riporagent_process_resident_memory_bytes gauge\nriporagent_process_resident_memory_bytes {}", m.mem_mb * 1024 * 1024);
+    // sin heartbeat todavía: se omite la muestra (un 0 parecería recién enviado)
+    let _ = writeln!(out, "# TYPE riporagent_heartbeat_age_seconds gauge");
+    if last_hb > 0 {
+        let _ = writeln!(out, "riporagent_heartbeat_age_seconds {}", now_ms().saturating_sub(last_hb) as f64 / 1000.0);
+    }
+    let etag = ctx.policy_rt.get().etag.unwrap_or_default();
+    let _ = writeln!(out, "# TYPE riporagent_policy info\nriporagent_policy_info{{{}}} 1", agent_core::metrics::canonical_labels(&[("etag", &etag)]));
+    let _ = writeln!(out, "# TYPE riporagent_build info\nriporagent_build_info{{{}}} 1", agent_core::metrics::canonical_labels(&[("version", &ctx.version)]));
+    out.push_str("# EOF\n");
+    ([(header::CONTENT_TYPE, "application/openmetrics-text; version=1.0.0; charset=utf-8")], out)
+}
+
+fn minute_of(ms: u64) -> u64 { ms - ms % MINUTE_MS }
+
+fn now_ms() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
@@ -1,94 +1,95 @@
-use agent_core::auth::AgentSecrets;
-use agent_core::metrics::MetricsHandle;
-use agent_core::paths::Paths;
-use agent_core::state::AgentState;
-use reqwest::Client;
-use serde::Serialize;
-use std::sync::atomic::{AtomicU64, Ordering};
+use agent_core::backend::{primary_mac, BackendClient, BackendError, HeartbeatRequest, IngestEvent, IngestRequest, PolicyFetch};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use tokio::time::{sleep, Duration};
-use tracing::{info, warn, debug};
-use hmac::{Hmac, Mac};
-use sha2::Sha256;
-use crate::policy::{PolicyRuntime, PolicyState, load_policy, save_policy};
+use tracing::field::Empty;
+use tracing::{info, warn, debug, Instrument};
+use crate::otel::pipeline_span;
+use crate::policy::{PolicyRuntime, PolicyState, save_policy};
 
-#[derive(Serialize)]
-struct HeartbeatPayload<'a> {
-    device_id: &'a str,
-    agent_version: &'a str,
-    last_event_ts: u64,
-    queue_len: i64,
-    cpu_pct: f32,
-    mem_mb: u64,
+fn heartbeat_interval(rt: &PolicyRuntime) -> u64 {
+    agent_core::heartbeat::interval_secs(rt.get().policy.heartbeatIntervalSecs)
 }
 
-pub async fn run_heartbeat_loop(
-    state: Arc<AgentState>,
-    paths: &Paths,
-    metrics: MetricsHandle,
-    last_event_ts: Arc<AtomicU64>,
-    last_heartbeat_ts: Arc<AtomicU64>,
-) {
+pub async fn run_heartbeat_loop(ctx: crate::AppCtx) {
     info!("iniciando loop de heartbeat (Fase 1)");
-    let client = Client::builder().build().expect("client http");
-    let api_base = std::env::var("API_BASE_URL").ok();
+    let hb = ctx.heartbeat.clone();
+    hb.schedule_next(heartbeat_interval(&ctx.policy_rt), now_ms());
     loop {
-        sleep(Duration::from_secs(60)).await;
-        let last_evt = last_event_ts.load(Ordering::Relaxed);
-        if last_evt != 0 && now_ms().saturating_sub(last_evt) < 60_000 {
-            continue; // hubo eventos recientes; sin heartbeat
+        let due = hb.next_due_ms();
+        let now = now_ms();
+        if now < due {
+            // dormir en tramos cortos: el sender puede adelantar/reprogramar el heartbeat
+            sleep(Duration::from_millis((due - now).min(5_000))).await;
+            continue;
         }
-        let queue_len = match agent_core::queue::Queue::open(paths, &state) {
-            Ok(q) => q.queue_len().unwrap_or(0),
-            Err(_) => 0,
-        };
-        let _m = metrics.get();
-        if let Some(base) = api_base.as_deref() {
-            if let Some(secrets) = AgentSecrets::load(paths).ok().flatten() {
-                let body = serde_json::json!({
-                    "status": "running",
-                    "uptime_seconds": 0,
-                    "last_activity_ms": last_evt,
-                    "agent_version": state.agent_version,
-                });
-                let body_str = serde_json::to_string(&body).unwrap();
-                let sig = hmac_hex(&secrets.server_salt, body_str.as_bytes());
-                let url = format!("{}/v1/agents/heartbeat", base.trim_end_matches('/'));
-                if std::env::var("RIPOR_DEBUG_INGEST").ok().as_deref() == Some("1") {
-                    debug!(payload=%body_str, url=%url, "heartbeat payload");
+        if !hb.try_claim(heartbeat_interval(&ctx.policy_rt), now_ms()) { continue; }
+        let mut req = heartbeat_payload(&ctx);
+        if ctx.backend.is_configured() {
+            req.acks = ctx.commands.take_acks();
+            let res = ctx.backend.heartbeat(&req).await;
+            if !matches!(res, Err(BackendError::Config(_) | BackendError::EnrollmentRequired)) { record_heartbeat(&ctx, res.is_ok()); }
+            if res.is_err() { ctx.commands.requeue(std::mem::take(&mut req.acks)); }
+            match res {
+                Ok(cmds) => {
+                    ctx.last_heartbeat_ts.store(now_ms(), Ordering::Relaxed);
+                    crate::commands::dispatch(&ctx, cmds);
+                    continue;
                 }
-                let mut sent = false;
-                match client.post(url.clone())
-                    .header("Content-Type", "application/json")
-                    .header("Agent-Token", secrets.agent_token.clone())
-                    .header("X-Body-HMAC", sig.clone())
-                    .body(body_str.clone())
-                    .send().await {
-                    Ok(resp) if resp.status().is_success() => { last_heartbeat_ts.store(now_ms(), Ordering::Relaxed); sent = true; }
-                    Ok(resp) if resp.status().as_u16() == 401 => {
-                        if let Some(newsec) = rebootstrap(paths, &state).await {
-                            let sig2 = hmac_hex(&newsec.server_salt, body_str.as_bytes());
-                            match client.post(url)
-                                .header("Content-Type", "application/json")
-                                .header("Agent-Token", newsec.agent_token)
-                                .header("X-Body-HMAC", sig2)
-                                .body(body_str)
-                                .send().await {
-                                Ok(r2) if r2.status().is_success() => { last_heartbeat_ts.store(now_ms(), Ordering::Relaxed); sent = true; }
-                                Ok(r2) => warn!(status=?r2.status(), "heartbeat tras re-bootstrap falló"),
-                                Err(e2) => warn!(?e2, "heartbeat error red tras re-bootstrap"),
-                            }
-                        } else { warn!("re-bootstrap no disponible"); }
-                    }
-                    Ok(resp) if resp.status().as_u16() == 403 => { warn!(status=?resp.status(), "heartbeat forbidden (403)"); }
-                    Ok(resp) => warn!(status=?resp.status(), "heartbeat falló"),
-                    Err(e) => warn!(?e, "heartbeat error red"),
-                }
-                continue;
+                Err(e @ (BackendError::Config(_) | BackendError::EnrollmentRequired)) => debug!(reason=%e, "heartbeat remoto no disponible"),
+                Err(e) if e.is_pin_mismatch() => { warn!("heartbeat: pin TLS no coincide; conexión rechazada"); continue; }
+                Err(e) => { warn!(error=%e, "heartbeat falló"); continue; }
             }
         }
-        info!(queue_len, "heartbeat local (sin API_BASE_URL o sin bootstrap)");
-        last_heartbeat_ts.store(now_ms(), Ordering::Relaxed);
+        info!(queue_len = req.queue_len, "heartbeat local (sin API_BASE_URL o sin bootstrap)");
+        ctx.last_heartbeat_ts.store(now_ms(), Ordering::Relaxed);
+    }
+}
+
+/// Resultado de un heartbeat: ventana de `/state` y métricas (`heartbeats`, `heartbeat_ok`).
+fn record_heartbeat(ctx: &crate::AppCtx, ok: bool) {
+    ctx.heartbeat.record(ok, now_ms());
+    ctx.registry.inc("heartbeats", &[("result", if ok { "ok" } else { "error" })], 1);
+    ctx.registry.set("heartbeat_ok", &[], if ok { 1.0 } else { 0.0 });
+}
+
+/// Id con el que el backend conoce al dispositivo: el de las credenciales, que firman heartbeat e
+/// ingest (como en el loop de ingest), o el local si aún no hay. Tras re-enrolar puede diferir del
+/// de `agent_state.json`.
+pub fn backend_device_id(ctx: &crate::AppCtx) -> String {
+    ctx.backend.secrets().ok().flatten().and_then(|s| s.device_id).unwrap_or_else(|| ctx.state.device_id.clone())
+}
+
+/// Payload del heartbeat sin acuses de comandos (los agrega quien lo envía).
+pub fn heartbeat_payload(ctx: &crate::AppCtx) -> HeartbeatRequest {
+    let now = now_ms();
+    let (queue_len, oldest) = match agent_core::queue::Queue::open(&ctx.paths, &ctx.state) {
+        Ok(q) => (q.queue_len().unwrap_or(0), q.oldest_created_at().ok().flatten()),
+        Err(_) => (0, None),
+    };
+    let m = ctx.metrics.get();
+    let p95 = ctx.metrics.p95();
+    let last_ingest = ctx.last_ingest_ts.load(Ordering::Relaxed);
+    HeartbeatRequest {
+        status: "running".into(),
+        uptime_seconds: now.saturating_sub(ctx.started_at_ms) / 1000,
+        last_activity_ms: ctx.last_event_ts.load(Ordering::Relaxed),
+        agent_version: ctx.state.agent_version.clone(),
+        device_id: backend_device_id(ctx),
+        queue_len,
+        oldest_event_age_ms: oldest.map(|ts| now.saturating_sub(ts)),
+        cpu_pct: m.cpu_pct,
+        mem_mb: m.mem_mb,
+        cpu_p95_pct: p95.cpu_pct,
+        mem_p95_mb: p95.mem_mb,
+        dropped_total: ctx.dropped_events.load(Ordering::Relaxed),
+        dropped_by_reason: ctx.drop_counters.snapshot(),
+        policy_etag: ctx.policy_rt.get().etag,
+        capture: ctx.capture_health.report(),
+        permissions: crate::perms_value(),
+        last_ingest_ms: (last_ingest != 0).then_some(last_ingest),
+        policy_apply_latency_ms: ctx.policy_sync.last_latency_ms(),
+        acks: Vec::new(),
     }
 }
 
@@ -100,154 +101,133 @@ fn now_ms() -> u64 {
         .as_millis() as u64
 }
 
-pub async fn run_sender_loop(state: Arc<AgentState>, paths: &Paths) {
-    let client = Client::builder().build().expect("client http");
-    let api_base = match std::env::var("API_BASE_URL") { Ok(u) => u, Err(_) => { info!("API_BASE_URL no configurado; skip sender"); return; } };
+pub async fn run_sender_loop(ctx: crate::AppCtx) {
+    let (state, paths, backend) = (&ctx.state, &ctx.paths, &ctx.backend);
+    if !backend.is_configured() { info!("API_BASE_URL no configurado; skip sender"); return; }
     let mut backoff = 1u64;
     loop {
-        // pequeña pausa base
-        sleep(Duration::from_secs(5)).await;
-        let q = match agent_core::queue::Queue::open(paths, &state) {
+        // pequeña pausa base (o antes, si un comando `flush_queue` lo pide)
+        tokio::select! {
+            _ = sleep(Duration::from_secs(5)) => {}
+            _ = ctx.commands.flush.notified() => info!("flush de cola solicitado"),
+        }
+        let q = match agent_core::queue::Queue::open(paths, state) {
             Ok(q) => q,
             Err(_) => continue,
         };
-        let batch = match q.fetch_batch_decrypted(100) {
-            Ok(b) => b,
-            Err(_) => vec![],
-        };
+        let batch = q.fetch_batch_decrypted(100).unwrap_or_default();
         if batch.is_empty() {
             backoff = 1;
             continue;
         }
         // Require secrets for authenticated ingest
-        let secrets = match AgentSecrets::load(paths).ok().flatten() { Some(s) => s, None => { info!("sin bootstrap; skip ingest"); continue; } };
-        let mac = get_primary_mac().unwrap_or_default();
+        let secrets = match backend.ensure_bootstrapped().await {
+            Ok(s) => s,
+            Err(e) => { info!(error=%e, "sin bootstrap; skip ingest"); continue; }
+        };
+        let mac = primary_mac().unwrap_or_default();
         let os_name = std::env::consts::OS;
-        let mut events_json: Vec<serde_json::Value> = Vec::new();
+        let (org, user) = backend.identity().map(|i| (i.org_id, i.user_email)).unwrap_or_default();
+        let device_id = secrets.device_id.clone().unwrap_or_else(|| state.device_id.clone());
+        let mut events = Vec::new();
+        let mut traceparents = Vec::new();
         for (_id, plain) in &batch {
             if let Ok(evt) = serde_json::from_slice::<serde_json::Value>(plain) {
+                if let Some(tp) = evt.get("traceparent").and_then(|v| v.as_str()) { traceparents.push(tp.to_string()); }
                 let app = evt.get("app_name").and_then(|v| v.as_str()).unwrap_or_default();
                 let title = evt.get("window_title").and_then(|v| v.as_str()).unwrap_or_default();
-                let org = std::env::var("ORG_ID").ok().unwrap_or_default();
-                let user = std::env::var("USER_EMAIL").ok().unwrap_or_default();
-                if evt.get("type").and_then(|v| v.as_str()) == Some("focus_block") {
+                let base = IngestEvent {
+                    org_id: org.clone(),
+                    user_email: user.clone(),
+                    device_id: device_id.clone(),
+                    mac_address: mac.clone(),
+                    os: os_name.to_string(),
+                    app_name: app.to_string(),
+                    window_title: title.to_string(),
+                    state: "active".into(),
+                    timestamp_ms: 0,
+                    dur_ms: 0,
+                    category: String::new(),
+                    focus: true,
+                    focus_start_ms: 0,
+                    focus_end_ms: 0,
+                    input_idle_ms: 0,
+                    media_hint: String::new(),
+                    agent_version: state.agent_version.clone(),
+                    dropped_reason: None,
+                };
+                let kind = evt.get("type").and_then(|v| v.as_str());
+                if kind == Some("excluded") {
+                    let (s, e) = (evt.get("start_ms").and_then(|v| v.as_u64()).unwrap_or(0), evt.get("end_ms").and_then(|v| v.as_u64()).unwrap_or(0));
+                    let reason = evt.get("dropped_reason").and_then(|v| v.as_str()).unwrap_or("excluded").to_string();
+                    events.push(IngestEvent { state: "excluded".into(), timestamp_ms: e, dur_ms: e.saturating_sub(s), focus: false, focus_start_ms: s, focus_end_ms: e, dropped_reason: Some(reason), ..base });
+                } else if kind == Some("focus_block") {
                     let fs = evt.get("focus_start_ms").and_then(|v| v.as_u64()).unwrap_or(0);
                     let fe = evt.get("focus_end_ms").and_then(|v| v.as_u64()).unwrap_or(0);
                     let dur = evt.get("dur_ms").and_then(|v| v.as_u64()).unwrap_or(0);
-                    let ts = fe.max(fs);
-                    events_json.push(serde_json::json!({
-                        "org_id": org,
-                        "user_email": user,
-                        "device_id": secrets.device_id.as_deref().unwrap_or(&state.device_id),
-                        "mac_address": mac,
-                        "os": os_name,
-                        "app_name": app,
-                        "window_title": title,
-                        "state": "active",
-                        "timestamp_ms": ts,
-                        "dur_ms": dur,
-                        "category": "",
-                        "focus": true,
-                        "focus_start_ms": fs,
-                        "focus_end_ms": fe,
-                        "input_idle_ms": 0,
-                        "media_hint": "",
-                        "agent_version": state.agent_version,
-                    }));
+                    events.push(IngestEvent { timestamp_ms: fe.max(fs), dur_ms: dur, focus_start_ms: fs, focus_end_ms: fe, ..base });
                 } else {
                     let ts = evt.get("ts_ms").and_then(|v| v.as_u64()).unwrap_or(0);
                     let idle = evt.get("input_idle_ms").and_then(|v| v.as_u64()).unwrap_or(0);
                     let state_s = if idle < idle_threshold_ms() { "active" } else { "idle" };
-                    events_json.push(serde_json::json!({
-                        "org_id": org,
-                        "user_email": user,
-                        "device_id": secrets.device_id.as_deref().unwrap_or(&state.device_id),
-                        "mac_address": mac,
-                        "os": os_name,
-                        "app_name": app,
-                        "window_title": title,
-                        "state": state_s,
-                        "timestamp_ms": ts,
-                        "dur_ms": 0,
-                        "category": "",
-                        "focus": true,
-                        "focus_start_ms": ts,
-                        "focus_end_ms": ts,
-                        "input_idle_ms": idle,
-                        "media_hint": "",
-                        "agent_version": state.agent_version,
-                    }));
+                    events.push(IngestEvent { state: state_s.into(), timestamp_ms: ts, focus_start_ms: ts, focus_end_ms: ts, input_idle_ms: idle, ..base });
                 }
             }
         }
-        let body = serde_json::json!({ "events": events_json });
-        let body_str = serde_json::to_string(&body).unwrap();
-        let sig = hmac_hex(&secrets.server_salt, body_str.as_bytes());
-        let url = format!("{}/v1/events:ingest", api_base.trim_end_matches('/'));
-        if std::env::var("RIPOR_DEBUG_INGEST").ok().as_deref() == Some("1") {
-            debug!(payload=%body_str, url=%url, count=%events_json.len(), "ingest payload");
+        // heartbeat vencido: viaja con este envío en lugar de ir por separado
+        let heartbeat = ctx.heartbeat.try_claim(heartbeat_interval(&ctx.policy_rt), now_ms()).then(|| HeartbeatRequest { acks: ctx.commands.take_acks(), ..heartbeat_payload(&ctx) });
+        let with_hb = heartbeat.is_some();
+        let mut req = IngestRequest { events, heartbeat };
+        // un span por envío, enlazado a la captura de cada evento; `event.ids` son los ids de la cola
+        let ids: Vec<i64> = batch.iter().map(|(id, _)| *id).collect();
+        let send = pipeline_span!("ingest.batch", events = req.events.len(), event.ids = Empty, outcome = Empty);
+        if !send.is_disabled() {
+            send.record("event.ids", ids.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(",").as_str());
+            crate::otel::link_events(&send, traceparents.iter().map(String::as_str));
         }
-        let mut ok_sent = false;
-        match client.post(url.clone())
-            .header("Content-Type", "application/json")
-            .header("Agent-Token", secrets.agent_token.clone())
-            .header("X-Body-HMAC", sig.clone())
-            .body(body_str.clone())
-            .send().await {
-            Ok(resp) if resp.status().is_success() => {
-                let ids: Vec<i64> = batch.iter().map(|(id, _)| *id).collect();
+        let started = std::time::Instant::now();
+        let res = backend.ingest(&req).instrument(send.clone()).await;
+        send.record("outcome", if res.is_ok() { "acked" } else { "error" });
+        drop(send);
+        match res {
+            Ok(cmds) => {
+                ctx.registry.observe("flush_latency_ms", &[], started.elapsed().as_secs_f64() * 1000.0);
+                ctx.registry.inc("ingest_requests", &[("result", "ok")], 1);
+                ctx.registry.inc("events_sent", &[], req.events.len() as u64);
                 if let Ok(count) = q.delete_ids(&ids) {
                     info!(count, "eventos enviados y eliminados de la cola");
                 }
                 backoff = 1;
-                ok_sent = true;
-            }
-            Ok(resp) if resp.status().as_u16() == 401 => {
-                if let Some(newsec) = rebootstrap(paths, &state).await {
-                    let sig2 = hmac_hex(&newsec.server_salt, body_str.as_bytes());
-                    match client.post(url)
-                        .header("Content-Type", "application/json")
-                        .header("Agent-Token", newsec.agent_token)
-                        .header("X-Body-HMAC", sig2)
-                        .body(body_str)
-                        .send().await {
-                        Ok(r2) if r2.status().is_success() => {
-                            let ids: Vec<i64> = batch.iter().map(|(id, _)| *id).collect();
-                            if let Ok(count) = q.delete_ids(&ids) { info!(count, "eventos enviados tras re-bootstrap y eliminados"); }
-                            backoff = 1; ok_sent = true;
-                        }
-                        Ok(r2) => warn!(status=?r2.status(), "ingest tras re-bootstrap falló"),
-                        Err(e2) => warn!(?e2, "ingest error red tras re-bootstrap"),
-                    }
-                } else { warn!("re-bootstrap no disponible"); }
-            }
-            Ok(resp) if resp.status().as_u16() == 403 => {
-                warn!(status=?resp.status(), "ingest forbidden (403)");
-                sleep(Duration::from_secs(backoff)).await; backoff = (backoff*2).min(60);
-            }
-            Ok(resp) => {
-                warn!(status=?resp.status(), "envío de eventos falló");
-                sleep(Duration::from_secs(backoff)).await;
-                backoff = (backoff * 2).min(60);
+                ctx.last_ingest_ts.store(now_ms(), Ordering::Relaxed);
+                if with_hb {
+                    record_heartbeat(&ctx, true);
+                    ctx.last_heartbeat_ts.store(now_ms(), Ordering::Relaxed);
+                }
+                crate::commands::dispatch(&ctx, cmds);
             }
             Err(e) => {
-                warn!(?e, "error de red al enviar eventos");
-                sleep(Duration::from_secs(backoff)).await;
+                ctx.registry.inc("ingest_requests", &[("result", "error")], 1);
+                if let Some(hb) = req.heartbeat.as_mut() {
+                    ctx.heartbeat.release(now_ms());
+                    ctx.commands.requeue(std::mem::take(&mut hb.acks));
+                }
+                match &e {
+                    BackendError::Forbidden => warn!("ingest forbidden (403)"),
+                    e if e.is_pin_mismatch() => warn!("ingest: pin TLS no coincide; conexión rechazada"),
+                    BackendError::Network(err) => warn!(?err, "error de red al enviar eventos"),
+                    e => warn!(error=%e, "envío de eventos falló"),
+                }
+                let wait = match e {
+                    BackendError::RateLimited { retry_after: Some(d) } => d.as_secs().max(backoff),
+                    _ => backoff,
+                };
+                sleep(Duration::from_secs(wait)).await;
                 backoff = (backoff * 2).min(60);
             }
         }
-        if ok_sent { continue; }
     }
 }
 
-type HmacSha256 = Hmac<Sha256>;
-fn hmac_hex(key: &str, body: &[u8]) -> String {
-    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("hmac key");
-    mac.update(body);
-    let res = mac.finalize().into_bytes();
-    hex::encode(res)
-}
-
 fn idle_threshold_ms() -> u64 {
     std::env::var("IDLE_ACTIVE_THRESHOLD_MS")
         .ok()
@@ -255,156 +235,184 @@ fn idle_threshold_ms() -> u64 {
         .unwrap_or(60_000)
 }
 
-fn get_primary_mac() -> Option<String> {
-    match mac_address::get_mac_address() {
-        Ok(Some(ma)) => Some(format!("{}", ma)),
-        _ => None,
+pub async fn bootstrap_if_needed(backend: &BackendClient) {
+    if !backend.is_configured() { info!("API_BASE_URL no configurado; skip bootstrap"); return; }
+    match backend.ensure_bootstrapped().await {
+        Ok(_) => {}
+        Err(BackendError::Config(reason)) => info!(%reason, "skip bootstrap"),
+        Err(BackendError::EnrollmentRequired) => info!("agente pendiente de enrolamiento; usa `agent enroll --code ...` o el panel"),
+        Err(e) if e.is_pin_mismatch() => warn!("bootstrap: pin TLS no coincide; conexión rechazada"),
+        Err(e) => warn!(error=%e, "bootstrap falló"),
     }
 }
 
-pub async fn bootstrap_if_needed(paths: &Paths, state: &AgentState) {
-    if AgentSecrets::load(paths).ok().flatten().is_some() { return; }
-    let base = match std::env::var("API_BASE_URL") { Ok(u) => u, Err(_) => { info!("API_BASE_URL no configurado; skip bootstrap"); return; } };
-    let org = match std::env::var("ORG_ID") { Ok(v) if !v.is_empty() => v, _ => { info!("ORG_ID no configurado; skip bootstrap"); return; } };
-    let user = match std::env::var("USER_EMAIL") { Ok(v) if !v.is_empty() => v, _ => { info!("USER_EMAIL no configurado; skip bootstrap"); return; } };
-    let mac = get_primary_mac().unwrap_or_default();
-    let body = serde_json::json!({
-        "org_id": org,
-        "user_email": user,
-        "mac_address": mac,
-        "agent_version": state.agent_version,
-    });
-    let url = format!("{}/v1/agents/bootstrap", base.trim_end_matches('/'));
-    let client = Client::builder().build().expect("client http");
-    match client.post(url).json(&body).send().await {
-        Ok(resp) if resp.status().is_success() => {
-            match resp.json::<serde_json::Value>().await {
-                Ok(v) => {
-                    let token = v.get("agentToken").and_then(|x| x.as_str()).unwrap_or("").to_string();
-                    let salt = v.get("serverSalt").and_then(|x| x.as_str()).unwrap_or("").to_string();
-                    let dev = v.get("deviceId").and_then(|x| x.as_str()).map(|s| s.to_string());
-                    if !token.is_empty() && !salt.is_empty() {
-                        let secrets = AgentSecrets { agent_token: token, server_salt: salt, device_id: dev };
-                        if let Err(e) = secrets.save(paths) { warn!(?e, "no se pudo guardar secrets"); }
-                        else { info!("bootstrap ok: token guardado"); }
-                    } else {
-                        warn!(?v, "bootstrap respuesta incompleta");
-                    }
-                }
-                Err(e) => warn!(?e, "bootstrap parse fallo"),
-            }
+/// Renueva el token antes de su expiración (`TOKEN_REFRESH_MARGIN_SECS`, por defecto 300 s).
+/// Sin `expires_at` en los secrets solo revisa cada 10 min.
+pub async fn run_token_refresh_loop(backend: BackendClient) {
+    let margin = Duration::from_secs(
+        std::env::var("TOKEN_REFRESH_MARGIN_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(300),
+    );
+    loop {
+        let wait_ms = match backend.secrets().ok().flatten().and_then(|s| s.expires_at) {
+            Some(exp) => exp.saturating_sub(margin.as_millis() as u64).saturating_sub(now_ms()).clamp(30_000, 3_600_000),
+            None => 600_000,
+        };
+        sleep(Duration::from_millis(wait_ms)).await;
+        match backend.refresh_if_expiring(margin).await {
+            Ok(Some(s)) => info!(expires_at=?s.expires_at, "token renovado antes de expirar"),
+            Ok(None) => {}
+            Err(e @ (BackendError::Config(_) | BackendError::EnrollmentRequired)) => debug!(reason=%e, "refresh de token no disponible"),
+            Err(e) if e.is_pin_mismatch() => warn!("refresh: pin TLS no coincide; conexión rechazada"),
+            Err(e) => warn!(error=%e, "refresh de token falló"),
         }
-        Ok(resp) => warn!(status=?resp.status(), "bootstrap falló"),
-        Err(e) => warn!(?e, "bootstrap error red"),
     }
 }
 
-async fn rebootstrap(paths: &Paths, state: &AgentState) -> Option<AgentSecrets> {
-    let base = match std::env::var("API_BASE_URL") { Ok(u) => u, Err(_) => { info!("API_BASE_URL no configurado; no se puede re-bootstrap"); return None; } };
-    let org = match std::env::var("ORG_ID") { Ok(v) if !v.is_empty() => v, _ => { info!("ORG_ID no configurado; no se puede re-bootstrap"); return None; } };
-    let user = match std::env::var("USER_EMAIL") { Ok(v) if !v.is_empty() => v, _ => { info!("USER_EMAIL no configurado; no se puede re-bootstrap"); return None; } };
-    let mac = get_primary_mac().unwrap_or_default();
-    let body = serde_json::json!({
-        "org_id": org,
-        "user_email": user,
-        "mac_address": mac,
-        "agent_version": state.agent_version,
-    });
-    let url = format!("{}/v1/agents/bootstrap", base.trim_end_matches('/'));
-    let client = Client::builder().build().expect("client http");
-    match client.post(url).json(&body).send().await {
-        Ok(resp) if resp.status().is_success() => {
-            match resp.json::<serde_json::Value>().await {
-                Ok(v) => {
-                    let token = v.get("agentToken").and_then(|x| x.as_str()).unwrap_or("").to_string();
-                    let salt = v.get("serverSalt").and_then(|x| x.as_str()).unwrap_or("").to_string();
-                    let dev = v.get("deviceId").and_then(|x| x.as_str()).map(|s| s.to_string());
-                    if !token.is_empty() && !salt.is_empty() {
-                        let secrets = AgentSecrets { agent_token: token, server_salt: salt, device_id: dev };
-                        if let Err(e) = secrets.save(paths) { warn!(?e, "no se pudo guardar secrets"); None }
-                        else { info!("re-bootstrap ok: token actualizado"); Some(secrets) }
-                    } else { warn!(?v, "re-bootstrap respuesta incompleta"); None }
-                }
-                Err(e) => { warn!(?e, "re-bootstrap parse fallo"); None }
-            }
+/// Seguimiento de la sincronización de policy: modo (long-poll o intervalo) y latencia de
+/// aplicación (publicación en el backend → aplicada en el agente).
+#[derive(Default)]
+pub struct PolicySync {
+    inner: std::sync::Mutex<PolicySyncInner>,
+}
+
+#[derive(Default)]
+struct PolicySyncInner {
+    mode: &'static str,
+    last_check_ms: u64,
+    last_apply_ms: u64,
+    latencies: std::collections::VecDeque<u64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PolicySyncReport {
+    pub mode: &'static str,
+    pub last_check_ms: u64,
+    pub last_apply_ms: u64,
+    /// Latencia de la última aplicación (requiere `X-Policy-Published-At`/`publishedAt`)
+    pub apply_latency_ms: Option<u64>,
+    pub apply_latency_p95_ms: Option<u64>,
+    pub apply_latency_max_ms: Option<u64>,
+    pub samples: usize,
+}
+
+impl PolicySync {
+    pub fn new() -> Arc<Self> { Arc::new(Self::default()) }
+
+    fn set_mode(&self, mode: &'static str) { self.inner.lock().unwrap().mode = mode; }
+
+    fn checked(&self) { self.inner.lock().unwrap().last_check_ms = now_ms(); }
+
+    fn applied(&self, published_at: Option<u64>) {
+        let now = now_ms();
+        let mut g = self.inner.lock().unwrap();
+        g.last_apply_ms = now;
+        if let Some(p) = published_at {
+            g.latencies.push_back(now.saturating_sub(p));
+            while g.latencies.len() > POLICY_LATENCY_SAMPLES { g.latencies.pop_front(); }
+        }
+    }
+
+    pub fn last_latency_ms(&self) -> Option<u64> { self.inner.lock().unwrap().latencies.back().copied() }
+
+    pub fn report(&self) -> PolicySyncReport {
+        let g = self.inner.lock().unwrap();
+        let mut sorted: Vec<u64> = g.latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let p95 = (!sorted.is_empty()).then(|| sorted[((sorted.len() * 95).div_ceil(100)).saturating_sub(1)]);
+        PolicySyncReport {
+            mode: if g.mode.is_empty() { "disabled" } else { g.mode },
+            last_check_ms: g.last_check_ms,
+            last_apply_ms: g.last_apply_ms,
+            apply_latency_ms: g.latencies.back().copied(),
+            apply_latency_p95_ms: p95,
+            apply_latency_max_ms: sorted.last().copied(),
+            samples: sorted.len(),
         }
-        Ok(resp) => { warn!(status=?resp.status(), "re-bootstrap falló"); None }
-        Err(e) => { warn!(?e, "re-bootstrap error red"); None }
     }
 }
 
-pub async fn run_policy_loop(paths: &Paths, rt: Arc<PolicyRuntime>) {
-    // load initial from disk
-    let initial = load_policy(paths);
-    rt.set(initial.clone());
-    let client = Client::builder().build().expect("client http");
+const POLICY_LATENCY_SAMPLES: usize = 50;
+/// Tras detectar un backend sin long-poll, se vuelve a probar pasado este tiempo
+const LONGPOLL_REPROBE_MS: u64 = 3_600_000;
+
+/// Long-poll (`POLICY_LONGPOLL_SECS`, por defecto 60; 0 lo desactiva) con polling de respaldo
+/// cada `POLICY_POLL_SECS` (por defecto 300) si el backend no lo soporta o falla.
+pub async fn run_policy_loop(ctx: crate::AppCtx) {
+    let env_secs = |key: &str, default: u64| std::env::var(key).ok().and_then(|s| s.parse::<u64>().ok()).unwrap_or(default);
+    let poll = Duration::from_secs(env_secs("POLICY_POLL_SECS", 300).max(10));
+    let wait = Some(env_secs("POLICY_LONGPOLL_SECS", 60).min(300)).filter(|s| *s > 0).map(Duration::from_secs);
+    let mut longpoll_retry_at = 0u64;
+    let mut backoff = 5u64;
     loop {
-        let base = match std::env::var("API_BASE_URL") { Ok(u) => u, Err(_) => { sleep(Duration::from_secs(60)).await; continue; } };
-        let user = match std::env::var("USER_EMAIL") { Ok(v) if !v.is_empty() => v, _ => { sleep(Duration::from_secs(60)).await; continue; } };
-        // secrets required
-        let secrets = match AgentSecrets::load(paths).ok().flatten() { Some(s) => s, None => { sleep(Duration::from_secs(30)).await; continue; } };
-        let url = format!("{}/v1/policy/{}", base.trim_end_matches('/'), urlencoding::encode(&user));
-        let etag = rt.get().etag;
-        let mut req = client.get(url).header("Agent-Token", secrets.agent_token);
-        if let Some(tag) = etag.as_deref() { req = req.header("If-None-Match", tag); }
-        match req.send().await {
-            Ok(resp) if resp.status().as_u16() == 304 => { /* unchanged */ }
-            Ok(resp) if resp.status().is_success() => {
-                let hdr = resp.headers().get("etag").and_then(|v| v.to_str().ok()).map(|s| s.to_string());
-                match resp.json::<serde_json::Value>().await {
-                    Ok(v) => {
-                        // soporta respuesta con campo policy o directamente la policy rica
-                        let polv = v.get("policy").cloned().unwrap_or(v);
-                        match serde_json::from_value::<crate::policy::Policy>(polv) {
-                            Ok(policy) => {
-                                let st = PolicyState { policy, etag: hdr };
-                                if let Err(e) = save_policy(paths, &st) { warn!(?e, "no se pudo guardar policy"); }
-                                rt.set(st);
-                                info!("policy actualizada");
-                            }
-                            Err(e) => warn!(?e, "parse policy fallo"),
-                        }
-                    }
-                    Err(e) => warn!(?e, "parse json en policy fallo"),
-                }
+        let lp = wait.filter(|_| now_ms() >= longpoll_retry_at);
+        ctx.policy_sync.set_mode(if lp.is_some() { "long_poll" } else { "interval" });
+        let started = std::time::Instant::now();
+        let res = fetch_policy(&ctx, lp).await;
+        let quick = started.elapsed() < Duration::from_secs(1);
+        match res {
+            // un 304 inmediato a un `?wait=` indica que el backend ignora el parámetro
+            Ok(false) if lp.is_some() && quick => {
+                info!(poll_secs = poll.as_secs(), "backend sin long-poll de policy; se usa polling por intervalo");
+                longpoll_retry_at = now_ms() + LONGPOLL_REPROBE_MS;
+                ctx.policy_sync.set_mode("interval");
+            }
+            Ok(_) if lp.is_some() => {
+                backoff = 5;
+                if quick { sleep(Duration::from_secs(1)).await; }
+                continue;
+            }
+            Ok(_) => {}
+            Err(BackendError::Server { status: 400 | 404 | 405 | 501 }) if lp.is_some() => {
+                info!("long-poll de policy no soportado; se usa polling por intervalo");
+                longpoll_retry_at = now_ms() + LONGPOLL_REPROBE_MS;
+                ctx.policy_sync.set_mode("interval");
             }
-            Ok(resp) if resp.status().as_u16() == 401 => {
-                // try rebootstrap then retry once
-                if let Some(ns) = rebootstrap(paths, &AgentState { device_id: String::new(), agent_version: String::new(), created_at: 0, updated_at: 0 }).await {
-                    let url2 = format!("{}/v1/policy/{}", base.trim_end_matches('/'), urlencoding::encode(&user));
-                    let mut r2 = client.get(url2).header("Agent-Token", ns.agent_token);
-                    if let Some(tag) = etag.as_deref() { r2 = r2.header("If-None-Match", tag); }
-                    let _ = r2.send().await; // siguiente ciclo parseará
+            Err(e @ (BackendError::Config(_) | BackendError::EnrollmentRequired)) => debug!(reason=%e, "policy remota no disponible"),
+            Err(e) if e.is_pin_mismatch() => warn!("policy: pin TLS no coincide; conexión rechazada"),
+            Err(e) => {
+                warn!(error=%e, "policy fallo");
+                if lp.is_some() {
+                    sleep(Duration::from_secs(backoff).min(poll)).await;
+                    backoff = (backoff * 2).min(300);
+                    continue;
                 }
             }
-            Ok(resp) => warn!(status=?resp.status(), "policy fallo"),
-            Err(e) => warn!(?e, "policy error red"),
         }
-        sleep(Duration::from_secs(300)).await;
+        sleep(poll).await;
     }
 }
 
+pub async fn fetch_policy_once(ctx: crate::AppCtx) {
+    if let Err(e) = fetch_policy(&ctx, None).await { warn!(error=%e, "refresh de policy falló"); }
+}
 
-pub async fn fetch_policy_once(paths: &Paths, rt: std::sync::Arc<PolicyRuntime>) {
-    let client = Client::builder().build().expect("client http");
-    let base = match std::env::var("API_BASE_URL") { Ok(u) => u, Err(_) => return };
-    let user = match std::env::var("USER_EMAIL") { Ok(v) if !v.is_empty() => v, _ => return };
-    let secrets = match AgentSecrets::load(paths).ok().flatten() { Some(s) => s, None => return };
-    let url = format!("{}/v1/policy/{}", base.trim_end_matches('/'), urlencoding::encode(&user));
+/// Consulta la policy (con long-poll si `wait`) y la aplica si cambió. Devuelve `true` si hubo policy nueva.
+pub async fn fetch_policy(ctx: &crate::AppCtx, wait: Option<Duration>) -> Result<bool, BackendError> {
+    let (paths, rt, backend) = (&ctx.paths, &ctx.policy_rt, &ctx.backend);
     let etag = rt.get().etag;
-    let mut req = client.get(url).header("Agent-Token", secrets.agent_token);
-    if let Some(tag) = etag.as_deref() { req = req.header("If-None-Match", tag); }
-    if let Ok(resp) = req.send().await {
-        if resp.status().is_success() {
-            let hdr = resp.headers().get("etag").and_then(|v| v.to_str().ok()).map(|s| s.to_string());
-            if let Ok(v) = resp.json::<serde_json::Value>().await {
-                let polv = v.get("policy").cloned().unwrap_or(v);
-                if let Ok(policy) = serde_json::from_value::<crate::policy::Policy>(polv) {
-                    let st = PolicyState { policy, etag: hdr };
-                    let _ = save_policy(paths, &st);
-                    rt.set(st);
-                }
-            }
+    let span = pipeline_span!("policy.fetch", long_poll = wait.is_some(), outcome = Empty);
+    let fetched = backend.fetch_policy_wait(etag.as_deref(), wait).instrument(span.clone()).await;
+    span.record("outcome", match &fetched {
+        Ok(PolicyFetch::NotModified) => "not_modified",
+        Ok(PolicyFetch::Updated { .. }) => "updated",
+        Err(_) => "error",
+    });
+    ctx.policy_sync.checked();
+    match fetched? {
+        PolicyFetch::NotModified => {
+            // reintenta pins que no se pudieron comprobar (no hace nada si ya están aplicados)
+            backend.apply_tls_pins(&rt.get().policy.tlsPins).await;
+            Ok(false)
+        }
+        PolicyFetch::Updated { policy: raw, etag, published_at, signature } => {
+            // firma inválida o policy mal formada: se conserva la actual y el loop aplica backoff
+            let policy = ctx.policy_gate.admit(&raw, signature.as_ref(), "backend").map_err(|r| BackendError::Decode(r.reason))?.policy;
+            if let Err(e) = save_policy(paths, &raw, etag.clone(), signature) { warn!(?e, "no se pudo guardar policy"); }
+            backend.apply_tls_pins(&policy.tlsPins).await;
+            rt.set(PolicyState::new(policy, etag));
+            ctx.policy_sync.applied(published_at);
+            info!(latency_ms = ?ctx.policy_sync.last_latency_ms(), "policy actualizada");
+            Ok(true)
         }
     }
 }
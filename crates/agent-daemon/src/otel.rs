@@ -0,0 +1,99 @@
+// Trazas OTLP del pipeline captura → cola → ingest → ack (feature `otel`, activo solo si
+// `OTEL_EXPORTER_OTLP_ENDPOINT` está definido). Los spans usan el target `riporagent::pipeline`
+// en nivel TRACE: `RUST_LOG` no los habilita y sin exportador ningún layer los pide, así que
+// crearlos se reduce a una consulta de interés cacheada por callsite.
+use tracing::Span;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Target de los spans del pipeline (ver `pipeline_span!`).
+pub const TARGET: &str = "riporagent::pipeline";
+
+/// Span del pipeline: `pipeline_span!("capture.tick", outcome = Empty)` (acepta `parent:`).
+macro_rules! pipeline_span {
+    ($($args:tt)*) => {
+        tracing::trace_span!(target: $crate::otel::TARGET, $($args)*)
+    };
+}
+pub(crate) use pipeline_span;
+
+pub type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+/// Mantiene vivo el exportador; al soltarlo envía los spans pendientes.
+pub struct Guard(#[cfg(feature = "otel")] Option<opentelemetry_sdk::trace::SdkTracerProvider>);
+
+#[cfg(feature = "otel")]
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(p) = self.0.take() { let _ = p.shutdown(); }
+    }
+}
+
+fn endpoint() -> Option<String> {
+    std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().filter(|s| !s.trim().is_empty())
+}
+
+/// Layer de OpenTelemetry (solo spans del pipeline) si hay endpoint configurado.
+#[cfg(feature = "otel")]
+pub fn init<S>(version: &str) -> (Option<BoxedLayer<S>>, Guard)
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    use opentelemetry::trace::TracerProvider as _;
+    use tracing_subscriber::filter::Targets;
+    let Some(endpoint) = endpoint() else { return (None, Guard(None)) };
+    // el exportador lee OTEL_EXPORTER_OTLP_ENDPOINT/_HEADERS/_TIMEOUT y agrega `/v1/traces`
+    let exporter = match opentelemetry_otlp::SpanExporter::builder().with_http().build() {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("[otel] no se pudo crear el exportador OTLP ({}): {}", endpoint, e);
+            return (None, Guard(None));
+        }
+    };
+    let mut resource = opentelemetry_sdk::Resource::builder().with_attribute(opentelemetry::KeyValue::new("service.version", version.to_string()));
+    if std::env::var("OTEL_SERVICE_NAME").is_err() { resource = resource.with_service_name("riporagent-agent"); }
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder().with_batch_exporter(exporter).with_resource(resource.build()).build();
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("riporagent"))
+        .with_filter(Targets::new().with_target(TARGET, tracing::Level::TRACE));
+    (Some(Box::new(layer)), Guard(Some(provider)))
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init<S>(_version: &str) -> (Option<BoxedLayer<S>>, Guard)
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    if endpoint().is_some() { eprintln!("[otel] OTEL_EXPORTER_OTLP_ENDPOINT definido pero el binario se compiló sin la feature `otel`"); }
+    (None, Guard())
+}
+
+/// Contexto W3C (`traceparent`) del span actual, para guardarlo con el evento encolado.
+#[cfg(feature = "otel")]
+pub fn traceparent() -> Option<String> {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    let cx = Span::current().context();
+    let sc = cx.span().span_context().clone();
+    sc.is_valid().then(|| format!("00-{}-{}-{:02x}", sc.trace_id(), sc.span_id(), sc.trace_flags().to_u8()))
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn traceparent() -> Option<String> { None }
+
+/// Enlaza `span` con los spans de captura de cada evento del batch.
+#[cfg(feature = "otel")]
+pub fn link_events<'a>(span: &Span, traceparents: impl Iterator<Item = &'a str>) {
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    if span.is_disabled() { return; }
+    for tp in traceparents {
+        let parts: Vec<&str> = tp.split('-').collect();
+        let [_, trace, id, flags] = parts[..] else { continue };
+        let (Ok(trace), Ok(id), Ok(flags)) = (TraceId::from_hex(trace), SpanId::from_hex(id), u8::from_str_radix(flags, 16)) else { continue };
+        span.add_link(SpanContext::new(trace, id, TraceFlags::new(flags), true, TraceState::default()));
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn link_events<'a>(_span: &Span, _traceparents: impl Iterator<Item = &'a str>) {}
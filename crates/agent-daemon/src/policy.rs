@@ -1,43 +1,39 @@
+use agent_core::policy_sig::{PolicySignature, SigStatus, SignatureMode, TrustStore};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, RwLock};
 use std::collections::VecDeque;
 use std::sync::Mutex;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Policy {
-    #[serde(default)]
-    pub killSwitch: bool,
-    #[serde(default)]
-    pub pauseCapture: bool,
-    #[serde(default = "default_true")]
-    pub titleCapture: bool,
-    #[serde(default)]
-    pub excludeApps: Vec<String>,
-    #[serde(default)]
-    pub excludePatterns: Vec<String>,
-    #[serde(default)]
-    pub excludeExePaths: Vec<String>,
-    #[serde(default)]
-    pub updateChannel: Option<String>,
-    #[serde(default)]
-    pub titleSampleHz: Option<u32>,
-    #[serde(default)]
-    pub titleBurstPerMinute: Option<u32>,
-    #[serde(default)]
-    pub focusMinMinutes: Option<u32>,
-}
-
-fn default_true() -> bool { true }
+pub use agent_core::policy::{CompiledPolicy, Policy};
+pub use agent_core::policy_sig::{Admitted, Rejected};
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct PolicyMeta { pub etag: Option<String> }
+pub struct PolicyMeta {
+    pub etag: Option<String>,
+    /// Firma de `policy.json` (se re-verifica al arrancar)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<PolicySignature>,
+}
 
 #[derive(Debug, Default)]
 pub struct PolicyRuntime { inner: RwLock<PolicyState> }
 
+/// Policy vigente, su ETag y sus matchers ya compilados (compartidos entre clones).
+/// `locked`: sin policy verificada; se captura como con `killSwitch` hasta admitir una.
 #[derive(Debug, Default, Clone)]
-pub struct PolicyState { pub policy: Policy, pub etag: Option<String> }
+pub struct PolicyState { pub policy: Policy, pub etag: Option<String>, pub compiled: Arc<CompiledPolicy>, pub locked: Option<String> }
+
+impl PolicyState {
+    pub fn new(policy: Policy, etag: Option<String>) -> Self {
+        let compiled = Arc::new(CompiledPolicy::compile(&policy));
+        Self { policy, etag, compiled, locked: None }
+    }
+
+    fn locked(reason: String) -> Self {
+        Self { locked: Some(reason), ..Self::new(Policy { killSwitch: true, ..Default::default() }, None) }
+    }
+}
 
 impl PolicyRuntime {
     pub fn new() -> Arc<Self> { Arc::new(Self { inner: RwLock::new(PolicyState::default()) }) }
@@ -45,35 +41,166 @@ impl PolicyRuntime {
     pub fn set(&self, st: PolicyState) { *self.inner.write().unwrap() = st; }
 }
 
-pub fn load_policy(paths: &agent_core::paths::Paths) -> PolicyState {
-    let mut st = PolicyState::default();
-    let pf = paths.policy_file();
-    if pf.exists() {
-        if let Ok(txt) = std::fs::read_to_string(&pf) { if let Ok(p) = serde_json::from_str::<Policy>(&txt) { st.policy = p; } }
+/// Verificación de firmas antes de aplicar cualquier policy (backend, panel/CLI o disco).
+pub struct PolicyGate {
+    trust: RwLock<TrustStore>,
+    report: Mutex<SignatureReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SignatureReport {
+    pub mode: SignatureMode,
+    pub trusted_keys: Vec<String>,
+    /// Estado de la firma de la policy aplicada
+    pub current: Option<SigStatus>,
+    pub last_rejected: Option<PolicyRejection>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyRejection {
+    pub ts_ms: u64,
+    pub source: &'static str,
+    pub reason: String,
+}
+
+impl PolicyGate {
+    pub fn new(paths: &agent_core::paths::Paths) -> Arc<Self> {
+        let trust = TrustStore::load(paths);
+        let report = SignatureReport { mode: trust.mode, trusted_keys: trust.key_ids(), current: None, last_rejected: None };
+        Arc::new(Self { trust: RwLock::new(trust), report: Mutex::new(report) })
     }
-    let mf = paths.policy_meta_file();
-    if mf.exists() {
-        if let Ok(txt) = std::fs::read_to_string(&mf) { if let Ok(m) = serde_json::from_str::<PolicyMeta>(&txt) { st.etag = m.etag; } }
+
+    /// Relee las claves (p.ej. tras un enrolamiento que las provisiona).
+    pub fn reload(&self, paths: &agent_core::paths::Paths) {
+        let trust = TrustStore::load(paths);
+        let mut r = self.report.lock().unwrap();
+        r.mode = trust.mode;
+        r.trusted_keys = trust.key_ids();
+        *self.trust.write().unwrap() = trust;
     }
-    st
+
+    /// [`TrustStore::admit`] con registro en el reporte de `/state`.
+    pub fn admit(&self, raw: &serde_json::Value, sig: Option<&PolicySignature>, source: &'static str) -> std::result::Result<Admitted, Rejected> {
+        let (status, res) = self.trust.read().unwrap().admit(raw, sig);
+        let mut r = self.report.lock().unwrap();
+        match &res {
+            Ok(a) => {
+                if !status.is_valid() && r.mode == SignatureMode::Warn {
+                    tracing::warn!(source, ?status, "policy sin firma válida aplicada (binario compilado en modo warn)");
+                }
+                for w in &a.warnings { tracing::info!(source, field=%w.field, message=%w.message, "aviso de policy"); }
+                r.current = Some(status);
+            }
+            Err(rej) => {
+                tracing::warn!(source, reason=%rej.reason, "policy rechazada");
+                r.last_rejected = Some(PolicyRejection { ts_ms: now_ms(), source, reason: rej.reason.clone() });
+            }
+        }
+        res
+    }
+
+    pub fn report(&self) -> SignatureReport { self.report.lock().unwrap().clone() }
+
+    /// Claves de confianza vigentes (también verifican los comandos remotos).
+    pub fn trust(&self) -> std::sync::RwLockReadGuard<'_, TrustStore> { self.trust.read().unwrap() }
+
+    pub fn mode(&self) -> SignatureMode { self.report.lock().unwrap().mode }
 }
 
-pub fn save_policy(paths: &agent_core::paths::Paths, st: &PolicyState) -> Result<()> {
+/// Carga `policy.json` verificando la firma guardada. Si no hay policy que pase y el binario exige
+/// firma, se arranca bloqueado (sin capturar) hasta que llegue una verificada; si no, la de por defecto.
+pub fn load_policy(paths: &agent_core::paths::Paths, gate: &PolicyGate) -> PolicyState {
+    let meta = std::fs::read_to_string(paths.policy_meta_file())
+        .ok()
+        .and_then(|txt| serde_json::from_str::<PolicyMeta>(&txt).ok())
+        .unwrap_or_default();
+    let pf = paths.policy_file();
+    let reason = if pf.exists() {
+        match std::fs::read_to_string(&pf).map_err(|e| e.to_string()).and_then(|txt| serde_json::from_str::<serde_json::Value>(&txt).map_err(|e| e.to_string())) {
+            Ok(raw) => match gate.admit(&raw, meta.signature.as_ref(), "disk") {
+                Ok(a) => return PolicyState::new(a.policy, meta.etag),
+                Err(rej) => rej.reason,
+            },
+            Err(e) => format!("policy.json ilegible: {}", e),
+        }
+    } else {
+        "sin policy en disco".to_string()
+    };
+    // sin etag: el próximo fetch descarga la policy completa otra vez
+    if gate.mode() == SignatureMode::Enforce {
+        tracing::warn!(%reason, "sin policy verificada: captura bloqueada hasta recibir una policy firmada");
+        return PolicyState::locked(reason);
+    }
+    PolicyState { etag: if pf.exists() { None } else { meta.etag }, ..Default::default() }
+}
+
+/// Guarda la policy tal como llegó (`raw`), para que su firma siga siendo verificable.
+pub fn save_policy(paths: &agent_core::paths::Paths, raw: &serde_json::Value, etag: Option<String>, signature: Option<PolicySignature>) -> Result<()> {
     let pf = paths.policy_file();
     if let Some(dir) = pf.parent() { std::fs::create_dir_all(dir).ok(); }
-    std::fs::write(&pf, serde_json::to_vec_pretty(&st.policy)?)?;
+    std::fs::write(&pf, serde_json::to_vec_pretty(raw)?)?;
     let mf = paths.policy_meta_file();
-    std::fs::write(&mf, serde_json::to_vec_pretty(&PolicyMeta { etag: st.etag.clone() })?)?;
+    std::fs::write(&mf, serde_json::to_vec_pretty(&PolicyMeta { etag, signature })?)?;
     Ok(())
 }
 
+fn now_ms() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Descartes por motivo. Se persisten en `drops.json` para sobrevivir reinicios.
 #[derive(Debug, Default)]
 pub struct DropCounters {
     pub kill_switch: std::sync::atomic::AtomicU64,
     pub pause: std::sync::atomic::AtomicU64,
     pub excluded_app: std::sync::atomic::AtomicU64,
     pub excluded_pattern: std::sync::atomic::AtomicU64,
+    pub rule: std::sync::atomic::AtomicU64,
     pub throttled: std::sync::atomic::AtomicU64,
+    file: Option<std::path::PathBuf>,
+}
+
+impl DropCounters {
+    pub fn load(paths: &agent_core::paths::Paths) -> Arc<Self> {
+        use std::sync::atomic::Ordering;
+        let file = paths.drops_file();
+        let saved: std::collections::BTreeMap<String, u64> = std::fs::read_to_string(&file)
+            .ok()
+            .and_then(|txt| serde_json::from_str(&txt).ok())
+            .unwrap_or_default();
+        let c = Self { file: Some(file), ..Default::default() };
+        for (k, v) in c.counters() {
+            v.store(saved.get(k).copied().unwrap_or(0), Ordering::Relaxed);
+        }
+        Arc::new(c)
+    }
+
+    fn counters(&self) -> [(&'static str, &std::sync::atomic::AtomicU64); 6] {
+        [
+            ("killSwitch", &self.kill_switch),
+            ("pauseCapture", &self.pause),
+            ("excludedApp", &self.excluded_app),
+            ("excludedPattern", &self.excluded_pattern),
+            ("rule", &self.rule),
+            ("throttled", &self.throttled),
+        ]
+    }
+
+    pub fn snapshot(&self) -> std::collections::BTreeMap<String, u64> {
+        use std::sync::atomic::Ordering;
+        self.counters().into_iter().map(|(k, v)| (k.to_string(), v.load(Ordering::Relaxed))).collect()
+    }
+
+    /// Total histórico (cada descarte suma en exactamente un motivo).
+    pub fn total(&self) -> u64 { self.snapshot().values().sum() }
+
+    pub fn save(&self) {
+        let Some(f) = &self.file else { return };
+        if let Err(e) = std::fs::write(f, serde_json::to_vec(&self.snapshot()).unwrap_or_default()) {
+            tracing::warn!(error=%e, "no se pudieron guardar los contadores de descartes");
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
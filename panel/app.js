@@ -25,10 +25,21 @@ async function refreshAll(){
     $('queue_len').textContent = st.queue_len;
     $('last_event_ts').textContent = fmtTs(st.last_event_ts);
     $('last_heartbeat_ts').textContent = fmtTs(st.last_heartbeat_ts);
+    const hb = st.heartbeat || {};
+    $('hb_rate').textContent = hb.success_rate == null ? '—' : `${Math.round(hb.success_rate*100)}% (${hb.successes}/${hb.attempts}, cada ${hb.interval_secs}s)`;
     $('queue').textContent = json(st.queue_preview);
     $('policy_etag').textContent = st.policy_etag || '';
     $('dropped').textContent = String(st.dropped_events || 0);
     $('policy').textContent = json(st.policy || {});
+    const au = st.auth_state || {};
+    if(au.phase === 'pending_enrollment'){
+      $('enroll_status').innerHTML = '<b class="warn">Pendiente de enrolamiento</b>: ingresa el código entregado por tu administrador o ejecuta <code>agent enroll --code ...</code>';
+    }else if(au.identity){
+      $('enroll_status').textContent = `${au.identity.user_email} (${au.identity.org_id}, ${au.identity.source}) — ${au.phase}`;
+    }else{
+      $('enroll_status').textContent = au.phase || '—';
+    }
+    $('enroll_form').style.display = au.phase === 'pending_enrollment' ? '' : 'none';
     const dbr = document.getElementById('dropped_by_reason');
     if(dbr){ dbr.textContent = json(st.dropped_by_reason || {}); }
     const fb = document.getElementById('focus_blocks');
@@ -108,6 +119,12 @@ async function refreshAll(){
 
 document.addEventListener('DOMContentLoaded', ()=>{
   $('btn-refresh-perms').onclick = refreshAll;
+  $('btn-enroll').onclick = async ()=>{
+    const res = await fetch(BASE+'/enroll', { method:'POST', headers:{'Content-Type':'application/json'}, body: JSON.stringify({ code: $('enroll_code').value }) });
+    const r = await res.json();
+    if(!r.ok){ alert(r.error); }
+    refreshAll();
+  };
   $('btn-prompt-perms').onclick = ()=>fetchJson('/permissions/prompt').then(()=>setTimeout(refreshAll,1500));
   refreshAll();
   setInterval(refreshAll, 2000);
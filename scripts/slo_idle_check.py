@@ -10,6 +10,8 @@ Uso:
 
 Opciones:
   --use-running    No lanza el agente; usa uno ya ejecutándose.
+  --from-agent MIN No muestrea: usa las series del propio agente (`/metrics/summary`)
+                   de los últimos MIN minutos (implica --use-running).
   --json-out PATH  Guarda resultados en JSON.
 """
 import argparse
@@ -39,6 +41,31 @@ def fetch_state():
         return json.loads(r.read().decode("utf-8"))
 
 
+def fetch_summary(minutes):
+    url = f"{PANEL_URL}/metrics/summary?minutes={int(minutes)}"
+    with contextlib.closing(urllib.request.urlopen(url, timeout=5)) as r:
+        data = json.loads(r.read().decode("utf-8"))
+    return {s["name"]: s for s in data.get("series", []) if not s.get("labels")}
+
+
+def summary_result(args):
+    series = fetch_summary(args.from_agent)
+    cpu = series.get("cpu_pct")
+    mem = series.get("mem_mb")
+    if not cpu or not mem:
+        raise RuntimeError("el agente aún no tiene muestras de cpu_pct/mem_mb")
+    cpu_ok = cpu["p95"] <= args.cpu_threshold
+    mem_ok = mem["p95"] <= args.mem_threshold
+    return {
+        "source": "agent",
+        "window_minutes": args.from_agent,
+        "samples": cpu["count"],
+        "cpu": {"p95": cpu["p95"], "avg": cpu["avg"], "threshold": args.cpu_threshold, "ok": cpu_ok},
+        "mem": {"p95": mem["p95"], "avg": mem["avg"], "threshold": args.mem_threshold, "ok": mem_ok},
+        "pass": bool(cpu_ok and mem_ok),
+    }
+
+
 def wait_ready(timeout=10):
     start = time.time()
     while time.time() - start < timeout:
@@ -72,10 +99,20 @@ def main():
     ap.add_argument("--cpu-threshold", type=float, default=1.0, help="umbral CPU p95 (%)")
     ap.add_argument("--mem-threshold", type=float, default=60.0, help="umbral RAM p95 (MB)")
     ap.add_argument("--use-running", action="store_true", help="no lanzar agente, usar existente")
+    ap.add_argument("--from-agent", type=int, default=None, metavar="MIN", help="usar /metrics/summary de los últimos MIN minutos")
     ap.add_argument("--json-out", type=str, default=None)
     ap.add_argument("--debug-build", action="store_true", help="usar build debug en vez de release")
     args = ap.parse_args()
 
+    if args.from_agent:
+        result = summary_result(args)
+        print("Resultados SLO (idle, series del agente):")
+        print(json.dumps(result, indent=2))
+        if args.json_out:
+            with open(args.json_out, "w") as f:
+                json.dump(result, f, indent=2)
+        return 0 if result["pass"] else 2
+
     proc = None
     try:
         if not args.use_running: